@@ -0,0 +1,204 @@
+use crate::csv_format;
+use crate::models;
+use crate::selection::{ExportFile, Selection};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// First entry in an archive; lets the files be checked after moving or storing it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub tool_version: String,
+    pub exported_at: DateTime<Utc>,
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub size: u64,
+    /// Lowercase hex
+    pub sha256: String,
+}
+
+/// Bundle the selected export files in `dir` into a zstd-compressed tar at `path`.
+pub fn create(dir: &Path, selection: Selection, path: &Path) -> Result<Manifest> {
+    let selection = selection.exported_in_or_err(dir)?;
+    let mut files = Vec::new();
+    for file in ExportFile::ALL
+        .into_iter()
+        .filter(|&f| selection.contains(f))
+    {
+        let path = models::export_path(dir, file.file_name());
+        let data = std::fs::read(&path).with_context(|| format!("Reading {}", path.display()))?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        files.push((name.into_owned(), data));
+    }
+    let manifest = Manifest {
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        exported_at: Utc::now(),
+        files: files
+            .iter()
+            .map(|(name, data)| ManifestEntry {
+                name: name.clone(),
+                size: data.len() as u64,
+                sha256: sha256(data),
+            })
+            .collect(),
+    };
+    write(path, &manifest, &files)?;
+    Ok(manifest)
+}
+
+fn write(path: &Path, manifest: &Manifest, files: &[(String, Vec<u8>)]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let out = std::fs::File::create(&tmp).with_context(|| format!("Creating {}", tmp.display()))?;
+    let mut tar = tar::Builder::new(zstd::Encoder::new(out, 0)?.auto_finish());
+    let manifest = serde_json::to_vec_pretty(manifest)?;
+    let entries = std::iter::once((MANIFEST_FILE, manifest.as_slice())).chain(
+        files
+            .iter()
+            .map(|(name, data)| (name.as_str(), data.as_slice())),
+    );
+    for (name, data) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(Utc::now().timestamp() as u64);
+        tar.append_data(&mut header, name, data)?;
+    }
+    tar.into_inner()?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Writing {}", path.display()))
+}
+
+/// Check the archive at `path` against its manifest and unpack the export files into
+/// `dir`, so the commands that read export directories can work on it (and import can
+/// keep its journal next to them). A file already in `dir` with different content is
+/// an error rather than overwritten.
+pub fn unpack(path: &Path, dir: &Path) -> Result<Manifest> {
+    let file = std::fs::File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    let mut tar = tar::Archive::new(zstd::Decoder::new(file)?);
+    let mut entries = HashMap::new();
+    for entry in tar
+        .entries()
+        .with_context(|| format!("Reading {}", path.display()))?
+    {
+        let mut entry = entry.with_context(|| format!("Reading {}", path.display()))?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        entries.insert(name, data);
+    }
+
+    let manifest: Manifest = serde_json::from_slice(
+        &entries
+            .remove(MANIFEST_FILE)
+            .with_context(|| format!("{} has no {}", path.display(), MANIFEST_FILE))?,
+    )
+    .with_context(|| format!("Parsing {} in {}", MANIFEST_FILE, path.display()))?;
+    for entry in &manifest.files {
+        // Only ever write the files we know, whatever names the archive uses
+        anyhow::ensure!(
+            ExportFile::ALL.iter().any(|f| f.file_name() == entry.name
+                || csv_format::csv_name(f.file_name()) == entry.name),
+            "{} lists unknown file {}",
+            path.display(),
+            entry.name
+        );
+        let data = entries
+            .get(&entry.name)
+            .with_context(|| format!("{} is missing {}", path.display(), entry.name))?;
+        anyhow::ensure!(
+            data.len() as u64 == entry.size && sha256(data) == entry.sha256,
+            "{} in {} doesn't match its checksum; the archive is damaged",
+            entry.name,
+            path.display()
+        );
+    }
+    if let Some(extra) = entries
+        .keys()
+        .find(|name| !manifest.files.iter().any(|e| &e.name == *name))
+    {
+        anyhow::bail!(
+            "{} contains {}, which its manifest doesn't list",
+            path.display(),
+            extra
+        );
+    }
+
+    // Check for conflicts before writing anything, so a refusal leaves `dir` as it was
+    let mut unpack = Vec::new();
+    for entry in &manifest.files {
+        let data = &entries[&entry.name];
+        let target = dir.join(&entry.name);
+        match std::fs::read(&target) {
+            Ok(existing) if existing == *data => {}
+            Ok(_) => anyhow::bail!(
+                "{} already exists and differs from the one in {}; unpack into another --input-dir",
+                target.display(),
+                path.display()
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => unpack.push((target, data)),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", target.display())),
+        }
+    }
+    std::fs::create_dir_all(dir)?;
+    for (target, data) in unpack {
+        let tmp = target.with_extension("json.tmp");
+        std::fs::write(&tmp, data).with_context(|| format!("Writing {}", tmp.display()))?;
+        std::fs::rename(&tmp, &target).with_context(|| format!("Writing {}", target.display()))?;
+    }
+    Ok(manifest)
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_rejects_damage() {
+        let root = std::env::temp_dir().join(format!("archive-test-{}", std::process::id()));
+        let (src, dst) = (root.join("src"), root.join("dst"));
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(src.join("watchlist.json"), "{\"items\": []}").unwrap();
+        std::fs::write(src.join("ratings.json"), "{\"items\": [1]}").unwrap();
+        std::fs::write(src.join("journal.json"), "{}").unwrap();
+
+        let path = root.join("backup.tar.zst");
+        let created = create(&src, Selection::default(), &path).unwrap();
+        let names: Vec<_> = created.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["watchlist.json", "ratings.json"]);
+
+        let unpacked = unpack(&path, &dst).unwrap();
+        assert_eq!(unpacked.files[1].sha256, created.files[1].sha256);
+        assert_eq!(
+            std::fs::read(dst.join("ratings.json")).unwrap(),
+            std::fs::read(src.join("ratings.json")).unwrap()
+        );
+        assert!(!dst.join("journal.json").exists());
+        // Unpacking again over identical files is fine, over edited ones it isn't
+        unpack(&path, &dst).unwrap();
+        std::fs::write(dst.join("ratings.json"), "{\"items\": []}").unwrap();
+        let err = unpack(&path, &dst).unwrap_err().to_string();
+        assert!(err.contains("already exists and differs"), "{}", err);
+
+        let mut damaged = created;
+        damaged.files[0].sha256 = sha256(b"something else");
+        let files = vec![("watchlist.json".to_string(), b"{\"items\": []}".to_vec())];
+        write(&path, &damaged, &files).unwrap();
+        let err = unpack(&path, &root.join("other")).unwrap_err().to_string();
+        assert!(err.contains("doesn't match its checksum"), "{}", err);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,7 +1,8 @@
+use crate::client::ClientOptions;
 use anyhow::{Context, Result};
+use crunchyroll_rs::Crunchyroll;
 use crunchyroll_rs::crunchyroll::{DeviceIdentifier, SessionToken};
 use crunchyroll_rs::profile::Profile;
-use crunchyroll_rs::{Crunchyroll, Locale};
 use dialoguer::Select;
 
 pub struct InitialSession {
@@ -17,6 +18,7 @@ pub async fn initial_login(
     email: Option<String>,
     password: Option<String>,
     context: &str,
+    client: &ClientOptions,
 ) -> Result<InitialSession> {
     let email = email.unwrap_or_else(|| prompt_email(context));
     let password = password.unwrap_or_else(|| prompt_password(context));
@@ -28,9 +30,9 @@ pub async fn initial_login(
     } else {
         format!("[{}] ", context)
     };
-    println!("{}Logging in as {}...", prefix, email);
-    let crunchy = Crunchyroll::builder()
-        .locale(Locale::en_US)
+    eprintln!("{}Logging in as {}...", prefix, email);
+    let crunchy = client
+        .builder()?
         .login_with_credentials(&email, &password, device.clone())
         .await
         .context("Failed to login")?;
@@ -94,15 +96,16 @@ pub async fn switch_profile(
     refresh_token: &str,
     profile: &Profile,
     device: DeviceIdentifier,
+    client: &ClientOptions,
 ) -> Result<Crunchyroll> {
-    println!("Switching to profile '{}'...", profile.profile_name);
-    let crunchy = Crunchyroll::builder()
-        .locale(Locale::en_US)
+    eprintln!("Switching to profile '{}'...", profile.profile_name);
+    let crunchy = client
+        .builder()?
         .login_with_refresh_token_profile_id(refresh_token, &profile.profile_id, device)
         .await
         .context("Failed to switch profile")?;
 
-    println!("Authenticated as '{}'\n", profile.profile_name);
+    eprintln!("Authenticated as '{}'\n", profile.profile_name);
     Ok(crunchy)
 }
 
@@ -115,8 +118,9 @@ pub async fn login(
     profile_name: Option<String>,
     context: &str,
     allow_create: bool,
+    client: &ClientOptions,
 ) -> Result<Crunchyroll> {
-    let session = initial_login(email, password, context).await?;
+    let session = initial_login(email, password, context, client).await?;
     let can_create = allow_create && session.crunchy.premium().await;
 
     // When a specific name is given, try to find it or offer to create it
@@ -126,7 +130,7 @@ pub async fn login(
             .iter()
             .find(|p| p.profile_name.eq_ignore_ascii_case(name))
         {
-            return switch_profile(&session.refresh_token, profile, session.device).await;
+            return switch_profile(&session.refresh_token, profile, session.device, client).await;
         }
 
         if can_create {
@@ -137,7 +141,7 @@ pub async fn login(
                 .context("Profile creation cancelled")?;
 
             if create {
-                return create_and_switch(&session, name.clone()).await;
+                return create_and_switch(&session, name.clone(), client).await;
             }
         }
 
@@ -174,6 +178,7 @@ pub async fn login(
             &session.refresh_token,
             &session.profiles[idx],
             session.device,
+            client,
         )
         .await
     } else {
@@ -181,18 +186,28 @@ pub async fn login(
             .with_prompt("Profile name")
             .interact_text()
             .context("Failed to read profile name")?;
-        create_and_switch(&session, name).await
+        create_and_switch(&session, name, client).await
     }
 }
 
-async fn create_and_switch(session: &InitialSession, name: String) -> Result<Crunchyroll> {
+async fn create_and_switch(
+    session: &InitialSession,
+    name: String,
+    client: &ClientOptions,
+) -> Result<Crunchyroll> {
     let username = name.to_lowercase().replace(' ', "_");
 
     let profiles = session.crunchy.profiles().await?;
     match profiles.new_profile(name.clone(), username).await {
         Ok(new_profile) => {
-            println!("Created profile '{}'", new_profile.profile_name);
-            switch_profile(&session.refresh_token, &new_profile, session.device.clone()).await
+            eprintln!("Created profile '{}'", new_profile.profile_name);
+            switch_profile(
+                &session.refresh_token,
+                &new_profile,
+                session.device.clone(),
+                client,
+            )
+            .await
         }
         Err(e) => {
             let msg = format!("{}", e);
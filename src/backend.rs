@@ -0,0 +1,632 @@
+use crate::client::{ClientOptions, DEFAULT_API_BASE};
+use crate::import::retry_with_backoff;
+use crate::limiter::{Outcome, RateLimiter};
+use crate::models::{CrunchylistItem, WatchHistoryItem, WatchlistItem};
+use anyhow::{Context, Result};
+use crunchyroll_rs::list::{Crunchylist, WatchHistoryEntry, WatchlistOptions};
+use crunchyroll_rs::{Crunchyroll, MediaCollection};
+use futures_util::StreamExt;
+use futures_util::stream;
+use futures_util::stream::BoxStream;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, OnceCell};
+
+/// Content types `add_to_watchlist`, `mark_favourite` and `rate` know how to write.
+pub const CONTENT_TYPES: [&str; 2] = ["series", "movie_listing"];
+
+/// Watch history entries asked for per page.
+pub const HISTORY_PAGE_SIZE: u32 = 100;
+
+/// Ratings `rate` accepts, lowest first.
+pub const RATINGS: [&str; 5] = [
+    "OneStar",
+    "TwoStars",
+    "ThreeStars",
+    "FourStars",
+    "FiveStars",
+];
+
+/// The profile-scoped operations export, diff and import need, in terms of the export
+/// models. Implemented for the real API by [`CrunchyrollBackend`]; tests use an in-memory
+/// fake. Login and profile selection stay in `auth`, which produces the real client.
+pub trait Backend: Sync {
+    fn profile_id(&self) -> impl Future<Output = String> + Send;
+
+    /// Paces this backend's requests. Pipelines size their concurrency from it and the
+    /// dashboard shows its current rate.
+    fn limiter(&self) -> &Arc<RateLimiter>;
+
+    fn watchlist(&self) -> impl Future<Output = Result<Vec<WatchlistItem>>> + Send;
+
+    /// Watch history, newest first as the API returns it. Entries that fail to load are
+    /// yielded as errors without ending the stream; a page that still fails after retries
+    /// is yielded as an error and ends it.
+    fn watch_history(&self) -> BoxStream<'_, Result<WatchHistoryItem>>;
+
+    /// One page of watch history (pages start at 1), newest first. Unlike `watch_history`,
+    /// a failed page can be fetched again.
+    fn watch_history_page(
+        &self,
+        page: u32,
+        page_size: u32,
+    ) -> impl Future<Output = Result<HistoryPage>> + Send;
+
+    /// Names of the profile's crunchylists.
+    fn crunchylist_names(&self) -> impl Future<Output = Result<Vec<String>>> + Send;
+
+    fn crunchylist(&self, name: &str) -> impl Future<Output = Result<Vec<CrunchylistItem>>> + Send;
+
+    /// The profile's rating (e.g. "FourStars") for a series or movie listing, if any.
+    fn rating(
+        &self,
+        content_id: &str,
+        content_type: &str,
+    ) -> impl Future<Output = Result<Option<String>>> + Send;
+
+    fn add_to_watchlist(
+        &self,
+        content_id: &str,
+        content_type: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Flag an entry that is already on the watchlist as favourite.
+    fn mark_favourite(
+        &self,
+        content_id: &str,
+        content_type: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn create_crunchylist(&self, name: &str) -> impl Future<Output = Result<()>> + Send;
+
+    fn add_to_crunchylist(
+        &self,
+        list_name: &str,
+        content_id: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn rate(
+        &self,
+        content_id: &str,
+        content_type: &str,
+        rating: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn set_playhead(
+        &self,
+        content_id: &str,
+        playhead: u32,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn mark_as_watched(&self, content_id: &str) -> impl Future<Output = Result<()>> + Send;
+}
+
+pub struct HistoryPage {
+    pub items: Vec<WatchHistoryItem>,
+    /// Entries in the whole history, as the API reports it
+    pub total: u32,
+}
+
+pub struct CrunchyrollBackend {
+    crunchy: Crunchyroll,
+    /// Sends the raw playhead requests the way the library sends its own
+    client: ClientOptions,
+    /// Fetched on first use by the raw playhead endpoints
+    account_id: OnceCell<String>,
+    /// Full crunchylists by name, so adding items doesn't re-fetch the list every time
+    lists: Mutex<HashMap<String, Crunchylist>>,
+}
+
+impl CrunchyrollBackend {
+    pub fn new(crunchy: Crunchyroll, client: &ClientOptions) -> Self {
+        Self {
+            crunchy,
+            client: client.clone(),
+            account_id: OnceCell::new(),
+            lists: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run one library request under the rate limiter.
+    async fn limited<T>(
+        &self,
+        request: impl Future<Output = std::result::Result<T, crunchyroll_rs::error::Error>>,
+    ) -> Result<T> {
+        let _permit = self.client.limiter().acquire().await?;
+        let result = request.await;
+        self.client.limiter().record(match &result {
+            Ok(_) => Outcome::Success,
+            Err(e) => outcome(e),
+        });
+        Ok(result?)
+    }
+
+    async fn account_id(&self) -> Result<&str> {
+        let id = self
+            .account_id
+            .get_or_try_init(|| async {
+                anyhow::Ok(self.limited(self.crunchy.account()).await?.account_id)
+            })
+            .await?;
+        Ok(id)
+    }
+
+    /// A list to add items to; only its id matters, so a cached copy will do.
+    async fn resolve_list(&self, name: &str) -> Result<Crunchylist> {
+        if let Some(list) = self.lists.lock().await.get(name) {
+            return Ok(list.clone());
+        }
+        self.fetch_list(name).await
+    }
+
+    /// Fetch a list with its current items.
+    async fn fetch_list(&self, name: &str) -> Result<Crunchylist> {
+        let lists = self.limited(self.crunchy.crunchylists()).await?;
+        let preview = lists
+            .items
+            .iter()
+            .find(|p| p.title == name)
+            .with_context(|| format!("Crunchylist '{}' not found", name))?;
+        let list = self.limited(preview.crunchylist()).await?;
+        self.lists
+            .lock()
+            .await
+            .insert(name.to_string(), list.clone());
+        Ok(list)
+    }
+
+    /// POST to one of the raw playhead endpoints the library doesn't wrap.
+    async fn post_raw(
+        &self,
+        url: &str,
+        body: serde_json::Value,
+        operation: &str,
+        content_id: &str,
+    ) -> Result<()> {
+        let request = self
+            .crunchy
+            .client()
+            .post(url)
+            .header(
+                "Authorization",
+                format!("Bearer {}", self.crunchy.access_token().await),
+            )
+            .json(&body)
+            .build()?;
+        let (status, _) = self.send_raw(request).await?;
+        check_status(status, operation, content_id)
+    }
+
+    /// Send a request the library doesn't wrap under the rate limiter, returning the
+    /// status and body.
+    async fn send_raw(&self, request: reqwest::Request) -> Result<(u16, String)> {
+        let _permit = self.client.limiter().acquire().await?;
+        let response = self.client.execute(&self.crunchy.client(), request).await?;
+        let status = response.status().as_u16();
+        let retry_after = parse_retry_after(response.headers());
+        let url = response.url().to_string();
+        let body = response.text().await.unwrap_or_default();
+        // Classify Cloudflare's challenge page the same way the library does
+        if status == 403 && body.contains("<title>Just a moment...</title>") {
+            self.client.limiter().record(Outcome::Blocked);
+            return Err(crunchyroll_rs::error::Error::Block {
+                message: "Triggered Cloudflare bot protection".to_string(),
+                body,
+                url,
+            }
+            .into());
+        }
+        self.client.limiter().record(match status {
+            429 => Outcome::RateLimited(retry_after),
+            _ if (200..300).contains(&status) || status == 409 => Outcome::Success,
+            _ => Outcome::Failed,
+        });
+        Ok((status, body))
+    }
+}
+
+impl Backend for CrunchyrollBackend {
+    async fn profile_id(&self) -> String {
+        self.crunchy.profile_id().await
+    }
+
+    fn limiter(&self) -> &Arc<RateLimiter> {
+        self.client.limiter()
+    }
+
+    async fn watchlist(&self) -> Result<Vec<WatchlistItem>> {
+        let entries = self
+            .limited(self.crunchy.watchlist(WatchlistOptions::default()))
+            .await
+            .context("Failed to fetch watchlist")?;
+
+        Ok(entries
+            .iter()
+            .filter_map(|entry| {
+                let (content_id, title, slug, content_type) = extract_series_info(&entry.panel)?;
+                Some(WatchlistItem {
+                    content_id,
+                    title,
+                    slug,
+                    content_type,
+                    is_favourite: entry.is_favorite,
+                    fully_watched: entry.fully_watched,
+                })
+            })
+            .collect())
+    }
+
+    fn watch_history(&self) -> BoxStream<'_, Result<WatchHistoryItem>> {
+        // A page at a time, so every page takes a token and is retried on its own
+        stream::unfold(Some(1), move |page| async move {
+            let page = page?;
+            let fetch = || self.watch_history_page(page, HISTORY_PAGE_SIZE);
+            let (items, next) = match retry_with_backoff(fetch).await {
+                Ok(fetched) => {
+                    let last = fetched.items.is_empty()
+                        || page as usize * HISTORY_PAGE_SIZE as usize >= fetched.total as usize;
+                    let items: Vec<_> = fetched.items.into_iter().map(Ok).collect();
+                    (items, (!last).then_some(page + 1))
+                }
+                Err(e) => {
+                    let e = e.context(format!("Fetching watch history page {}", page));
+                    (vec![Err(e)], None)
+                }
+            };
+            Some((stream::iter(items), next))
+        })
+        .flatten()
+        .boxed()
+    }
+
+    async fn watch_history_page(&self, page: u32, page_size: u32) -> Result<HistoryPage> {
+        #[derive(serde::Deserialize)]
+        struct Page {
+            total: u32,
+            data: Vec<WatchHistoryEntry>,
+        }
+
+        let url = format!(
+            "{}/content/v2/{}/watch-history",
+            DEFAULT_API_BASE,
+            self.account_id().await?
+        );
+        let request = self
+            .crunchy
+            .client()
+            .get(url)
+            .query(&[
+                ("page", page.to_string()),
+                ("page_size", page_size.to_string()),
+                ("locale", "en-US".to_string()),
+            ])
+            .header(
+                "Authorization",
+                format!("Bearer {}", self.crunchy.access_token().await),
+            )
+            .build()?;
+        let (status, body) = self.send_raw(request).await?;
+        check_status(status, "watch_history", &format!("page {}", page))?;
+        let page: Page = serde_json::from_str(&body)
+            .with_context(|| format!("Parsing watch history page {}", page))?;
+        Ok(HistoryPage {
+            items: page.data.into_iter().map(history_item).collect(),
+            total: page.total,
+        })
+    }
+
+    async fn crunchylist_names(&self) -> Result<Vec<String>> {
+        let lists = self
+            .limited(self.crunchy.crunchylists())
+            .await
+            .context("Failed to fetch crunchylists")?;
+        Ok(lists.items.into_iter().map(|p| p.title).collect())
+    }
+
+    async fn crunchylist(&self, name: &str) -> Result<Vec<CrunchylistItem>> {
+        let list = self
+            .fetch_list(name)
+            .await
+            .with_context(|| format!("Failed to fetch crunchylist '{}'", name))?;
+        Ok(list
+            .items
+            .iter()
+            .filter_map(|entry| {
+                let (content_id, title, _, _) = extract_series_info(&entry.panel)?;
+                Some(CrunchylistItem { content_id, title })
+            })
+            .collect())
+    }
+
+    async fn rating(&self, content_id: &str, content_type: &str) -> Result<Option<String>> {
+        let rating = match content_type {
+            "series" => {
+                let series: crunchyroll_rs::Series =
+                    self.limited(self.crunchy.media_from_id(content_id)).await?;
+                self.limited(series.rating()).await?
+            }
+            "movie_listing" => {
+                let ml: crunchyroll_rs::MovieListing =
+                    self.limited(self.crunchy.media_from_id(content_id)).await?;
+                self.limited(ml.rating()).await?
+            }
+            _ => anyhow::bail!("Unknown content type: {}", content_type),
+        };
+        Ok(rating.rating.map(|stars| format!("{:?}", stars)))
+    }
+
+    async fn add_to_watchlist(&self, content_id: &str, content_type: &str) -> Result<()> {
+        match content_type {
+            "series" => {
+                let series: crunchyroll_rs::Series =
+                    self.limited(self.crunchy.media_from_id(content_id)).await?;
+                self.limited(series.add_to_watchlist()).await?;
+            }
+            "movie_listing" => {
+                let ml: crunchyroll_rs::MovieListing =
+                    self.limited(self.crunchy.media_from_id(content_id)).await?;
+                self.limited(ml.add_to_watchlist()).await?;
+            }
+            _ => anyhow::bail!("Unknown content type: {}", content_type),
+        }
+        Ok(())
+    }
+
+    async fn mark_favourite(&self, content_id: &str, content_type: &str) -> Result<()> {
+        let entry = match content_type {
+            "series" => {
+                let series: crunchyroll_rs::Series =
+                    self.limited(self.crunchy.media_from_id(content_id)).await?;
+                self.limited(series.into_watchlist_entry()).await?
+            }
+            "movie_listing" => {
+                let ml: crunchyroll_rs::MovieListing =
+                    self.limited(self.crunchy.media_from_id(content_id)).await?;
+                self.limited(ml.into_watchlist_entry()).await?
+            }
+            _ => anyhow::bail!("Unknown content type: {}", content_type),
+        };
+        let mut entry = entry.with_context(|| format!("{} is not on the watchlist", content_id))?;
+        self.limited(entry.mark_favorite(true)).await?;
+        Ok(())
+    }
+
+    async fn create_crunchylist(&self, name: &str) -> Result<()> {
+        let lists = self.limited(self.crunchy.crunchylists()).await?;
+        let preview = self
+            .limited(lists.create(name))
+            .await
+            .with_context(|| format!("Creating crunchylist '{}'", name))?;
+        let list = self.limited(preview.crunchylist()).await?;
+        self.lists.lock().await.insert(name.to_string(), list);
+        Ok(())
+    }
+
+    async fn add_to_crunchylist(&self, list_name: &str, content_id: &str) -> Result<()> {
+        let list = self.resolve_list(list_name).await?;
+        if let Ok(series) = self
+            .limited(
+                self.crunchy
+                    .media_from_id::<crunchyroll_rs::Series>(content_id),
+            )
+            .await
+        {
+            self.limited(list.add(MediaCollection::from(series)))
+                .await?;
+        } else if let Ok(ml) = self
+            .limited(
+                self.crunchy
+                    .media_from_id::<crunchyroll_rs::MovieListing>(content_id),
+            )
+            .await
+        {
+            self.limited(list.add(MediaCollection::from(ml))).await?;
+        } else {
+            anyhow::bail!(
+                "Content {} not found as series or movie_listing",
+                content_id
+            );
+        }
+        Ok(())
+    }
+
+    async fn rate(&self, content_id: &str, content_type: &str, rating: &str) -> Result<()> {
+        use crunchyroll_rs::media::RatingStar;
+
+        let stars = match rating {
+            "OneStar" => RatingStar::OneStar,
+            "TwoStars" => RatingStar::TwoStars,
+            "ThreeStars" => RatingStar::ThreeStars,
+            "FourStars" => RatingStar::FourStars,
+            "FiveStars" => RatingStar::FiveStars,
+            other => anyhow::bail!("Unknown rating: {}", other),
+        };
+
+        match content_type {
+            "series" => {
+                let series: crunchyroll_rs::Series =
+                    self.limited(self.crunchy.media_from_id(content_id)).await?;
+                self.limited(series.rate(stars)).await?;
+            }
+            "movie_listing" => {
+                let ml: crunchyroll_rs::MovieListing =
+                    self.limited(self.crunchy.media_from_id(content_id)).await?;
+                self.limited(ml.rate(stars)).await?;
+            }
+            _ => anyhow::bail!("Unknown content type: {}", content_type),
+        }
+        Ok(())
+    }
+
+    async fn set_playhead(&self, content_id: &str, playhead: u32) -> Result<()> {
+        let url = format!(
+            "{}/content/v2/{}/playheads",
+            DEFAULT_API_BASE,
+            self.account_id().await?
+        );
+        let body = serde_json::json!({
+            "content_id": content_id,
+            "playhead": playhead
+        });
+        self.post_raw(&url, body, "set_playhead", content_id).await
+    }
+
+    async fn mark_as_watched(&self, content_id: &str) -> Result<()> {
+        let url = format!(
+            "{}/content/v2/discover/{}/mark_as_watched/{}",
+            DEFAULT_API_BASE,
+            self.account_id().await?,
+            content_id
+        );
+        self.post_raw(&url, serde_json::json!({}), "mark_as_watched", content_id)
+            .await
+    }
+}
+
+fn history_item(entry: WatchHistoryEntry) -> WatchHistoryItem {
+    let (title, series_title, partial) = match &entry.panel {
+        Some(panel) => (panel_title(panel), panel_series_title(panel), false),
+        None => (String::new(), String::new(), true),
+    };
+    WatchHistoryItem {
+        content_id: entry.id,
+        parent_id: entry.parent_id,
+        parent_type: entry.parent_type,
+        title,
+        series_title,
+        date_played: entry.date_played,
+        playhead: entry.playhead,
+        fully_watched: entry.fully_watched,
+        partial,
+    }
+}
+
+/// How a failed library request should affect the pace.
+fn outcome(e: &crunchyroll_rs::error::Error) -> Outcome {
+    match e {
+        crunchyroll_rs::error::Error::Request {
+            status: Some(status),
+            message,
+            ..
+        } if status.as_u16() == 429 => Outcome::RateLimited(retry_after_from_message(message)),
+        crunchyroll_rs::error::Error::Block { .. } => Outcome::Blocked,
+        _ => Outcome::Failed,
+    }
+}
+
+/// The library folds Retry-After into its error message as "Try again in N seconds".
+fn retry_after_from_message(message: &str) -> Option<Duration> {
+    let (_, rest) = message.split_once("Try again in ")?;
+    let (secs, _) = rest.split_once(" seconds")?;
+    secs.parse().ok().map(Duration::from_secs)
+}
+
+/// Retry-After as either delay-seconds or an HTTP date.
+fn parse_retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (at.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+/// Treat 2xx and 409 (already exists) as OK; fail on anything else.
+fn check_status(status: u16, operation: &str, content_id: &str) -> Result<()> {
+    if (200..300).contains(&status) || status == 409 {
+        Ok(())
+    } else {
+        anyhow::bail!("{} returned {} for {}", operation, status, content_id)
+    }
+}
+
+/// Extract series/movie_listing ID, title, slug, and content_type from a MediaCollection panel.
+pub fn extract_series_info(panel: &MediaCollection) -> Option<(String, String, String, String)> {
+    match panel {
+        MediaCollection::Episode(ep) => Some((
+            ep.series_id.clone(),
+            ep.series_title.clone(),
+            ep.series_slug_title.clone(),
+            "series".to_string(),
+        )),
+        MediaCollection::Movie(mv) => Some((
+            mv.movie_listing_id.clone(),
+            mv.movie_listing_title.clone(),
+            mv.movie_listing_slug_title.clone(),
+            "movie_listing".to_string(),
+        )),
+        MediaCollection::Series(s) => Some((
+            s.id.clone(),
+            s.title.clone(),
+            s.slug_title.clone(),
+            "series".to_string(),
+        )),
+        MediaCollection::MovieListing(ml) => Some((
+            ml.id.clone(),
+            ml.title.clone(),
+            ml.slug_title.clone(),
+            "movie_listing".to_string(),
+        )),
+        _ => None,
+    }
+}
+
+fn panel_title(panel: &MediaCollection) -> String {
+    match panel {
+        MediaCollection::Episode(ep) => ep.title.clone(),
+        MediaCollection::Movie(mv) => mv.title.clone(),
+        MediaCollection::Series(s) => s.title.clone(),
+        MediaCollection::MovieListing(ml) => ml.title.clone(),
+        _ => String::new(),
+    }
+}
+
+fn panel_series_title(panel: &MediaCollection) -> String {
+    match panel {
+        MediaCollection::Episode(ep) => ep.series_title.clone(),
+        MediaCollection::Movie(mv) => mv.movie_listing_title.clone(),
+        MediaCollection::Series(s) => s.title.clone(),
+        MediaCollection::MovieListing(ml) => ml.title.clone(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+    #[test]
+    fn reads_retry_after_from_library_errors() {
+        assert_eq!(
+            retry_after_from_message("Rate limit detected. Try again in 30 seconds"),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            retry_after_from_message("Rate limit detected. Try again later"),
+            None
+        );
+    }
+
+    #[test]
+    fn reads_retry_after_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("12"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(12)));
+
+        let at = (chrono::Utc::now() + chrono::Duration::seconds(90)).to_rfc2822();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(&at).unwrap());
+        let wait = parse_retry_after(&headers).unwrap();
+        assert!(wait > Duration::from_secs(85) && wait <= Duration::from_secs(90));
+    }
+}
@@ -0,0 +1,437 @@
+#[cfg(feature = "mock-server")]
+use crate::mock_server::{self, Request, Response};
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+#[cfg(feature = "mock-server")]
+use std::net::SocketAddr;
+use std::path::Path;
+#[cfg(feature = "mock-server")]
+use std::sync::Arc;
+use std::sync::Mutex;
+
+pub const CASSETTE_FILE: &str = "cassette.jsonl";
+
+const REDACTED: &str = "REDACTED";
+
+/// Request and response fields holding credentials or personal details.
+const SECRET_FIELDS: [&str; 7] = [
+    "username",
+    "password",
+    "email",
+    "refresh_token",
+    "id_token",
+    "device_id",
+    "device_name",
+];
+
+/// Access token claims that survive redaction; the library reads these.
+const TOKEN_CLAIMS: [&str; 4] = ["account_id", "profile_id", "benefits", "exp"];
+
+/// One API request and the response it got, with secrets redacted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    method: String,
+    /// Path on www.crunchyroll.com, whatever the API base was
+    path: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    query: BTreeMap<String, String>,
+    /// JSON or form body
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    request: Value,
+    status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_after: Option<String>,
+    /// JSON, or a string for anything else (e.g. Cloudflare's HTML)
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    response: Value,
+}
+
+#[cfg(feature = "mock-server")]
+impl Interaction {
+    /// Whether this was recorded for the same request.
+    fn answers(&self, other: &Interaction) -> bool {
+        self.method == other.method
+            && self.path == other.path
+            && self.query == other.query
+            && self.request == other.request
+    }
+}
+
+/// The recorded half of an outgoing request, taken before it is sent.
+pub fn capture(request: &reqwest::Request) -> Interaction {
+    let content_type = request
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    let body = request
+        .body()
+        .and_then(|b| b.as_bytes())
+        .unwrap_or_default();
+    Interaction {
+        method: request.method().to_string(),
+        path: request.url().path().to_string(),
+        query: request.url().query_pairs().into_owned().collect(),
+        request: body_value(content_type, body),
+        status: 0,
+        content_type: None,
+        retry_after: None,
+        response: Value::Null,
+    }
+}
+
+/// Appends interactions to `cassette.jsonl` as they complete, so a run that crashes or is
+/// interrupted still leaves everything up to that point behind.
+pub struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    /// Start a fresh cassette in `dir`, replacing any previous one.
+    pub fn create(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir).with_context(|| format!("Creating {}", dir.display()))?;
+        let path = dir.join(CASSETTE_FILE);
+        let file = File::create(&path).with_context(|| format!("Creating {}", path.display()))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Save `response` as the answer to `interaction` and hand back an equivalent response.
+    pub async fn record(
+        &self,
+        mut interaction: Interaction,
+        response: reqwest::Response,
+    ) -> Result<reqwest::Response, crunchyroll_rs::error::Error> {
+        let status = response.status();
+        let headers = response.headers().clone();
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let body = response.bytes().await?;
+
+        interaction.status = status.as_u16();
+        interaction.content_type = header(reqwest::header::CONTENT_TYPE);
+        interaction.retry_after = header(reqwest::header::RETRY_AFTER);
+        interaction.response = body_value(interaction.content_type.as_deref(), &body);
+        self.write(&interaction)
+            .map_err(|e| crunchyroll_rs::error::Error::Internal {
+                message: format!("{:#}", e),
+            })?;
+
+        let mut rebuilt = http::Response::new(body);
+        *rebuilt.status_mut() = status;
+        *rebuilt.headers_mut() = headers;
+        Ok(rebuilt.into())
+    }
+
+    fn write(&self, interaction: &Interaction) -> Result<()> {
+        let mut line = serde_json::to_string(interaction)?;
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())
+            .and_then(|()| file.flush())
+            .context("Writing cassette")
+    }
+}
+
+/// Parse a body for recording or matching, with secrets redacted.
+fn body_value(content_type: Option<&str>, body: &[u8]) -> Value {
+    if body.is_empty() {
+        return Value::Null;
+    }
+    let text = || Value::String(String::from_utf8_lossy(body).into_owned());
+    let mut value =
+        if content_type.is_some_and(|t| t.starts_with("application/x-www-form-urlencoded")) {
+            serde_urlencoded::from_bytes::<Vec<(String, String)>>(body)
+                .map(|pairs| {
+                    Value::Object(
+                        pairs
+                            .into_iter()
+                            .map(|(k, v)| (k, Value::String(v)))
+                            .collect(),
+                    )
+                })
+                .unwrap_or_else(|_| text())
+        } else {
+            serde_json::from_slice(body).unwrap_or_else(|_| text())
+        };
+    redact(&mut value);
+    value
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                if key == "access_token" {
+                    if let Some(token) = field.as_str() {
+                        *field = Value::String(redact_token(token));
+                    }
+                } else if SECRET_FIELDS.contains(&key.as_str()) && !field.is_null() {
+                    *field = Value::String(REDACTED.to_string());
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// The library decodes the access token's claims, so keep it a JWT: unsigned, with only the
+/// claims it reads.
+fn redact_token(token: &str) -> String {
+    let claims: Map<String, Value> = token
+        .split('.')
+        .nth(1)
+        .and_then(|payload| URL_SAFE_NO_PAD.decode(payload).ok())
+        .and_then(|payload| serde_json::from_slice(&payload).ok())
+        .unwrap_or_default();
+    let claims: Map<String, Value> = claims
+        .into_iter()
+        .filter(|(name, _)| TOKEN_CLAIMS.contains(&name.as_str()))
+        .collect();
+    format!(
+        "{}.{}.{}",
+        URL_SAFE_NO_PAD.encode(json!({ "alg": "HS256", "typ": "JWT" }).to_string()),
+        URL_SAFE_NO_PAD.encode(Value::Object(claims).to_string()),
+        REDACTED
+    )
+}
+
+#[cfg(feature = "mock-server")]
+struct Replay {
+    interactions: Vec<Interaction>,
+    served: Vec<bool>,
+    /// Requests the cassette had no answer for
+    misses: Vec<String>,
+}
+
+/// Serves a recorded cassette on a local port, standing in for the API under `--replay`.
+///
+/// Each request gets the first unserved recorded response to an identical request (same
+/// method, path, query and redacted body), so repeated requests replay in recorded order
+/// regardless of how concurrent writes interleave. Once they run out, the last one repeats.
+#[cfg(feature = "mock-server")]
+pub struct ReplayServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<Replay>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(feature = "mock-server")]
+impl ReplayServer {
+    pub async fn start(dir: &Path) -> Result<Self> {
+        let path = dir.join(CASSETTE_FILE);
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Reading {}", path.display()))?;
+        let interactions = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(n, line)| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("Parsing {} line {}", path.display(), n + 1))
+            })
+            .collect::<Result<Vec<Interaction>>>()?;
+
+        let state = Arc::new(Mutex::new(Replay {
+            served: vec![false; interactions.len()],
+            interactions,
+            misses: Vec::new(),
+        }));
+        let handler_state = state.clone();
+        let (addr, task) = mock_server::listen(
+            0,
+            Arc::new(move |request| replay(&mut handler_state.lock().unwrap(), request)),
+        )
+        .await?;
+        Ok(Self { addr, state, task })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// "METHOD /path" of every request that had no recorded response.
+    pub fn misses(&self) -> Vec<String> {
+        self.state.lock().unwrap().misses.clone()
+    }
+}
+
+#[cfg(feature = "mock-server")]
+impl Drop for ReplayServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(feature = "mock-server")]
+fn replay(state: &mut Replay, request: &Request) -> Response {
+    // The library's pre-login cookie fetch isn't recorded
+    if request.method == "GET" && request.path == "/" {
+        return Response::new(200, "text/html", Vec::new());
+    }
+
+    let wanted = Interaction {
+        method: request.method.clone(),
+        path: request.path.clone(),
+        query: request.query.clone().into_iter().collect(),
+        request: body_value(
+            request.headers.get("content-type").map(String::as_str),
+            &request.body,
+        ),
+        status: 0,
+        content_type: None,
+        retry_after: None,
+        response: Value::Null,
+    };
+    let matching: Vec<usize> = (0..state.interactions.len())
+        .filter(|&i| state.interactions[i].answers(&wanted))
+        .collect();
+    let Some(&index) = matching
+        .iter()
+        .find(|&&i| !state.served[i])
+        .or(matching.last())
+    else {
+        state
+            .misses
+            .push(format!("{} {}", request.method, request.path));
+        return Response::json(404, json!({ "error": "not_recorded" }));
+    };
+    state.served[index] = true;
+
+    let recorded = &state.interactions[index];
+    let body = match &recorded.response {
+        Value::Null => Vec::new(),
+        Value::String(text) => text.clone().into_bytes(),
+        json => json.to_string().into_bytes(),
+    };
+    let content_type = recorded
+        .content_type
+        .as_deref()
+        .unwrap_or("application/json");
+    let mut response = Response::new(recorded.status, content_type, body);
+    if let Some(retry_after) = &recorded.retry_after {
+        response
+            .headers
+            .push(("Retry-After".to_string(), retry_after.clone()));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "mock-server")]
+    use crate::{
+        auth,
+        backend::{Backend, CrunchyrollBackend},
+        client::ClientOptions,
+        mock_server::MockServer,
+    };
+
+    #[cfg(feature = "mock-server")]
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("cassette-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[cfg(feature = "mock-server")]
+    async fn login(client: &ClientOptions, password: &str) -> CrunchyrollBackend {
+        let crunchy = auth::login(
+            Some("source@example.com".to_string()),
+            Some(password.to_string()),
+            Some("Main".to_string()),
+            "",
+            false,
+            client,
+        )
+        .await
+        .unwrap();
+        CrunchyrollBackend::new(crunchy, client)
+    }
+
+    #[test]
+    fn redaction_keeps_token_claims_and_drops_secrets() {
+        let claims = json!({ "account_id": "A1", "profile_id": "P1", "sub": "someone" });
+        let token = format!("x.{}.signature", URL_SAFE_NO_PAD.encode(claims.to_string()));
+        let body = json!({
+            "access_token": token,
+            "refresh_token": "secret",
+            "profiles": [{ "email": "me@example.com", "profile_name": "Main" }],
+        });
+
+        let value = body_value(Some("application/json"), body.to_string().as_bytes());
+        assert_eq!(value["refresh_token"], REDACTED);
+        assert_eq!(value["profiles"][0]["email"], REDACTED);
+        assert_eq!(value["profiles"][0]["profile_name"], "Main");
+        let token = value["access_token"].as_str().unwrap();
+        let payload = URL_SAFE_NO_PAD
+            .decode(token.split('.').nth(1).unwrap())
+            .unwrap();
+        let kept: Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(kept, json!({ "account_id": "A1", "profile_id": "P1" }));
+
+        let form = body_value(
+            Some("application/x-www-form-urlencoded"),
+            b"grant_type=password&username=me%40example.com&password=hunter2",
+        );
+        assert_eq!(
+            form,
+            json!({ "grant_type": "password", "username": REDACTED, "password": REDACTED })
+        );
+    }
+
+    #[cfg(feature = "mock-server")]
+    #[tokio::test]
+    async fn replays_a_recorded_session_offline() {
+        let fixtures = include_str!("../tests/fixtures/mock_crunchyroll.json");
+        let server = MockServer::start(serde_json::from_str(fixtures).unwrap(), 0)
+            .await
+            .unwrap();
+        let dir = temp_dir("replay");
+
+        let recording = ClientOptions::new(Some(&server.url()), true)
+            .unwrap()
+            .record(&dir)
+            .unwrap();
+        let backend = login(&recording, "source-password").await;
+        let ids = |items: Vec<crate::models::WatchlistItem>| -> Vec<String> {
+            items.into_iter().map(|i| i.content_id).collect()
+        };
+        let watchlist = ids(backend.watchlist().await.unwrap());
+        backend.set_playhead("E3", 600).await.unwrap();
+        drop(backend);
+        drop(server);
+
+        let cassette = std::fs::read_to_string(dir.join(CASSETTE_FILE)).unwrap();
+        assert!(!cassette.contains("source-password"));
+        assert!(!cassette.contains("source@example.com"));
+
+        // The mock is gone; everything comes from the cassette, whatever the credentials
+        let replay = ReplayServer::start(&dir).await.unwrap();
+        let replaying = ClientOptions::new(Some(&replay.url()), true).unwrap();
+        let backend = login(&replaying, "anything").await;
+        assert_eq!(ids(backend.watchlist().await.unwrap()), watchlist);
+        backend.set_playhead("E3", 600).await.unwrap();
+        assert!(replay.misses().is_empty());
+
+        assert!(backend.set_playhead("E1", 5).await.is_err());
+        assert_eq!(replay.misses().len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,464 @@
+use crate::diff::DiffFormat;
+use crate::models::{CrunchylistsExport, WatchHistoryExport, WatchHistoryItem, WatchlistItem};
+use crate::selection::{Exports, Selection};
+use crate::ui::DataType;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+const DATA_TYPES: [DataType; 5] = [
+    DataType::Watchlist,
+    DataType::Favourites,
+    DataType::History,
+    DataType::Crunchylists,
+    DataType::Ratings,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl ChangeKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Changed => "changed",
+        }
+    }
+}
+
+/// One difference between two export snapshots.
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub data_type: DataType,
+    pub kind: ChangeKind,
+    pub content_id: String,
+    pub title: String,
+    /// What changed, e.g. "playhead 120s -> watched" or the crunchylist name
+    pub detail: Option<String>,
+}
+
+/// `diff --from --to`: compare two export directories without logging in. Only data
+/// types selected and exported in both are compared.
+pub fn run(
+    from: &Path,
+    to: &Path,
+    selection: Selection,
+    format: DiffFormat,
+    details: bool,
+) -> Result<()> {
+    let selection = selection
+        .exported_in(from)
+        .intersect(selection.exported_in(to));
+    let changes = compare(
+        &Exports::read(from, selection)?,
+        &Exports::read(to, selection)?,
+    );
+    match format {
+        DiffFormat::Table => {
+            println!("\n  Changes from {} to {}", from.display(), to.display());
+            print_table(&changes, selection);
+            if details {
+                print_details(&changes);
+            }
+        }
+        DiffFormat::Json => {
+            let json = if details {
+                serde_json::to_string_pretty(&changes)?
+            } else {
+                serde_json::to_string_pretty(&counts(&changes, selection))?
+            };
+            println!("{}", json);
+        }
+        DiffFormat::Csv => print_csv(&changes, selection, details)?,
+    }
+    Ok(())
+}
+
+fn compare(from: &Exports, to: &Exports) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    // Watchlist membership, then favourite flags
+    let old_wl: HashMap<&str, &WatchlistItem> = from
+        .watchlist
+        .items
+        .iter()
+        .map(|i| (i.content_id.as_str(), i))
+        .collect();
+    let new_wl: HashMap<&str, &WatchlistItem> = to
+        .watchlist
+        .items
+        .iter()
+        .map(|i| (i.content_id.as_str(), i))
+        .collect();
+    diff_keyed(
+        &old_wl,
+        &new_wl,
+        |i| i.title.clone(),
+        |_, _| None,
+        DataType::Watchlist,
+        &mut changes,
+    );
+    diff_keyed(
+        &favourites(&old_wl),
+        &favourites(&new_wl),
+        |i| i.title.clone(),
+        |_, _| None,
+        DataType::Favourites,
+        &mut changes,
+    );
+
+    // History: latest entry per content_id
+    let old_hist = latest_history(&from.history);
+    let new_hist = latest_history(&to.history);
+    diff_keyed(
+        &old_hist,
+        &new_hist,
+        |i| format!("{} - {}", i.series_title, i.title),
+        |old, new| {
+            (old.playhead != new.playhead || old.fully_watched != new.fully_watched)
+                .then(|| format!("{} -> {}", describe_progress(old), describe_progress(new)))
+        },
+        DataType::History,
+        &mut changes,
+    );
+
+    // Crunchylist membership, per list
+    let members = |export: &CrunchylistsExport| -> HashMap<(String, String), String> {
+        export
+            .lists
+            .iter()
+            .flat_map(|l| {
+                l.items
+                    .iter()
+                    .map(|i| ((l.name.clone(), i.content_id.clone()), i.title.clone()))
+            })
+            .collect()
+    };
+    let old_members = members(&from.crunchylists);
+    let new_members = members(&to.crunchylists);
+    for ((list, id), title) in &new_members {
+        if !old_members.contains_key(&(list.clone(), id.clone())) {
+            changes.push(Change {
+                detail: Some(list.clone()),
+                ..change(DataType::Crunchylists, ChangeKind::Added, id, title)
+            });
+        }
+    }
+    for ((list, id), title) in &old_members {
+        if !new_members.contains_key(&(list.clone(), id.clone())) {
+            changes.push(Change {
+                detail: Some(list.clone()),
+                ..change(DataType::Crunchylists, ChangeKind::Removed, id, title)
+            });
+        }
+    }
+
+    let old_ratings: HashMap<&str, _> = from
+        .ratings
+        .items
+        .iter()
+        .map(|i| (i.content_id.as_str(), i))
+        .collect();
+    let new_ratings: HashMap<&str, _> = to
+        .ratings
+        .items
+        .iter()
+        .map(|i| (i.content_id.as_str(), i))
+        .collect();
+    diff_keyed(
+        &old_ratings,
+        &new_ratings,
+        |i| i.title.clone(),
+        |old, new| (old.rating != new.rating).then(|| format!("{} -> {}", old.rating, new.rating)),
+        DataType::Ratings,
+        &mut changes,
+    );
+
+    // HashMap iteration order is arbitrary; keep output stable
+    changes.sort_by(|a, b| {
+        let rank = |c: &Change| DATA_TYPES.iter().position(|d| *d == c.data_type);
+        rank(a)
+            .cmp(&rank(b))
+            .then_with(|| a.title.cmp(&b.title))
+            .then_with(|| a.detail.cmp(&b.detail))
+    });
+    changes
+}
+
+/// Added/removed by key, plus `changed` for entries present in both.
+fn diff_keyed<T>(
+    old: &HashMap<&str, &T>,
+    new: &HashMap<&str, &T>,
+    title: impl Fn(&T) -> String,
+    changed: impl Fn(&T, &T) -> Option<String>,
+    data_type: DataType,
+    changes: &mut Vec<Change>,
+) {
+    for (id, item) in new {
+        match old.get(id) {
+            None => changes.push(change(data_type, ChangeKind::Added, id, &title(item))),
+            Some(previous) => {
+                if let Some(detail) = changed(previous, item) {
+                    changes.push(Change {
+                        detail: Some(detail),
+                        ..change(data_type, ChangeKind::Changed, id, &title(item))
+                    });
+                }
+            }
+        }
+    }
+    for (id, item) in old {
+        if !new.contains_key(id) {
+            changes.push(change(data_type, ChangeKind::Removed, id, &title(item)));
+        }
+    }
+}
+
+fn favourites<'a>(
+    watchlist: &HashMap<&'a str, &'a WatchlistItem>,
+) -> HashMap<&'a str, &'a WatchlistItem> {
+    watchlist
+        .iter()
+        .filter(|(_, i)| i.is_favourite)
+        .map(|(id, i)| (*id, *i))
+        .collect()
+}
+
+fn change(data_type: DataType, kind: ChangeKind, content_id: &str, title: &str) -> Change {
+    Change {
+        data_type,
+        kind,
+        content_id: content_id.to_string(),
+        title: title.to_string(),
+        detail: None,
+    }
+}
+
+fn latest_history(export: &WatchHistoryExport) -> HashMap<&str, &WatchHistoryItem> {
+    // Exports are sorted oldest-first, so later entries overwrite earlier ones
+    export
+        .items
+        .iter()
+        .map(|i| (i.content_id.as_str(), i))
+        .collect()
+}
+
+fn describe_progress(item: &WatchHistoryItem) -> String {
+    if item.fully_watched {
+        "watched".to_string()
+    } else {
+        format!("{}s", item.playhead)
+    }
+}
+
+#[derive(Serialize)]
+struct ChangeCounts {
+    data_type: DataType,
+    added: usize,
+    removed: usize,
+    changed: usize,
+}
+
+/// Added/removed/changed counts per selected data type, in display order.
+fn counts(changes: &[Change], selection: Selection) -> Vec<ChangeCounts> {
+    DATA_TYPES
+        .into_iter()
+        .filter(|&data_type| selection.includes(data_type))
+        .map(|data_type| {
+            let n = |kind| {
+                changes
+                    .iter()
+                    .filter(|c| c.data_type == data_type && c.kind == kind)
+                    .count()
+            };
+            ChangeCounts {
+                data_type,
+                added: n(ChangeKind::Added),
+                removed: n(ChangeKind::Removed),
+                changed: n(ChangeKind::Changed),
+            }
+        })
+        .collect()
+}
+
+fn print_table(changes: &[Change], selection: Selection) {
+    println!();
+    println!(
+        "  {:<14} {:>10} {:>10} {:>10}",
+        "Data Type", "Added", "Removed", "Changed"
+    );
+    println!("  {}", "─".repeat(47));
+    for c in counts(changes, selection) {
+        println!(
+            "  {:<14} {:>10} {:>10} {:>10}",
+            c.data_type, c.added, c.removed, c.changed
+        );
+    }
+    println!();
+}
+
+fn print_details(changes: &[Change]) {
+    for dt in DATA_TYPES {
+        let of_type: Vec<_> = changes.iter().filter(|c| c.data_type == dt).collect();
+        if of_type.is_empty() {
+            continue;
+        }
+        println!("  {}", dt);
+        for c in of_type {
+            let marker = match c.kind {
+                ChangeKind::Added => '+',
+                ChangeKind::Removed => '-',
+                ChangeKind::Changed => '~',
+            };
+            match &c.detail {
+                Some(detail) => println!("    {} {} ({})", marker, c.title, detail),
+                None => println!("    {} {}", marker, c.title),
+            }
+        }
+        println!();
+    }
+}
+
+fn print_csv(changes: &[Change], selection: Selection, details: bool) -> Result<()> {
+    let mut out = csv::Writer::from_writer(std::io::stdout());
+    if details {
+        out.write_record(["data_type", "change", "content_id", "title", "detail"])?;
+        for c in changes {
+            out.write_record([
+                c.data_type.to_string().to_lowercase().as_str(),
+                c.kind.as_str(),
+                &c.content_id,
+                &c.title,
+                c.detail.as_deref().unwrap_or_default(),
+            ])?;
+        }
+    } else {
+        out.write_record(["data_type", "added", "removed", "changed"])?;
+        for c in counts(changes, selection) {
+            out.write_record([
+                c.data_type.to_string().to_lowercase(),
+                c.added.to_string(),
+                c.removed.to_string(),
+                c.changed.to_string(),
+            ])?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{history_item, metadata};
+    use crate::models::{
+        CrunchylistData, CrunchylistItem, RatingItem, RatingsExport, WatchlistExport,
+    };
+
+    fn snapshot(
+        watchlist: Vec<(&str, bool)>,
+        history: Vec<(&str, u32, bool)>,
+        lists: Vec<(&str, &str)>,
+        ratings: Vec<(&str, &str)>,
+    ) -> Exports {
+        Exports {
+            watchlist: WatchlistExport {
+                metadata: metadata(0),
+                items: watchlist
+                    .into_iter()
+                    .map(|(id, is_favourite)| WatchlistItem {
+                        content_id: id.to_string(),
+                        title: id.to_string(),
+                        slug: String::new(),
+                        content_type: "series".to_string(),
+                        is_favourite,
+                        fully_watched: false,
+                    })
+                    .collect(),
+            },
+            history: WatchHistoryExport {
+                metadata: metadata(0),
+                items: history
+                    .into_iter()
+                    .map(|(id, playhead, fully_watched)| history_item(id, playhead, fully_watched))
+                    .collect(),
+            },
+            crunchylists: CrunchylistsExport {
+                metadata: metadata(0),
+                lists: lists
+                    .into_iter()
+                    .map(|(name, id)| CrunchylistData {
+                        name: name.to_string(),
+                        items: vec![CrunchylistItem {
+                            content_id: id.to_string(),
+                            title: id.to_string(),
+                        }],
+                    })
+                    .collect(),
+            },
+            ratings: RatingsExport {
+                metadata: metadata(0),
+                items: ratings
+                    .into_iter()
+                    .map(|(id, rating)| RatingItem {
+                        content_id: id.to_string(),
+                        content_type: "series".to_string(),
+                        title: id.to_string(),
+                        rating: rating.to_string(),
+                    })
+                    .collect(),
+            },
+            selection: Selection::default(),
+        }
+    }
+
+    #[test]
+    fn compare_finds_every_kind_of_change() {
+        let from = snapshot(
+            vec![("S1", false), ("S2", true)],
+            vec![("E1", 100, false), ("E2", 0, true)],
+            vec![("Faves", "S1")],
+            vec![("S1", "ThreeStars")],
+        );
+        let to = snapshot(
+            vec![("S1", true), ("S3", false)],
+            vec![("E1", 900, false), ("E2", 0, true), ("E3", 60, false)],
+            vec![("Faves", "S3")],
+            vec![("S1", "FiveStars")],
+        );
+
+        let summary: Vec<_> = compare(&from, &to)
+            .into_iter()
+            .map(|c| {
+                format!(
+                    "{} {} {} {}",
+                    c.data_type,
+                    c.kind.as_str(),
+                    c.content_id,
+                    c.detail.unwrap_or_default()
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                "Watchlist removed S2 ",
+                "Watchlist added S3 ",
+                "Favourites added S1 ",
+                "Favourites removed S2 ",
+                "History changed E1 100s -> 900s",
+                "History added E3 ",
+                "Crunchylists removed S1 Faves",
+                "Crunchylists added S3 Faves",
+                "Ratings changed S1 ThreeStars -> FiveStars",
+            ]
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use crate::cassette::{self, Recorder};
+use crate::limiter::RateLimiter;
+use anyhow::{Context, Result};
+use crunchyroll_rs::crunchyroll::CrunchyrollBuilder;
+use crunchyroll_rs::{Crunchyroll, Locale};
+use futures_util::future::BoxFuture;
+use reqwest::{Client, Url};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::task::Poll;
+
+pub const DEFAULT_API_BASE: &str = "https://www.crunchyroll.com";
+const API_HOST: &str = "www.crunchyroll.com";
+
+/// How to reach the API, shared by every login in a run.
+#[derive(Clone, Default)]
+pub struct ClientOptions {
+    /// Replaces `https://www.crunchyroll.com` in every API request, e.g. a reverse proxy or
+    /// a local stand-in server. May include a path prefix.
+    api_base: Option<Url>,
+    /// Saves every request and response when recording a cassette
+    recorder: Option<Arc<Recorder>>,
+    /// Paces requests across every backend in the run
+    limiter: Arc<RateLimiter>,
+    /// Address of the local stand-in server at the API base, whose self-signed certificate
+    /// is accepted; resolved once, up front
+    local_server: Option<SocketAddr>,
+}
+
+impl ClientOptions {
+    /// With `insecure`, the API base must be on this machine; its certificate isn't checked.
+    pub fn new(api_base: Option<&str>, insecure: bool) -> Result<Self> {
+        let mut loopback = None;
+        let api_base = api_base
+            .map(|base| {
+                let url =
+                    Url::parse(base).with_context(|| format!("Invalid API base '{}'", base))?;
+                if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+                    anyhow::bail!("Invalid API base '{}': expected an http(s) URL", base);
+                }
+                loopback = loopback_addr(&url);
+                // Credentials and tokens go to the API base, so only in the clear locally
+                if url.scheme() == "http" && loopback.is_none() {
+                    anyhow::bail!(
+                        "Refusing to send credentials to '{}' over plain http; use https",
+                        base
+                    );
+                }
+                Ok(url)
+            })
+            .transpose()?;
+        anyhow::ensure!(
+            !insecure || loopback.is_some(),
+            "--insecure only works with an --api-base on this machine"
+        );
+        Ok(Self {
+            api_base,
+            local_server: loopback.filter(|_| insecure),
+            ..Default::default()
+        })
+    }
+
+    /// Send at most `rate` requests per second, `concurrency` at a time.
+    pub fn limit(mut self, rate: f64, concurrency: usize) -> Self {
+        self.limiter = Arc::new(RateLimiter::new(rate, concurrency));
+        self
+    }
+
+    pub fn limiter(&self) -> &Arc<RateLimiter> {
+        &self.limiter
+    }
+
+    /// Also save every API exchange to a cassette in `dir`.
+    pub fn record(mut self, dir: &Path) -> Result<Self> {
+        self.recorder = Some(Arc::new(Recorder::create(dir)?));
+        Ok(self)
+    }
+
+    /// A client builder that sends its requests to the configured API base.
+    pub fn builder(&self) -> Result<CrunchyrollBuilder> {
+        let builder = Crunchyroll::builder().locale(Locale::en_US);
+        if self.api_base.is_none() && self.recorder.is_none() {
+            return Ok(builder);
+        }
+
+        let client = match self.local_server {
+            // The library fetches https://www.crunchyroll.com for cookies before logging in,
+            // bypassing the middleware. Point that at the local server too, which can only
+            // present a self-signed certificate.
+            Some(addr) => Client::builder()
+                .cookie_store(true)
+                .default_headers(CrunchyrollBuilder::DEFAULT_HEADERS.into_iter().collect())
+                .resolve(API_HOST, addr)
+                .danger_accept_invalid_certs(true),
+            None => {
+                CrunchyrollBuilder::predefined_client_builder().https_only(self.api_base.is_none())
+            }
+        }
+        .build()
+        .context("Failed to build HTTP client")?;
+
+        Ok(builder.client(client.clone()).middleware(Transport {
+            client,
+            options: self.clone(),
+        }))
+    }
+
+    /// Send a request for `https://www.crunchyroll.com` the way library requests go: to the
+    /// API base, and into the cassette when recording.
+    pub async fn execute(
+        &self,
+        client: &Client,
+        mut request: reqwest::Request,
+    ) -> Result<reqwest::Response, crunchyroll_rs::error::Error> {
+        let interaction = self.recorder.as_ref().map(|_| cassette::capture(&request));
+        if let Some(base) = &self.api_base
+            && request.url().host_str() == Some(API_HOST)
+        {
+            *request.url_mut() = rebase(base, request.url());
+        }
+
+        let response = client.execute(request).await?;
+        match (&self.recorder, interaction) {
+            (Some(recorder), Some(interaction)) => recorder.record(interaction, response).await,
+            _ => Ok(response),
+        }
+    }
+}
+
+/// Blocks on name resolution for a host name, so only called while setting up.
+fn loopback_addr(base: &Url) -> Option<SocketAddr> {
+    base.socket_addrs(|| None)
+        .ok()?
+        .into_iter()
+        .find(|addr| addr.ip().is_loopback())
+}
+
+/// Middleware that routes library requests through [`ClientOptions::execute`]. The library
+/// holds a lock around middleware calls, so requests through it don't overlap.
+struct Transport {
+    client: Client,
+    options: ClientOptions,
+}
+
+impl tower_service::Service<reqwest::Request> for Transport {
+    type Response = reqwest::Response;
+    type Error = crunchyroll_rs::error::Error;
+    type Future = BoxFuture<'static, Result<reqwest::Response, crunchyroll_rs::error::Error>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: reqwest::Request) -> Self::Future {
+        let client = self.client.clone();
+        let options = self.options.clone();
+        Box::pin(async move { options.execute(&client, request).await })
+    }
+}
+
+fn rebase(base: &Url, url: &Url) -> Url {
+    let mut rebased = base.clone();
+    rebased.set_path(&format!(
+        "{}{}",
+        base.path().trim_end_matches('/'),
+        url.path()
+    ));
+    rebased.set_query(url.query());
+    rebased
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_http_and_insecure_only_locally() {
+        assert!(ClientOptions::new(Some("http://127.0.0.1:8080"), true).is_ok());
+        assert!(ClientOptions::new(Some("https://proxy.example/crunchyroll"), false).is_ok());
+        let err = ClientOptions::new(Some("http://proxy.example"), false)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("plain http"), "{}", err);
+        let err = ClientOptions::new(Some("https://proxy.example"), true)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("--insecure"), "{}", err);
+        assert!(ClientOptions::new(None, true).is_err());
+    }
+
+    #[test]
+    fn rebase_keeps_path_prefix_and_query() {
+        let url =
+            Url::parse("https://www.crunchyroll.com/content/v2/cms/series?locale=en-US").unwrap();
+
+        let base = Url::parse("http://127.0.0.1:8080").unwrap();
+        assert_eq!(
+            rebase(&base, &url).as_str(),
+            "http://127.0.0.1:8080/content/v2/cms/series?locale=en-US"
+        );
+
+        let base = Url::parse("https://proxy.example/crunchyroll/").unwrap();
+        assert_eq!(
+            rebase(&base, &url).as_str(),
+            "https://proxy.example/crunchyroll/content/v2/cms/series?locale=en-US"
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use anyhow::{Context, Result, anyhow};
+use argon2::Argon2;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Checked for the passphrase before prompting
+pub const PASSPHRASE_ENV: &str = "CRUNCHYROLL_EXPORT_PASSPHRASE";
+
+const CIPHER: &str = "xchacha20poly1305";
+const KDF: &str = "argon2id";
+
+/// What an encrypted export file holds instead of the export itself. Still JSON, so the
+/// file keeps its name and is recognised by its single `encrypted` key.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    encrypted: Sealed,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    cipher: String,
+    kdf: String,
+    /// Base64, like the two below
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Key derived from a passphrase, with the salt needed to derive it again.
+pub struct Key {
+    salt: [u8; 16],
+    cipher: XChaCha20Poly1305,
+}
+
+impl Key {
+    /// A key with a fresh salt, for writing an export.
+    pub fn new(passphrase: &str) -> Result<Self> {
+        let mut salt = [0; 16];
+        OsRng.fill_bytes(&mut salt);
+        Self::derive(passphrase, salt)
+    }
+
+    fn derive(passphrase: &str, salt: [u8; 16]) -> Result<Self> {
+        let mut key = [0; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow!("Deriving key from passphrase: {}", e))?;
+        Ok(Self {
+            salt,
+            cipher: XChaCha20Poly1305::new(&key.into()),
+        })
+    }
+
+    /// `plaintext` wrapped in an envelope, as JSON on one line.
+    pub fn seal(&self, plaintext: &[u8]) -> Result<String> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| anyhow!("Encryption failed"))?;
+        let envelope = Envelope {
+            encrypted: Sealed {
+                cipher: CIPHER.to_string(),
+                kdf: KDF.to_string(),
+                salt: STANDARD.encode(self.salt),
+                nonce: STANDARD.encode(nonce),
+                ciphertext: STANDARD.encode(ciphertext),
+            },
+        };
+        Ok(serde_json::to_string(&envelope)?)
+    }
+}
+
+/// Whether a parsed export file is an envelope rather than the export itself.
+pub fn is_sealed(doc: &Value) -> bool {
+    doc.as_object()
+        .is_some_and(|o| o.len() == 1 && o.contains_key("encrypted"))
+}
+
+/// Whether the file at `path` is an envelope.
+pub fn is_encrypted(path: &Path) -> Result<bool> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+    Ok(serde_json::from_str(&content).is_ok_and(|doc| is_sealed(&doc)))
+}
+
+/// The plaintext in an envelope, using the passphrase from `passphrase`.
+pub fn open(doc: Value) -> Result<Vec<u8>> {
+    let Envelope { encrypted: sealed } = serde_json::from_value(doc)?;
+    anyhow::ensure!(
+        sealed.cipher == CIPHER && sealed.kdf == KDF,
+        "Unsupported encryption {} with {}",
+        sealed.cipher,
+        sealed.kdf
+    );
+    let salt = STANDARD
+        .decode(&sealed.salt)?
+        .try_into()
+        .map_err(|_| anyhow!("Salt has the wrong length"))?;
+    let nonce: [u8; 24] = STANDARD
+        .decode(&sealed.nonce)?
+        .try_into()
+        .map_err(|_| anyhow!("Nonce has the wrong length"))?;
+    let ciphertext = STANDARD.decode(&sealed.ciphertext)?;
+
+    let passphrase = passphrase(false)?;
+    let mut state = STATE.lock().unwrap();
+    let state = state.get_or_insert_with(|| State::new(passphrase.clone()));
+    let key = match state.keys.iter().position(|k| k.salt == salt) {
+        Some(i) => &state.keys[i],
+        None => {
+            state.keys.push(Key::derive(&passphrase, salt)?);
+            state.keys.last().unwrap()
+        }
+    };
+    let plaintext = key
+        .cipher
+        .decrypt(&XNonce::from(nonce), ciphertext.as_slice())
+        .map_err(|_| anyhow!("Wrong passphrase, or the file was changed after encryption"))?;
+    state.sealing = true;
+    Ok(plaintext)
+}
+
+/// Contents for a file written next to an export (journal line, failures, reports,
+/// plans, target snapshot): sealed if this run has read an encrypted export file,
+/// as is otherwise.
+pub fn seal_if_encrypted(plaintext: String) -> Result<String> {
+    let mut state = STATE.lock().unwrap();
+    let Some(state) = state.as_mut().filter(|s| s.sealing) else {
+        return Ok(plaintext);
+    };
+    if state.keys.is_empty() {
+        let key = Key::new(&state.passphrase)?;
+        state.keys.push(key);
+    }
+    state.keys[0].seal(plaintext.as_bytes())
+}
+
+/// Reverse of `seal_if_encrypted`: `content` itself unless it is an envelope.
+pub fn open_if_sealed(content: &str) -> Result<String> {
+    if !content.trim_start().starts_with("{\"encrypted\"") {
+        return Ok(content.to_string());
+    }
+    let doc: Value = serde_json::from_str(content)?;
+    if !is_sealed(&doc) {
+        return Ok(content.to_string());
+    }
+    Ok(String::from_utf8(open(doc)?)?)
+}
+
+struct State {
+    passphrase: String,
+    /// Keys derived from the passphrase so far; the first one also seals side files
+    keys: Vec<Key>,
+    /// An encrypted export file was read, so side files get sealed too
+    sealing: bool,
+}
+
+impl State {
+    fn new(passphrase: String) -> Self {
+        Self {
+            passphrase,
+            keys: Vec::new(),
+            sealing: false,
+        }
+    }
+}
+
+/// Passphrase for this run and the keys derived from it so far. Argon2 is slow on
+/// purpose, and all the files from one export share a salt.
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+/// Use `passphrase` from now on instead of the environment or a prompt.
+pub fn set_passphrase(passphrase: String) {
+    *STATE.lock().unwrap() = Some(State::new(passphrase));
+}
+
+/// The passphrase set for this run, else the one in `PASSPHRASE_ENV`, else one asked
+/// for on the terminal (twice with `confirm`, for a new one). Asked for at most once.
+pub fn passphrase(confirm: bool) -> Result<String> {
+    let mut state = STATE.lock().unwrap();
+    if let Some(state) = &*state {
+        return Ok(state.passphrase.clone());
+    }
+    let passphrase = match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => passphrase,
+        Err(_) => prompt(confirm)?,
+    };
+    anyhow::ensure!(!passphrase.is_empty(), "The passphrase is empty");
+    *state = Some(State::new(passphrase.clone()));
+    Ok(passphrase)
+}
+
+fn prompt(confirm: bool) -> Result<String> {
+    anyhow::ensure!(
+        std::io::stdin().is_terminal(),
+        "No passphrase for the encrypted export: set {} or pass --passphrase-file",
+        PASSPHRASE_ENV
+    );
+    let passphrase = rpassword::prompt_password("Export passphrase: ")?;
+    if confirm {
+        let again = rpassword::prompt_password("Repeat passphrase: ")?;
+        anyhow::ensure!(passphrase == again, "The passphrases don't match");
+    }
+    Ok(passphrase)
+}
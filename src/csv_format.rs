@@ -0,0 +1,182 @@
+use crate::models::{
+    CrunchylistData, CrunchylistItem, CrunchylistsExport, ExportMetadata, RatingItem,
+    RatingsExport, SCHEMA_VERSION, WatchHistoryExport, WatchHistoryItem, WatchlistExport,
+    WatchlistItem,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+/// `filename` with a .csv extension instead of .json.
+pub fn csv_name(filename: &str) -> String {
+    Path::new(filename)
+        .with_extension("csv")
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// An export that can be written as a single CSV file, one item per row. The headers
+/// are the item's field names, in order.
+pub trait Rows {
+    const HEADERS: &'static [&'static str];
+
+    fn write_rows<W: std::io::Write>(&self, out: &mut csv::Writer<W>) -> Result<()>;
+}
+
+/// Crunchylists flattened to one row per item; a list with no items is one row with an
+/// empty content_id, so it survives the round trip.
+#[derive(Serialize, Deserialize)]
+struct CrunchylistRow {
+    list: String,
+    content_id: String,
+    title: String,
+}
+
+impl Rows for WatchlistExport {
+    const HEADERS: &'static [&'static str] = &[
+        "content_id",
+        "title",
+        "slug",
+        "content_type",
+        "is_favourite",
+        "fully_watched",
+    ];
+
+    fn write_rows<W: std::io::Write>(&self, out: &mut csv::Writer<W>) -> Result<()> {
+        self.items.iter().try_for_each(|i| out.serialize(i))?;
+        Ok(())
+    }
+}
+
+impl Rows for WatchHistoryExport {
+    const HEADERS: &'static [&'static str] = &[
+        "content_id",
+        "parent_id",
+        "parent_type",
+        "title",
+        "series_title",
+        "date_played",
+        "playhead",
+        "fully_watched",
+        "partial",
+    ];
+
+    fn write_rows<W: std::io::Write>(&self, out: &mut csv::Writer<W>) -> Result<()> {
+        self.items.iter().try_for_each(|i| out.serialize(i))?;
+        Ok(())
+    }
+}
+
+impl Rows for CrunchylistsExport {
+    const HEADERS: &'static [&'static str] = &["list", "content_id", "title"];
+
+    fn write_rows<W: std::io::Write>(&self, out: &mut csv::Writer<W>) -> Result<()> {
+        for list in &self.lists {
+            if list.items.is_empty() {
+                out.write_record([list.name.as_str(), "", ""])?;
+            }
+            for item in &list.items {
+                out.write_record([&list.name, &item.content_id, &item.title])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Rows for RatingsExport {
+    const HEADERS: &'static [&'static str] = &["content_id", "content_type", "title", "rating"];
+
+    fn write_rows<W: std::io::Write>(&self, out: &mut csv::Writer<W>) -> Result<()> {
+        self.items.iter().try_for_each(|i| out.serialize(i))?;
+        Ok(())
+    }
+}
+
+/// `data` as CSV, headers first even when there are no rows.
+pub fn to_csv<T: Rows>(data: &T) -> Result<Vec<u8>> {
+    let mut out = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    out.write_record(T::HEADERS)?;
+    data.write_rows(&mut out)?;
+    out.into_inner().context("Writing CSV")
+}
+
+/// Read the CSV form of export file `filename` (e.g. "watchlist.json") at `path` into
+/// the same document the JSON file would hold. CSV has no room for metadata: the profile
+/// name is left empty, the export time is when the file was last modified, and the
+/// count is the number of rows.
+pub fn read(path: &Path, filename: &str) -> Result<Value> {
+    let modified: DateTime<Utc> = std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .with_context(|| format!("Reading {}", path.display()))?
+        .into();
+    let metadata = |total_count| ExportMetadata {
+        schema_version: SCHEMA_VERSION,
+        profile_name: String::new(),
+        exported_at: modified,
+        total_count,
+        partial: false,
+    };
+    let parsing = || format!("Parsing {}", path.display());
+    let doc = match filename {
+        "watchlist.json" => {
+            let items: Vec<WatchlistItem> = rows(path).with_context(parsing)?;
+            serde_json::to_value(WatchlistExport {
+                metadata: metadata(items.len()),
+                items,
+            })
+        }
+        "watch_history.json" => {
+            let items: Vec<WatchHistoryItem> = rows(path).with_context(parsing)?;
+            serde_json::to_value(WatchHistoryExport {
+                metadata: metadata(items.len()),
+                items,
+            })
+        }
+        "crunchylists.json" => {
+            let mut lists: Vec<CrunchylistData> = Vec::new();
+            for row in rows::<CrunchylistRow>(path).with_context(parsing)? {
+                let i = match lists.iter().position(|l| l.name == row.list) {
+                    Some(i) => i,
+                    None => {
+                        lists.push(CrunchylistData {
+                            name: row.list,
+                            items: Vec::new(),
+                        });
+                        lists.len() - 1
+                    }
+                };
+                if !row.content_id.is_empty() {
+                    lists[i].items.push(CrunchylistItem {
+                        content_id: row.content_id,
+                        title: row.title,
+                    });
+                }
+            }
+            let total = lists.iter().map(|l| l.items.len()).sum();
+            serde_json::to_value(CrunchylistsExport {
+                metadata: metadata(total),
+                lists,
+            })
+        }
+        "ratings.json" => {
+            let items: Vec<RatingItem> = rows(path).with_context(parsing)?;
+            serde_json::to_value(RatingsExport {
+                metadata: metadata(items.len()),
+                items,
+            })
+        }
+        _ => anyhow::bail!("{} has no CSV form", filename),
+    };
+    Ok(doc?)
+}
+
+fn rows<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let rows = reader.deserialize().collect::<Result<_, _>>()?;
+    Ok(rows)
+}
@@ -1,127 +1,467 @@
-use crate::import::fetch_target_state;
-use crate::models::{self, CrunchylistsExport, RatingsExport, WatchHistoryExport, WatchlistExport};
+use crate::backend::Backend;
+use crate::import::TargetState;
+use crate::models::{
+    CrunchylistsExport, RatingsExport, WatchHistoryExport, WatchHistoryItem, WatchlistExport,
+};
+use crate::selection::{Exports, Selection};
+use crate::target_cache::TargetCache;
+use crate::ui::DataType;
 use anyhow::Result;
-use crunchyroll_rs::Crunchyroll;
-use std::collections::HashSet;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DiffFormat {
+    /// Aligned table of counts (and missing items with --details)
+    #[default]
+    Table,
+    Json,
+    Csv,
+}
 
 pub struct DiffResult {
-    pub watchlist: DiffCounts,
-    pub history: DiffCounts,
-    pub crunchylists: DiffCounts,
-    pub ratings: DiffCounts,
+    pub watchlist: DiffSection,
+    pub favourites: DiffSection,
+    pub history: DiffSection,
+    pub crunchylists: DiffSection,
+    pub ratings: DiffSection,
+    /// Data types that were compared; the other sections are empty and not shown
+    pub selection: Selection,
+}
+
+impl DiffResult {
+    fn sections(&self) -> Vec<(&'static str, &DiffSection)> {
+        [
+            (DataType::Watchlist, "Watchlist", &self.watchlist),
+            (DataType::Favourites, "Favourites", &self.favourites),
+            (DataType::History, "History", &self.history),
+            (DataType::Crunchylists, "Crunchylists", &self.crunchylists),
+            (DataType::Ratings, "Ratings", &self.ratings),
+        ]
+        .into_iter()
+        .filter(|(data_type, _, _)| self.selection.includes(*data_type))
+        .map(|(_, name, section)| (name, section))
+        .collect()
+    }
 }
 
+/// One data type's comparison, keeping the items in each bucket.
+#[derive(Serialize)]
+pub struct DiffSection {
+    pub in_export: usize,
+    pub on_target: usize,
+    pub missing: Vec<DiffItem>,
+    pub already_there: Vec<DiffItem>,
+    /// On the target but with less progress than the export (history only)
+    pub behind: Option<Vec<DiffItem>>,
+}
+
+impl DiffSection {
+    pub fn counts(&self) -> DiffCounts {
+        DiffCounts {
+            in_export: self.in_export,
+            on_target: self.on_target,
+            missing: self.missing.len(),
+            already_there: self.already_there.len(),
+            behind: self.behind.as_ref().map(Vec::len),
+        }
+    }
+}
+
+#[derive(Serialize)]
 pub struct DiffCounts {
     pub in_export: usize,
     pub on_target: usize,
     pub missing: usize,
     pub already_there: usize,
+    pub behind: Option<usize>,
 }
 
-pub async fn run(crunchy: &Crunchyroll, input_dir: &Path) -> Result<()> {
-    let result = compute_diff(crunchy, input_dir).await?;
-    print_diff_table(&result);
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffItem {
+    pub content_id: String,
+    pub title: String,
+    /// Series an episode belongs to (history only)
+    pub series_title: Option<String>,
+    pub content_type: Option<String>,
+    /// Crunchylist the item is in (crunchylists only)
+    pub list: Option<String>,
+}
+
+pub async fn run<B: Backend>(
+    crunchy: &B,
+    input_dir: &Path,
+    selection: Selection,
+    format: DiffFormat,
+    details: bool,
+    cache_ttl: Duration,
+) -> Result<()> {
+    let result = compute_diff(crunchy, input_dir, selection, cache_ttl).await?;
+    match format {
+        DiffFormat::Table => {
+            print_diff_table(&result);
+            if details {
+                print_diff_details(&result);
+            }
+        }
+        DiffFormat::Json => print_diff_json(&result, details)?,
+        DiffFormat::Csv => print_diff_csv(&result, details)?,
+    }
     Ok(())
 }
 
-pub async fn compute_diff(crunchy: &Crunchyroll, input_dir: &Path) -> Result<DiffResult> {
-    let watchlist_export: WatchlistExport = models::read_export(input_dir, "watchlist.json")?;
-    let history_export: WatchHistoryExport = models::read_export(input_dir, "watch_history.json")?;
-    let crunchylists_export: CrunchylistsExport =
-        models::read_export(input_dir, "crunchylists.json")?;
-    let ratings_export: RatingsExport = models::read_export(input_dir, "ratings.json")?;
+/// Compare the selected data types in the export against the target, reusing a cached
+/// target snapshot younger than `cache_ttl`.
+pub async fn compute_diff<B: Backend>(
+    crunchy: &B,
+    input_dir: &Path,
+    selection: Selection,
+    cache_ttl: Duration,
+) -> Result<DiffResult> {
+    let exports = Exports::read(input_dir, selection)?;
+    let (_, target) = TargetCache::load_or_fetch(
+        crunchy,
+        input_dir,
+        exports.selection,
+        &exports.ratings.items,
+        cache_ttl,
+    )
+    .await?;
 
-    let target = fetch_target_state(crunchy).await?;
+    Ok(DiffResult {
+        selection: exports.selection,
+        ..diff_against(
+            &exports.watchlist,
+            &exports.history,
+            &exports.crunchylists,
+            &exports.ratings,
+            &target,
+        )
+    })
+}
 
-    // Compute diffs
-    let export_wl_ids: HashSet<&str> = watchlist_export
-        .items
-        .iter()
-        .map(|i| i.content_id.as_str())
-        .collect();
-    let wl_already = export_wl_ids
-        .iter()
-        .filter(|id| target.watchlist_ids.contains(**id))
-        .count();
+/// Sort exported items into missing / already there (/ behind) against the target.
+pub fn diff_against(
+    watchlist_export: &WatchlistExport,
+    history_export: &WatchHistoryExport,
+    crunchylists_export: &CrunchylistsExport,
+    ratings_export: &RatingsExport,
+    target: &TargetState,
+) -> DiffResult {
+    let mut seen = HashSet::new();
+    let mut watchlist = empty_section(target.watchlist_ids.len());
+    let mut favourites = empty_section(target.watchlist_favourites.len());
+    for item in &watchlist_export.items {
+        if !seen.insert(item.content_id.as_str()) {
+            continue;
+        }
+        let entry = DiffItem {
+            content_id: item.content_id.clone(),
+            title: item.title.clone(),
+            series_title: None,
+            content_type: Some(item.content_type.clone()),
+            list: None,
+        };
+        watchlist.in_export += 1;
+        // Favourite flags: exported favourites not (yet) marked as favourite on the target
+        if item.is_favourite {
+            favourites.in_export += 1;
+            if target.watchlist_favourites.contains(&item.content_id) {
+                favourites.already_there.push(entry.clone());
+            } else {
+                favourites.missing.push(entry.clone());
+            }
+        }
+        if target.watchlist_ids.contains(&item.content_id) {
+            watchlist.already_there.push(entry);
+        } else {
+            watchlist.missing.push(entry);
+        }
+    }
 
-    let export_hist_ids: HashSet<&str> = history_export
+    // Keyed by content_id; for repeated entries the latest (last, as exports are sorted
+    // oldest-first) wins
+    let export_hist: HashMap<&str, &WatchHistoryItem> = history_export
         .items
         .iter()
-        .map(|i| i.content_id.as_str())
+        .map(|i| (i.content_id.as_str(), i))
         .collect();
-    let hist_already = export_hist_ids
-        .iter()
-        .filter(|id| target.history_ids.contains(**id))
-        .count();
+    let mut history = empty_section(target.history.len());
+    history.in_export = export_hist.len();
+    let mut behind = Vec::new();
+    // Walk the export in order so item lists come out oldest-first
+    for item in &history_export.items {
+        if !std::ptr::eq(export_hist[item.content_id.as_str()], item) {
+            continue;
+        }
+        let entry = DiffItem {
+            content_id: item.content_id.clone(),
+            title: item.title.clone(),
+            series_title: Some(item.series_title.clone()),
+            content_type: Some(episode_type(&item.parent_type).to_string()),
+            list: None,
+        };
+        match target.history.get(&item.content_id) {
+            Some(existing) if existing.is_behind(item) => behind.push(entry),
+            Some(_) => history.already_there.push(entry),
+            None => history.missing.push(entry),
+        }
+    }
+    history.behind = Some(behind);
 
-    // Count crunchylist items, checking per-item presence on target
-    let export_list_count: usize = crunchylists_export
-        .lists
-        .iter()
-        .map(|l| l.items.len())
-        .sum();
-    let list_already: usize = crunchylists_export
-        .lists
-        .iter()
-        .flat_map(|l| {
-            let target_items = target.crunchylists.get(&l.name);
-            l.items
-                .iter()
-                .filter(move |item| target_items.is_some_and(|ids| ids.contains(&item.content_id)))
-        })
-        .count();
+    // Crunchylist items, checking per-item presence on target
+    let mut crunchylists = empty_section(target.crunchylists.values().map(|s| s.len()).sum());
+    for list in &crunchylists_export.lists {
+        let target_items = target.crunchylists.get(&list.name);
+        for item in &list.items {
+            let entry = DiffItem {
+                content_id: item.content_id.clone(),
+                title: item.title.clone(),
+                series_title: None,
+                content_type: None,
+                list: Some(list.name.clone()),
+            };
+            crunchylists.in_export += 1;
+            if target_items.is_some_and(|ids| ids.contains(&item.content_id)) {
+                crunchylists.already_there.push(entry);
+            } else {
+                crunchylists.missing.push(entry);
+            }
+        }
+    }
+
+    // Only identical ratings count as present; conflicting ones are resolved at import
+    let mut ratings = empty_section(target.ratings.len());
+    for item in &ratings_export.items {
+        let entry = DiffItem {
+            content_id: item.content_id.clone(),
+            title: item.title.clone(),
+            series_title: None,
+            content_type: Some(item.content_type.clone()),
+            list: None,
+        };
+        ratings.in_export += 1;
+        if target.ratings.get(&item.content_id) == Some(&item.rating) {
+            ratings.already_there.push(entry);
+        } else {
+            ratings.missing.push(entry);
+        }
+    }
 
-    let ratings_count = ratings_export.items.len();
+    DiffResult {
+        watchlist,
+        favourites,
+        history,
+        crunchylists,
+        ratings,
+        selection: Selection::default(),
+    }
+}
 
-    Ok(DiffResult {
-        watchlist: DiffCounts {
-            in_export: export_wl_ids.len(),
-            on_target: target.watchlist_ids.len(),
-            missing: export_wl_ids.len() - wl_already,
-            already_there: wl_already,
-        },
-        history: DiffCounts {
-            in_export: export_hist_ids.len(),
-            on_target: target.history_ids.len(),
-            missing: export_hist_ids.len() - hist_already,
-            already_there: hist_already,
-        },
-        crunchylists: DiffCounts {
-            in_export: export_list_count,
-            on_target: target.crunchylists.values().map(|s| s.len()).sum(),
-            missing: export_list_count - list_already,
-            already_there: list_already,
-        },
-        ratings: DiffCounts {
-            in_export: ratings_count,
-            on_target: 0,
-            missing: ratings_count,
-            already_there: 0,
-        },
-    })
+fn empty_section(on_target: usize) -> DiffSection {
+    DiffSection {
+        in_export: 0,
+        on_target,
+        missing: Vec::new(),
+        already_there: Vec::new(),
+        behind: None,
+    }
+}
+
+/// History entries are episodes of a series or movies of a movie listing.
+fn episode_type(parent_type: &str) -> &str {
+    match parent_type {
+        "series" => "episode",
+        "movie_listing" => "movie",
+        other => other,
+    }
 }
 
 fn print_diff_table(result: &DiffResult) {
     println!();
     println!(
-        "  {:<14} {:>10} {:>10} {:>10} {:>14}",
-        "Data Type", "In Export", "On Target", "Missing", "Already There"
+        "  {:<14} {:>10} {:>10} {:>10} {:>14} {:>17}",
+        "Data Type", "In Export", "On Target", "Missing", "Already There", "Behind on Target"
     );
-    println!("  {}", "─".repeat(62));
-
-    let rows = [
-        ("Watchlist", &result.watchlist),
-        ("History", &result.history),
-        ("Crunchylists", &result.crunchylists),
-        ("Ratings", &result.ratings),
-    ];
+    println!("  {}", "─".repeat(80));
 
-    for (name, counts) in rows {
+    for (name, section) in result.sections() {
+        let counts = section.counts();
+        let behind = counts
+            .behind
+            .map_or_else(|| "-".to_string(), |n| n.to_string());
         println!(
-            "  {:<14} {:>10} {:>10} {:>10} {:>14}",
-            name, counts.in_export, counts.on_target, counts.missing, counts.already_there
+            "  {:<14} {:>10} {:>10} {:>10} {:>14} {:>17}",
+            name, counts.in_export, counts.on_target, counts.missing, counts.already_there, behind
         );
     }
     println!();
 }
+
+/// List what an import would write: missing items, and history the target is behind on.
+fn print_diff_details(result: &DiffResult) {
+    for (name, section) in result.sections() {
+        let behind = section.behind.as_deref().unwrap_or_default();
+        if section.missing.is_empty() && behind.is_empty() {
+            continue;
+        }
+        println!("  {}", name);
+        for item in &section.missing {
+            println!("    + {}", describe(item));
+        }
+        for item in behind {
+            println!("    ~ {} (behind)", describe(item));
+        }
+        println!();
+    }
+}
+
+fn describe(item: &DiffItem) -> String {
+    match (&item.series_title, &item.list) {
+        (Some(series), _) => format!("{} - {} [{}]", series, item.title, item.content_id),
+        (_, Some(list)) => format!("{} -> {} [{}]", list, item.title, item.content_id),
+        _ => format!("{} [{}]", item.title, item.content_id),
+    }
+}
+
+fn print_diff_json(result: &DiffResult, details: bool) -> Result<()> {
+    let sections: serde_json::Map<String, serde_json::Value> = result
+        .sections()
+        .into_iter()
+        .map(|(name, section)| {
+            let value = if details {
+                serde_json::to_value(section)?
+            } else {
+                serde_json::to_value(section.counts())?
+            };
+            Ok((name.to_lowercase(), value))
+        })
+        .collect::<Result<_>>()?;
+    let json = serde_json::to_string_pretty(&sections)?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Counts per data type, or with `details` one row per item and its status.
+fn print_diff_csv(result: &DiffResult, details: bool) -> Result<()> {
+    let mut out = csv::Writer::from_writer(std::io::stdout());
+    if details {
+        out.write_record([
+            "data_type",
+            "status",
+            "content_id",
+            "title",
+            "series_title",
+            "content_type",
+            "list",
+        ])?;
+        for (name, section) in result.sections() {
+            let buckets = [
+                ("missing", section.missing.as_slice()),
+                ("behind", section.behind.as_deref().unwrap_or_default()),
+                ("already_there", section.already_there.as_slice()),
+            ];
+            for (status, items) in buckets {
+                for item in items {
+                    out.write_record([
+                        name.to_lowercase().as_str(),
+                        status,
+                        &item.content_id,
+                        &item.title,
+                        item.series_title.as_deref().unwrap_or_default(),
+                        item.content_type.as_deref().unwrap_or_default(),
+                        item.list.as_deref().unwrap_or_default(),
+                    ])?;
+                }
+            }
+        }
+    } else {
+        out.write_record([
+            "data_type",
+            "in_export",
+            "on_target",
+            "missing",
+            "already_there",
+            "behind",
+        ])?;
+        for (name, section) in result.sections() {
+            let c = section.counts();
+            out.write_record([
+                name.to_lowercase(),
+                c.in_export.to_string(),
+                c.on_target.to_string(),
+                c.missing.to_string(),
+                c.already_there.to_string(),
+                c.behind.map(|n| n.to_string()).unwrap_or_default(),
+            ])?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{history_item, metadata};
+    use crate::import::PlayheadState;
+    use crate::models::WatchlistItem;
+
+    #[test]
+    fn diff_keeps_items_per_bucket() {
+        let watchlist = WatchlistExport {
+            metadata: metadata(0),
+            items: vec![WatchlistItem {
+                content_id: "S1".to_string(),
+                title: "Show".to_string(),
+                slug: "show".to_string(),
+                content_type: "series".to_string(),
+                is_favourite: true,
+                fully_watched: false,
+            }],
+        };
+        let history = WatchHistoryExport {
+            metadata: metadata(0),
+            items: vec![
+                history_item("E1", 100, false),
+                history_item("E2", 100, false),
+                history_item("E3", 100, false),
+                // Repeated entry: the later one is what counts
+                history_item("E1", 900, false),
+            ],
+        };
+        let crunchylists = CrunchylistsExport {
+            metadata: metadata(0),
+            lists: vec![],
+        };
+        let ratings = RatingsExport {
+            metadata: metadata(0),
+            items: vec![],
+        };
+        let mut target = TargetState::default();
+        target.watchlist_ids.insert("S1".to_string());
+        for id in ["E1", "E2"] {
+            target.history.insert(
+                id.to_string(),
+                PlayheadState {
+                    playhead: 500,
+                    fully_watched: false,
+                },
+            );
+        }
+
+        let result = diff_against(&watchlist, &history, &crunchylists, &ratings, &target);
+
+        assert_eq!(result.watchlist.already_there.len(), 1);
+        assert_eq!(result.favourites.missing[0].title, "Show");
+        assert_eq!(result.history.in_export, 3);
+        assert_eq!(result.history.missing[0].content_id, "E3");
+        assert_eq!(result.history.already_there[0].content_id, "E2");
+        let behind = result.history.behind.as_ref().unwrap();
+        assert_eq!(behind[0].content_id, "E1");
+        assert_eq!(behind[0].content_type.as_deref(), Some("episode"));
+        assert_eq!(result.history.counts().behind, Some(1));
+    }
+}
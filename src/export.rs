@@ -1,99 +1,168 @@
+use crate::backend::Backend;
+use crate::crypt::{self, Key};
+use crate::csv_format::{self, Rows};
+use crate::limiter::RateLimiter;
 use crate::models::{
-    CrunchylistData, CrunchylistItem, CrunchylistsExport, ExportMetadata, RatingItem,
-    RatingsExport, WatchHistoryExport, WatchHistoryItem, WatchlistExport, WatchlistItem,
+    self, CrunchylistData, CrunchylistsExport, ExportMetadata, RatingItem, RatingsExport,
+    SCHEMA_VERSION, WatchHistoryExport, WatchHistoryItem, WatchlistExport, WatchlistItem,
 };
-use crate::ui::{self, DataType, ProgressReporter, ProgressUpdate};
+use crate::selection::{ExportFile, Selection};
+use crate::ui::{self, DashboardHandle, DataType, ProgressReporter, ProgressUpdate};
 use anyhow::{Context, Result};
 use chrono::Utc;
-use crunchyroll_rs::list::WatchlistOptions;
-use crunchyroll_rs::{Crunchyroll, MediaCollection};
-use futures_util::StreamExt;
+use futures_util::{StreamExt, future, stream};
 use std::collections::HashSet;
 use std::path::Path;
-use tokio::sync::Semaphore;
 
-pub async fn run(crunchy: &Crunchyroll, output_dir: &Path) -> Result<()> {
+/// How export files are written. Either is read back by every command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    /// One spreadsheet-friendly CSV per data type, without the metadata
+    Csv,
+}
+
+/// Export the selected data types to `output_dir` in `format`, encrypting each file with
+/// `key` if given.
+pub async fn run<B: Backend>(
+    crunchy: &B,
+    output_dir: &Path,
+    selection: Selection,
+    format: ExportFormat,
+    key: Option<&Key>,
+    display: ui::Display,
+) -> Result<()> {
     std::fs::create_dir_all(output_dir)?;
 
     let profile_name = crunchy.profile_id().await;
-    let (reporter, dashboard) = ui::start_dashboard("Export", "", &profile_name);
-
-    let watchlist = export_watchlist(crunchy, &profile_name, &reporter).await?;
-    write_atomic(output_dir, "watchlist.json", &watchlist)?;
-    reporter.log_success(&format!("Watchlist: {} items", watchlist.items.len()));
-
-    let history = export_history(crunchy, &profile_name, &reporter).await?;
-    write_atomic(output_dir, "watch_history.json", &history)?;
-    reporter.log_success(&format!("Watch history: {} items", history.items.len()));
-
-    let crunchylists = export_crunchylists(crunchy, &profile_name, &reporter).await?;
-    write_atomic(output_dir, "crunchylists.json", &crunchylists)?;
-    let list_items: usize = crunchylists.lists.iter().map(|l| l.items.len()).sum();
-    reporter.log_success(&format!(
-        "Crunchylists: {} lists, {} items",
-        crunchylists.lists.len(),
-        list_items
-    ));
-
-    let ratings = export_ratings(
-        crunchy,
+    let (reporter, dashboard) = ui::start_dashboard(
+        display,
+        "Export",
+        "",
         &profile_name,
-        &watchlist.items,
-        &history.items,
-        &reporter,
-    )
-    .await?;
-    write_atomic(output_dir, "ratings.json", &ratings)?;
-    reporter.log_success(&format!("Ratings: {} rated items", ratings.items.len()));
+        selection.phases(),
+        crunchy.limiter().clone(),
+    );
+    // Ratings are looked up for everything in the watchlist and history, so those are
+    // fetched for ratings even when they aren't written out themselves
+    let fetch = |file| selection.contains(file) || selection.contains(ExportFile::Ratings);
+
+    let mut watchlist_items = Vec::new();
+    if fetch(ExportFile::Watchlist) {
+        let watchlist = export_watchlist(crunchy, &profile_name, &reporter).await?;
+        if selection.contains(ExportFile::Watchlist) {
+            write_export(output_dir, ExportFile::Watchlist, &watchlist, format, key)?;
+            reporter.log_success(&format!("Watchlist: {} items", watchlist.items.len()));
+        }
+        if reporter.is_cancelled() {
+            return cancelled(reporter, dashboard, output_dir, crunchy.limiter());
+        }
+        watchlist_items = watchlist.items;
+    }
+
+    let mut history_items = Vec::new();
+    if fetch(ExportFile::History) {
+        let history = export_history(crunchy, &profile_name, &reporter).await?;
+        if selection.contains(ExportFile::History) {
+            write_export(output_dir, ExportFile::History, &history, format, key)?;
+            reporter.log_success(&format!("Watch history: {} items", history.items.len()));
+        }
+        if reporter.is_cancelled() {
+            return cancelled(reporter, dashboard, output_dir, crunchy.limiter());
+        }
+        history_items = history.items;
+    }
+
+    if selection.contains(ExportFile::Crunchylists) {
+        let crunchylists = export_crunchylists(crunchy, &profile_name, &reporter).await?;
+        write_export(
+            output_dir,
+            ExportFile::Crunchylists,
+            &crunchylists,
+            format,
+            key,
+        )?;
+        let list_items: usize = crunchylists.lists.iter().map(|l| l.items.len()).sum();
+        reporter.log_success(&format!(
+            "Crunchylists: {} lists, {} items",
+            crunchylists.lists.len(),
+            list_items
+        ));
+        if reporter.is_cancelled() {
+            return cancelled(reporter, dashboard, output_dir, crunchy.limiter());
+        }
+    }
+
+    if selection.contains(ExportFile::Ratings) {
+        let ratings = export_ratings(
+            crunchy,
+            &profile_name,
+            &watchlist_items,
+            &history_items,
+            &reporter,
+        )
+        .await?;
+        write_export(output_dir, ExportFile::Ratings, &ratings, format, key)?;
+        reporter.log_success(&format!("Ratings: {} rated items", ratings.items.len()));
+        if reporter.is_cancelled() {
+            return cancelled(reporter, dashboard, output_dir, crunchy.limiter());
+        }
+    }
 
     reporter.done();
     dashboard.wait();
 
-    if !ui::is_tty() {
+    if !reporter.is_tty() {
         println!("Export complete -> {}", output_dir.display());
     }
     Ok(())
 }
 
-async fn export_watchlist(
-    crunchy: &Crunchyroll,
+/// Wind down after a cancelled export. The phase that was interrupted has already been
+/// written with `partial` set in its metadata; later phases are not written at all.
+/// A tripped circuit breaker stops the export the same way but fails with its own error.
+fn cancelled(
+    reporter: ProgressReporter,
+    dashboard: DashboardHandle,
+    output_dir: &Path,
+    limiter: &RateLimiter,
+) -> Result<()> {
+    reporter.done();
+    dashboard.wait();
+    println!(
+        "Export cancelled -- partial files written to {}",
+        output_dir.display()
+    );
+    limiter.check()?;
+    Err(ui::Cancelled.into())
+}
+
+async fn export_watchlist<B: Backend>(
+    crunchy: &B,
     profile_name: &str,
     reporter: &ProgressReporter,
 ) -> Result<WatchlistExport> {
-    let entries = crunchy
-        .watchlist(WatchlistOptions::default())
-        .await
-        .context("Failed to fetch watchlist")?;
-
-    let items: Vec<WatchlistItem> = entries
-        .iter()
-        .filter_map(|entry| {
-            let (content_id, title, slug, content_type) = extract_series_info(&entry.panel)?;
-            Some(WatchlistItem {
-                content_id,
-                title,
-                slug,
-                content_type,
-                is_favourite: entry.is_favorite,
-                fully_watched: entry.fully_watched,
-            })
-        })
-        .collect();
+    let items = crunchy.watchlist().await?;
 
     reporter.progress(export_progress(DataType::Watchlist, items.len()));
+    let favourites = items.iter().filter(|i| i.is_favourite).count();
+    reporter.progress(export_progress(DataType::Favourites, favourites));
 
     Ok(WatchlistExport {
         metadata: ExportMetadata {
+            schema_version: SCHEMA_VERSION,
             profile_name: profile_name.to_string(),
             exported_at: Utc::now(),
             total_count: items.len(),
+            partial: false,
         },
         items,
     })
 }
 
-async fn export_history(
-    crunchy: &Crunchyroll,
+async fn export_history<B: Backend>(
+    crunchy: &B,
     profile_name: &str,
     reporter: &ProgressReporter,
 ) -> Result<WatchHistoryExport> {
@@ -101,29 +170,12 @@ async fn export_history(
     let mut items = Vec::new();
     let mut failed = 0;
 
-    while let Some(result) = stream.next().await {
+    while !reporter.is_cancelled()
+        && let Some(result) = stream.next().await
+    {
         match result {
-            Ok(entry) => {
-                let (title, series_title, partial) = match &entry.panel {
-                    Some(panel) => {
-                        let title = panel_title(panel);
-                        let series_title = panel_series_title(panel);
-                        (title, series_title, false)
-                    }
-                    None => (String::new(), String::new(), true),
-                };
-
-                items.push(WatchHistoryItem {
-                    content_id: entry.id.clone(),
-                    parent_id: entry.parent_id.clone(),
-                    parent_type: entry.parent_type.clone(),
-                    title,
-                    series_title,
-                    date_played: entry.date_played,
-                    playhead: entry.playhead,
-                    fully_watched: entry.fully_watched,
-                    partial,
-                });
+            Ok(item) => {
+                items.push(item);
 
                 if items.len() % 50 == 0 {
                     reporter.progress(ProgressUpdate {
@@ -159,44 +211,30 @@ async fn export_history(
 
     Ok(WatchHistoryExport {
         metadata: ExportMetadata {
+            schema_version: SCHEMA_VERSION,
             profile_name: profile_name.to_string(),
             exported_at: Utc::now(),
             total_count: items.len(),
+            partial: reporter.is_cancelled(),
         },
         items,
     })
 }
 
-async fn export_crunchylists(
-    crunchy: &Crunchyroll,
+async fn export_crunchylists<B: Backend>(
+    crunchy: &B,
     profile_name: &str,
     reporter: &ProgressReporter,
 ) -> Result<CrunchylistsExport> {
-    let lists_meta = crunchy
-        .crunchylists()
-        .await
-        .context("Failed to fetch crunchylists")?;
+    let names = crunchy.crunchylist_names().await?;
 
     let mut lists = Vec::new();
-    for preview in &lists_meta.items {
-        let full_list = preview
-            .crunchylist()
-            .await
-            .with_context(|| format!("Failed to fetch crunchylist '{}'", preview.title))?;
-
-        let items: Vec<CrunchylistItem> = full_list
-            .items
-            .iter()
-            .filter_map(|entry| {
-                let (content_id, title, _, _) = extract_series_info(&entry.panel)?;
-                Some(CrunchylistItem { content_id, title })
-            })
-            .collect();
-
-        lists.push(CrunchylistData {
-            name: preview.title.clone(),
-            items,
-        });
+    for name in names {
+        if reporter.is_cancelled() {
+            break;
+        }
+        let items = crunchy.crunchylist(&name).await?;
+        lists.push(CrunchylistData { name, items });
     }
 
     let total: usize = lists.iter().map(|l| l.items.len()).sum();
@@ -204,16 +242,18 @@ async fn export_crunchylists(
 
     Ok(CrunchylistsExport {
         metadata: ExportMetadata {
+            schema_version: SCHEMA_VERSION,
             profile_name: profile_name.to_string(),
             exported_at: Utc::now(),
             total_count: total,
+            partial: reporter.is_cancelled(),
         },
         lists,
     })
 }
 
-async fn export_ratings(
-    crunchy: &Crunchyroll,
+async fn export_ratings<B: Backend>(
+    crunchy: &B,
     profile_name: &str,
     watchlist: &[WatchlistItem],
     history: &[WatchHistoryItem],
@@ -244,31 +284,21 @@ async fn export_ratings(
     }
 
     let total = to_check.len();
-    let semaphore = std::sync::Arc::new(Semaphore::new(5));
-    let crunchy_clone = crunchy.clone();
-    let mut handles = Vec::new();
-
-    for (content_id, content_type, title) in to_check {
-        let sem = semaphore.clone();
-        let cr = crunchy_clone.clone();
-
-        handles.push(tokio::spawn(async move {
-            let Ok(_permit) = sem.acquire().await else {
-                return None;
-            };
-            fetch_rating(&cr, &content_id, &content_type, &title).await
-        }));
-    }
+    // Lookups still queued are skipped once cancelled. Results keep the watchlist-then-
+    // history order so the same account always exports the same file.
+    let cancel = reporter.cancellation();
+    let mut lookups = stream::iter(&to_check)
+        .take_while(|_| future::ready(!cancel.is_cancelled()))
+        .map(|(content_id, content_type, title)| {
+            fetch_rating(crunchy, content_id, content_type, title)
+        })
+        .buffered(crunchy.limiter().concurrency());
 
     let mut items = Vec::new();
     let mut checked = 0;
-    for handle in handles {
+    while let Some(rating) = lookups.next().await {
         checked += 1;
-        match handle.await {
-            Ok(Some(item)) => items.push(item),
-            Ok(None) => {}
-            Err(e) => reporter.log_error(&format!("Rating task failed: {}", e)),
-        }
+        items.extend(rating);
         if checked % 5 == 0 || checked == total {
             reporter.progress(ProgressUpdate {
                 data_type: DataType::Ratings,
@@ -284,98 +314,31 @@ async fn export_ratings(
 
     Ok(RatingsExport {
         metadata: ExportMetadata {
+            schema_version: SCHEMA_VERSION,
             profile_name: profile_name.to_string(),
             exported_at: Utc::now(),
             total_count: items.len(),
+            partial: reporter.is_cancelled(),
         },
         items,
     })
 }
 
-async fn fetch_rating(
-    crunchy: &Crunchyroll,
+async fn fetch_rating<B: Backend>(
+    crunchy: &B,
     content_id: &str,
     content_type: &str,
     title: &str,
 ) -> Option<RatingItem> {
-    let rating_result = match content_type {
-        "series" => {
-            let series: crunchyroll_rs::Series = match crunchy.media_from_id(content_id).await {
-                Ok(s) => s,
-                Err(_) => return None,
-            };
-            series.rating().await
-        }
-        "movie_listing" => {
-            let ml: crunchyroll_rs::MovieListing = match crunchy.media_from_id(content_id).await {
-                Ok(m) => m,
-                Err(_) => return None,
-            };
-            ml.rating().await
-        }
-        _ => return None,
-    };
-
-    match rating_result {
-        Ok(rating) => rating.rating.map(|stars| RatingItem {
-            content_id: content_id.to_string(),
-            content_type: content_type.to_string(),
-            title: title.to_string(),
-            rating: format!("{:?}", stars),
-        }),
-        Err(_) => None,
-    }
-}
-
-/// Extract series/movie_listing ID, title, slug, and content_type from a MediaCollection panel.
-pub fn extract_series_info(panel: &MediaCollection) -> Option<(String, String, String, String)> {
-    match panel {
-        MediaCollection::Episode(ep) => Some((
-            ep.series_id.clone(),
-            ep.series_title.clone(),
-            ep.series_slug_title.clone(),
-            "series".to_string(),
-        )),
-        MediaCollection::Movie(mv) => Some((
-            mv.movie_listing_id.clone(),
-            mv.movie_listing_title.clone(),
-            mv.movie_listing_slug_title.clone(),
-            "movie_listing".to_string(),
-        )),
-        MediaCollection::Series(s) => Some((
-            s.id.clone(),
-            s.title.clone(),
-            s.slug_title.clone(),
-            "series".to_string(),
-        )),
-        MediaCollection::MovieListing(ml) => Some((
-            ml.id.clone(),
-            ml.title.clone(),
-            ml.slug_title.clone(),
-            "movie_listing".to_string(),
-        )),
-        _ => None,
-    }
-}
-
-fn panel_title(panel: &MediaCollection) -> String {
-    match panel {
-        MediaCollection::Episode(ep) => ep.title.clone(),
-        MediaCollection::Movie(mv) => mv.title.clone(),
-        MediaCollection::Series(s) => s.title.clone(),
-        MediaCollection::MovieListing(ml) => ml.title.clone(),
-        _ => String::new(),
-    }
-}
-
-fn panel_series_title(panel: &MediaCollection) -> String {
-    match panel {
-        MediaCollection::Episode(ep) => ep.series_title.clone(),
-        MediaCollection::Movie(mv) => mv.movie_listing_title.clone(),
-        MediaCollection::Series(s) => s.title.clone(),
-        MediaCollection::MovieListing(ml) => ml.title.clone(),
-        _ => String::new(),
-    }
+    // Unrated, unknown, or failed lookups all count as "no rating": a backup should still
+    // get written. Import looks up the target's ratings with `import::fetch_ratings`.
+    let rating = crunchy.rating(content_id, content_type).await.ok()??;
+    Some(RatingItem {
+        content_id: content_id.to_string(),
+        content_type: content_type.to_string(),
+        title: title.to_string(),
+        rating,
+    })
 }
 
 /// Build a progress update for an export phase where all items are "added" (fetched).
@@ -391,12 +354,138 @@ fn export_progress(data_type: DataType, count: usize) -> ProgressUpdate {
     }
 }
 
-fn write_atomic<T: serde::Serialize>(dir: &Path, filename: &str, data: &T) -> Result<()> {
+/// `upgrade-export`: rewrite the export files in `dir` that an older version wrote, so
+/// they match the current schema instead of being upgraded on every read.
+pub fn upgrade(dir: &Path, selection: Selection) -> Result<()> {
+    let selection = selection.exported_in_or_err(dir)?;
+    for file in ExportFile::ALL
+        .into_iter()
+        .filter(|&f| selection.contains(f))
+    {
+        let from = match file {
+            ExportFile::Watchlist => rewrite::<WatchlistExport>(dir, file)?,
+            ExportFile::History => rewrite::<WatchHistoryExport>(dir, file)?,
+            ExportFile::Crunchylists => rewrite::<CrunchylistsExport>(dir, file)?,
+            ExportFile::Ratings => rewrite::<RatingsExport>(dir, file)?,
+        };
+        if from == SCHEMA_VERSION {
+            println!(
+                "  {:20} already at schema version {}",
+                file.file_name(),
+                from
+            );
+        } else {
+            println!(
+                "  {:20} schema version {} -> {}",
+                file.file_name(),
+                from,
+                SCHEMA_VERSION
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite one file at the current schema if it is older, returning the version it had.
+/// An encrypted file stays encrypted, with the same passphrase.
+fn rewrite<T: serde::Serialize + serde::de::DeserializeOwned>(
+    dir: &Path,
+    file: ExportFile,
+) -> Result<u32> {
+    let (data, version) = models::read_versioned::<T>(dir, file.file_name())?;
+    if version < SCHEMA_VERSION {
+        let key = if crypt::is_encrypted(&dir.join(file.file_name()))? {
+            Some(Key::new(&crypt::passphrase(false)?)?)
+        } else {
+            None
+        };
+        write_json(dir, file.file_name(), &data, key.as_ref())?;
+    }
+    Ok(version)
+}
+
+/// Write one export file in `format`, replacing the file in the other format if there is
+/// one. Only JSON can be encrypted.
+fn write_export<T: serde::Serialize + Rows>(
+    dir: &Path,
+    file: ExportFile,
+    data: &T,
+    format: ExportFormat,
+    key: Option<&Key>,
+) -> Result<()> {
+    let json = file.file_name();
+    let csv = csv_format::csv_name(json);
+    let stale = match format {
+        ExportFormat::Json => {
+            write_json(dir, json, data, key)?;
+            csv
+        }
+        ExportFormat::Csv => {
+            write_atomic(dir, &csv, &csv_format::to_csv(data)?)?;
+            json.to_string()
+        }
+    };
+    match std::fs::remove_file(dir.join(&stale)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Removing {}", dir.join(&stale).display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn write_json<T: serde::Serialize>(
+    dir: &Path,
+    filename: &str,
+    data: &T,
+    key: Option<&Key>,
+) -> Result<()> {
+    let json = serde_json::to_string_pretty(data)?;
+    let json = match key {
+        Some(key) => key.seal(json.as_bytes())?,
+        None => json,
+    };
+    write_atomic(dir, filename, json.as_bytes())
+}
+
+fn write_atomic(dir: &Path, filename: &str, contents: &[u8]) -> Result<()> {
     let target = dir.join(filename);
     let tmp = dir.join(format!(".{}.tmp", filename));
-    let json = serde_json::to_string_pretty(data)?;
-    std::fs::write(&tmp, &json).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::write(&tmp, contents).with_context(|| format!("Failed to write {}", tmp.display()))?;
     std::fs::rename(&tmp, &target)
         .with_context(|| format!("Failed to rename {} -> {}", tmp.display(), target.display()))?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrade_rewrites_only_old_files() {
+        let dir = std::env::temp_dir().join(format!("upgrade-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let v0 = r#"{
+            "metadata": {"profile_name": "Main", "exported_at": "2025-06-01T00:00:00Z", "total_count": 1},
+            "items": [{"content_id": "S1", "content_type": "series", "title": "Show", "rating": "FiveStars"}]
+        }"#;
+        std::fs::write(dir.join("ratings.json"), v0).unwrap();
+        let mut current = WatchlistExport::default();
+        current.metadata.schema_version = SCHEMA_VERSION;
+        write_json(&dir, "watchlist.json", &current, None).unwrap();
+        let before = std::fs::read_to_string(dir.join("watchlist.json")).unwrap();
+
+        upgrade(&dir, Selection::default()).unwrap();
+
+        let (ratings, version) =
+            models::read_versioned::<RatingsExport>(&dir, "ratings.json").unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        assert_eq!(ratings.metadata.schema_version, SCHEMA_VERSION);
+        assert_eq!(ratings.items[0].rating, "FiveStars");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("watchlist.json")).unwrap(),
+            before
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+use crate::crypt;
+use crate::import;
+use crate::journal;
+use crate::limiter::CircuitOpen;
+use crate::ui::DataType;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const FAILURES_FILE: &str = "failures.json";
+
+/// Rough cause of a failed write, so the failures file says whether a retry is likely
+/// to help.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    RateLimited,
+    CloudflareBlock,
+    /// 5xx or timeout
+    ServerError,
+    NotFound,
+    /// Any other 4xx
+    Rejected,
+    Other,
+}
+
+pub fn classify(e: &anyhow::Error) -> ErrorKind {
+    if e.is::<CircuitOpen>() || import::is_cloudflare_block(e) {
+        return ErrorKind::CloudflareBlock;
+    }
+    if let Some(crunchyroll_rs::error::Error::Request {
+        status: Some(code), ..
+    }) = e.downcast_ref::<crunchyroll_rs::error::Error>()
+    {
+        return match code.as_u16() {
+            429 => ErrorKind::RateLimited,
+            404 => ErrorKind::NotFound,
+            500..=599 => ErrorKind::ServerError,
+            400..=499 => ErrorKind::Rejected,
+            _ => ErrorKind::Other,
+        };
+    }
+    // Raw endpoints put the status in the message
+    let msg = format!("{:#}", e);
+    if msg.contains("429") {
+        ErrorKind::RateLimited
+    } else if ["500", "502", "503", "504", "timeout"]
+        .iter()
+        .any(|s| msg.contains(s))
+    {
+        ErrorKind::ServerError
+    } else if msg.contains("404") || msg.contains("not found") {
+        ErrorKind::NotFound
+    } else if ["400", "401", "403", "422"].iter().any(|s| msg.contains(s)) {
+        ErrorKind::Rejected
+    } else {
+        ErrorKind::Other
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Failure {
+    pub data_type: DataType,
+    pub content_id: String,
+    /// Crunchylist the item was being added to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub list: Option<String>,
+    pub title: String,
+    pub kind: ErrorKind,
+    pub error: String,
+    pub at: DateTime<Utc>,
+}
+
+impl Failure {
+    pub fn new(data_type: DataType, content_id: &str, title: &str, error: &anyhow::Error) -> Self {
+        Self {
+            data_type,
+            content_id: content_id.to_string(),
+            list: None,
+            title: title.to_string(),
+            kind: classify(error),
+            error: format!("{:#}", error),
+            at: Utc::now(),
+        }
+    }
+
+    pub fn in_list(mut self, list_name: &str) -> Self {
+        self.list = Some(list_name.to_string());
+        self
+    }
+
+    /// Same key the journal uses for this item.
+    pub fn key(&self) -> String {
+        match &self.list {
+            Some(list) => journal::list_item_key(list, &self.content_id),
+            None => self.content_id.clone(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct FailuresFile {
+    items: Vec<Failure>,
+}
+
+/// Items whose last write failed, kept in `failures.json` next to the export. Rewritten
+/// whenever an item fails or a later attempt settles it, so `retry-failed` always sees
+/// what is still outstanding.
+pub struct Failures {
+    path: PathBuf,
+    items: Mutex<Vec<Failure>>,
+}
+
+impl Failures {
+    /// Start with no failures, discarding any previous file in `dir`.
+    pub fn create(dir: &Path) -> Result<Self> {
+        let path = dir.join(FAILURES_FILE);
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Removing {}", path.display()));
+            }
+            _ => {}
+        }
+        Ok(Self {
+            path,
+            items: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Pick up the failures file in `dir`, if there is one.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(FAILURES_FILE);
+        let items = match std::fs::read_to_string(&path) {
+            Ok(content) => {
+                serde_json::from_str::<FailuresFile>(&crypt::open_if_sealed(&content)?)
+                    .with_context(|| format!("Parsing {}", path.display()))?
+                    .items
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+        };
+        Ok(Self {
+            path,
+            items: Mutex::new(items),
+        })
+    }
+
+    pub fn items(&self) -> Vec<Failure> {
+        self.items.lock().unwrap().clone()
+    }
+
+    /// Record a failure, replacing any earlier one for the same item.
+    pub fn add(&self, failure: Failure) -> Result<()> {
+        let mut items = self.items.lock().unwrap();
+        let key = failure.key();
+        items.retain(|f| f.data_type != failure.data_type || f.key() != key);
+        items.push(failure);
+        self.save(&items)
+    }
+
+    /// Drop an item that has since gone through.
+    pub fn resolve(&self, data_type: DataType, key: &str) -> Result<()> {
+        let mut items = self.items.lock().unwrap();
+        let before = items.len();
+        items.retain(|f| f.data_type != data_type || f.key() != key);
+        if items.len() == before {
+            return Ok(());
+        }
+        self.save(&items)
+    }
+
+    fn save(&self, items: &[Failure]) -> Result<()> {
+        let file = FailuresFile {
+            items: items.to_vec(),
+        };
+        let tmp = self.path.with_extension("json.tmp");
+        let json = crypt::seal_if_encrypted(serde_json::to_string_pretty(&file)?)?;
+        std::fs::write(&tmp, json).with_context(|| format!("Writing {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Writing {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_by_status() {
+        let kind = |msg: &str| classify(&anyhow::anyhow!(msg.to_string()));
+        assert_eq!(
+            kind("set_playhead returned 503 for E1"),
+            ErrorKind::ServerError
+        );
+        assert_eq!(kind("429 Too Many Requests"), ErrorKind::RateLimited);
+        assert_eq!(kind("404 Not Found: no content S9"), ErrorKind::NotFound);
+        assert_eq!(
+            kind("400 Bad Request: cannot write S2"),
+            ErrorKind::Rejected
+        );
+        assert_eq!(kind("something else"), ErrorKind::Other);
+        assert_eq!(
+            classify(&anyhow::Error::new(CircuitOpen)),
+            ErrorKind::CloudflareBlock
+        );
+    }
+
+    #[test]
+    fn failures_are_replaced_and_resolved_on_disk() {
+        let dir = std::env::temp_dir().join(format!("failures-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let failures = Failures::create(&dir).unwrap();
+        let err = anyhow::anyhow!("503 Service Unavailable");
+        failures
+            .add(Failure::new(DataType::Watchlist, "S1", "Series 1", &err))
+            .unwrap();
+        failures
+            .add(Failure::new(DataType::Crunchylists, "S1", "Series 1", &err).in_list("Later"))
+            .unwrap();
+        let again = anyhow::anyhow!("400 Bad Request");
+        failures
+            .add(Failure::new(DataType::Watchlist, "S1", "Series 1", &again))
+            .unwrap();
+
+        let loaded = Failures::load(&dir).unwrap().items();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].key(), "Later/S1");
+        assert_eq!(loaded[1].kind, ErrorKind::Rejected);
+
+        failures
+            .resolve(DataType::Crunchylists, "Later/S1")
+            .unwrap();
+        let loaded = Failures::load(&dir).unwrap().items();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].data_type, DataType::Watchlist);
+
+        Failures::create(&dir).unwrap();
+        assert!(Failures::load(&dir).unwrap().items().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
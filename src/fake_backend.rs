@@ -0,0 +1,759 @@
+//! In-memory stand-in for a Crunchyroll profile, so export, diff, import and verify can be
+//! exercised end-to-end without network.
+
+use crate::backend::{Backend, HistoryPage};
+use crate::limiter::RateLimiter;
+use crate::models::{CrunchylistData, CrunchylistItem, WatchHistoryItem, WatchlistItem};
+use anyhow::{Result, anyhow, bail};
+use chrono::Utc;
+use futures_util::StreamExt;
+use futures_util::stream::{self, BoxStream};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Default, Clone)]
+pub struct FakeState {
+    pub watchlist: Vec<WatchlistItem>,
+    /// In any order; served newest first
+    pub history: Vec<WatchHistoryItem>,
+    pub crunchylists: Vec<CrunchylistData>,
+    pub ratings: HashMap<String, String>,
+}
+
+pub struct FakeBackend {
+    profile_id: String,
+    state: Mutex<FakeState>,
+    /// Titles and content types of series and movies that can be written
+    titles: HashMap<String, (String, String)>,
+    /// Episodes that can be written to history, as they'd first appear there
+    episodes: HashMap<String, WatchHistoryItem>,
+    /// Writes to these ids fail as if the API rejected them
+    failing: HashSet<String>,
+    /// Writes to these ids succeed but never show up, like playheads the API drops
+    dropped: HashSet<String>,
+    /// History entries that count towards the total but are never served
+    hidden: HashSet<String>,
+    /// Never waited on; fake requests are free
+    limiter: Arc<RateLimiter>,
+}
+
+impl FakeBackend {
+    /// A profile holding `state`. Everything in it is also known content, so it can be
+    /// written back.
+    pub fn new(profile_id: &str, state: FakeState) -> Self {
+        let mut backend = Self {
+            profile_id: profile_id.to_string(),
+            state: Mutex::new(FakeState::default()),
+            titles: HashMap::new(),
+            episodes: HashMap::new(),
+            failing: HashSet::new(),
+            dropped: HashSet::new(),
+            hidden: HashSet::new(),
+            limiter: Arc::default(),
+        };
+        backend.learn(&state);
+        *backend.state.get_mut().unwrap() = state;
+        backend
+    }
+
+    /// Make the content of `other` known here, the way the catalog is shared across
+    /// accounts.
+    pub fn with_catalog_of(mut self, other: &FakeBackend) -> Self {
+        self.titles.extend(other.titles.clone());
+        self.episodes.extend(other.episodes.clone());
+        self
+    }
+
+    pub fn fail_writes_for(mut self, content_id: &str) -> Self {
+        self.failing.insert(content_id.to_string());
+        self
+    }
+
+    pub fn drop_writes_for(mut self, content_id: &str) -> Self {
+        self.dropped.insert(content_id.to_string());
+        self
+    }
+
+    pub fn hide_history_entry(mut self, content_id: &str) -> Self {
+        self.hidden.insert(content_id.to_string());
+        self
+    }
+
+    pub fn state(&self) -> FakeState {
+        self.state.lock().unwrap().clone()
+    }
+
+    fn learn(&mut self, state: &FakeState) {
+        for item in &state.watchlist {
+            self.titles.insert(
+                item.content_id.clone(),
+                (item.title.clone(), item.content_type.clone()),
+            );
+        }
+        for item in &state.history {
+            self.titles.insert(
+                item.parent_id.clone(),
+                (item.series_title.clone(), item.parent_type.clone()),
+            );
+            self.episodes.insert(item.content_id.clone(), item.clone());
+        }
+        for item in state.crunchylists.iter().flat_map(|l| &l.items) {
+            self.titles
+                .entry(item.content_id.clone())
+                .or_insert_with(|| (item.title.clone(), "series".to_string()));
+        }
+    }
+
+    /// Apply `write` unless the id is set up to fail or be dropped.
+    fn write(
+        &self,
+        content_id: &str,
+        write: impl FnOnce(&mut FakeState) -> Result<()>,
+    ) -> Result<()> {
+        if self.failing.contains(content_id) {
+            bail!("400 Bad Request: cannot write {}", content_id);
+        }
+        if self.dropped.contains(content_id) {
+            return Ok(());
+        }
+        write(&mut self.state.lock().unwrap())
+    }
+
+    fn title(&self, content_id: &str) -> Result<&(String, String)> {
+        self.titles
+            .get(content_id)
+            .ok_or_else(|| anyhow!("404 Not Found: no content {}", content_id))
+    }
+
+    fn update_history(&self, content_id: &str, playhead: u32, fully_watched: bool) -> Result<()> {
+        let template = self
+            .episodes
+            .get(content_id)
+            .ok_or_else(|| anyhow!("404 Not Found: no episode {}", content_id))?;
+        self.write(content_id, |state| {
+            let entry = match state
+                .history
+                .iter_mut()
+                .find(|i| i.content_id == content_id)
+            {
+                Some(entry) => entry,
+                None => {
+                    state.history.push(template.clone());
+                    state.history.last_mut().unwrap()
+                }
+            };
+            entry.playhead = playhead;
+            entry.fully_watched = fully_watched;
+            entry.date_played = Utc::now();
+            Ok(())
+        })
+    }
+}
+
+impl Backend for FakeBackend {
+    async fn profile_id(&self) -> String {
+        self.profile_id.clone()
+    }
+
+    fn limiter(&self) -> &Arc<RateLimiter> {
+        &self.limiter
+    }
+
+    async fn watchlist(&self) -> Result<Vec<WatchlistItem>> {
+        Ok(self.state.lock().unwrap().watchlist.clone())
+    }
+
+    fn watch_history(&self) -> BoxStream<'_, Result<WatchHistoryItem>> {
+        let mut history = self.state.lock().unwrap().history.clone();
+        history.sort_by_key(|i| std::cmp::Reverse(i.date_played));
+        stream::iter(history.into_iter().map(Ok)).boxed()
+    }
+
+    async fn watch_history_page(&self, page: u32, page_size: u32) -> Result<HistoryPage> {
+        let mut history = self.state.lock().unwrap().history.clone();
+        history.sort_by_key(|i| std::cmp::Reverse(i.date_played));
+        let total = history.len() as u32;
+        let start = ((page.max(1) - 1) * page_size) as usize;
+        let items = history
+            .into_iter()
+            .skip(start)
+            .take(page_size as usize)
+            .filter(|i| !self.hidden.contains(&i.content_id))
+            .collect();
+        Ok(HistoryPage { items, total })
+    }
+
+    async fn crunchylist_names(&self) -> Result<Vec<String>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.crunchylists.iter().map(|l| l.name.clone()).collect())
+    }
+
+    async fn crunchylist(&self, name: &str) -> Result<Vec<CrunchylistItem>> {
+        let state = self.state.lock().unwrap();
+        let list = state.crunchylists.iter().find(|l| l.name == name);
+        Ok(list
+            .ok_or_else(|| anyhow!("Crunchylist '{}' not found", name))?
+            .items
+            .clone())
+    }
+
+    async fn rating(&self, content_id: &str, _content_type: &str) -> Result<Option<String>> {
+        Ok(self.state.lock().unwrap().ratings.get(content_id).cloned())
+    }
+
+    async fn add_to_watchlist(&self, content_id: &str, _content_type: &str) -> Result<()> {
+        let (title, content_type) = self.title(content_id)?.clone();
+        self.write(content_id, |state| {
+            if !state.watchlist.iter().any(|i| i.content_id == content_id) {
+                state.watchlist.push(WatchlistItem {
+                    content_id: content_id.to_string(),
+                    title,
+                    slug: String::new(),
+                    content_type,
+                    is_favourite: false,
+                    fully_watched: false,
+                });
+            }
+            Ok(())
+        })
+    }
+
+    async fn mark_favourite(&self, content_id: &str, _content_type: &str) -> Result<()> {
+        self.write(content_id, |state| {
+            let entry = state
+                .watchlist
+                .iter_mut()
+                .find(|i| i.content_id == content_id)
+                .ok_or_else(|| anyhow!("404 Not Found: {} is not on the watchlist", content_id))?;
+            entry.is_favourite = true;
+            Ok(())
+        })
+    }
+
+    async fn create_crunchylist(&self, name: &str) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .crunchylists
+            .push(CrunchylistData {
+                name: name.to_string(),
+                items: vec![],
+            });
+        Ok(())
+    }
+
+    async fn add_to_crunchylist(&self, list_name: &str, content_id: &str) -> Result<()> {
+        let (title, _) = self.title(content_id)?.clone();
+        self.write(content_id, |state| {
+            let list = state
+                .crunchylists
+                .iter_mut()
+                .find(|l| l.name == list_name)
+                .ok_or_else(|| anyhow!("Crunchylist '{}' not found", list_name))?;
+            if !list.items.iter().any(|i| i.content_id == content_id) {
+                list.items.push(CrunchylistItem {
+                    content_id: content_id.to_string(),
+                    title,
+                });
+            }
+            Ok(())
+        })
+    }
+
+    async fn rate(&self, content_id: &str, _content_type: &str, rating: &str) -> Result<()> {
+        self.title(content_id)?;
+        self.write(content_id, |state| {
+            state
+                .ratings
+                .insert(content_id.to_string(), rating.to_string());
+            Ok(())
+        })
+    }
+
+    async fn set_playhead(&self, content_id: &str, playhead: u32) -> Result<()> {
+        self.update_history(content_id, playhead, false)
+    }
+
+    async fn mark_as_watched(&self, content_id: &str) -> Result<()> {
+        self.update_history(content_id, 0, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff;
+    use crate::export::{self, ExportFormat};
+    use crate::failures::{ErrorKind, Failures};
+    use crate::fixtures::history_item_at;
+    use crate::import::{self, ImportOptions, RatingsPolicy};
+    use crate::journal::Journal;
+    use crate::selection::{ExportFile, Selection};
+    use crate::target_cache;
+    use crate::ui::{self, DataType};
+    use crate::verify;
+    use chrono::Duration;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fake-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn series(content_id: &str, is_favourite: bool) -> WatchlistItem {
+        WatchlistItem {
+            content_id: content_id.to_string(),
+            title: format!("Series {}", content_id),
+            slug: content_id.to_lowercase(),
+            content_type: "series".to_string(),
+            is_favourite,
+            fully_watched: false,
+        }
+    }
+
+    fn source_profile() -> FakeBackend {
+        let mut e3 = history_item_at("E3", "S2", Utc::now() - Duration::minutes(10));
+        e3.fully_watched = false;
+        e3.playhead = 300;
+        FakeBackend::new(
+            "source",
+            FakeState {
+                watchlist: vec![series("S1", true), series("S2", false)],
+                history: vec![
+                    history_item_at("E1", "S1", Utc::now() - Duration::minutes(30)),
+                    history_item_at("E2", "S1", Utc::now() - Duration::minutes(20)),
+                    e3,
+                ],
+                crunchylists: vec![CrunchylistData {
+                    name: "Later".to_string(),
+                    items: vec![CrunchylistItem {
+                        content_id: "S3".to_string(),
+                        title: "Series S3".to_string(),
+                    }],
+                }],
+                ratings: HashMap::from([("S1".to_string(), "FiveStars".to_string())]),
+            },
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn export_diff_import_verify_roundtrip() {
+        let dir = temp_dir("roundtrip");
+        let source = source_profile();
+        export::run(
+            &source,
+            &dir,
+            Selection::default(),
+            ExportFormat::Json,
+            None,
+            ui::Display::Headless,
+        )
+        .await
+        .unwrap();
+
+        let target = FakeBackend::new("target", FakeState::default()).with_catalog_of(&source);
+        let before = diff::compute_diff(
+            &target,
+            &dir,
+            Selection::default(),
+            std::time::Duration::ZERO,
+        )
+        .await
+        .unwrap();
+        assert_eq!(before.watchlist.missing.len(), 2);
+        assert_eq!(before.favourites.missing.len(), 1);
+        assert_eq!(before.history.missing.len(), 3);
+        assert_eq!(before.crunchylists.missing.len(), 1);
+        assert_eq!(before.ratings.missing.len(), 1);
+
+        import::run(&target, &dir, &ImportOptions::default())
+            .await
+            .unwrap();
+
+        let after = diff::compute_diff(
+            &target,
+            &dir,
+            Selection::default(),
+            std::time::Duration::ZERO,
+        )
+        .await
+        .unwrap();
+        assert!(after.watchlist.missing.is_empty());
+        assert!(after.favourites.missing.is_empty());
+        assert!(after.history.missing.is_empty());
+        assert_eq!(after.history.behind.as_ref().map(Vec::len), Some(0));
+        assert!(after.crunchylists.missing.is_empty());
+        assert!(after.ratings.missing.is_empty());
+
+        let state = target.state();
+        let e3 = state.history.iter().find(|i| i.content_id == "E3").unwrap();
+        assert_eq!((e3.playhead, e3.fully_watched), (300, false));
+
+        let report = verify::run(
+            &target,
+            &dir,
+            RatingsPolicy::default(),
+            Selection::default(),
+        )
+        .await
+        .unwrap();
+        assert!(report.problems.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn target_snapshot_is_reused_and_kept_current() {
+        let dir = temp_dir("target-cache");
+        let source = source_profile();
+        export::run(
+            &source,
+            &dir,
+            Selection::default(),
+            ExportFormat::Json,
+            None,
+            ui::Display::Headless,
+        )
+        .await
+        .unwrap();
+        let ttl = std::time::Duration::from_secs(600);
+
+        let target = FakeBackend::new("target", FakeState::default())
+            .with_catalog_of(&source)
+            .fail_writes_for("S2");
+        diff::compute_diff(&target, &dir, Selection::default(), ttl)
+            .await
+            .unwrap();
+        assert!(dir.join(target_cache::TARGET_STATE_FILE).exists());
+        let options = ImportOptions {
+            target_cache_ttl: ttl,
+            ..ImportOptions::default()
+        };
+        import::run(&target, &dir, &options).await.unwrap();
+
+        // An empty profile with the same id: only the snapshot knows what import wrote
+        let fresh = FakeBackend::new("target", FakeState::default());
+        let cached = diff::compute_diff(&fresh, &dir, Selection::default(), ttl)
+            .await
+            .unwrap();
+        let missing: Vec<_> = cached
+            .watchlist
+            .missing
+            .iter()
+            .map(|i| i.content_id.as_str())
+            .collect();
+        assert_eq!(missing, ["S2"]);
+        assert!(cached.history.missing.is_empty());
+        assert!(cached.crunchylists.missing.is_empty());
+        assert!(cached.ratings.missing.is_empty());
+
+        let other = FakeBackend::new("other", FakeState::default());
+        let refetched = diff::compute_diff(&other, &dir, Selection::default(), ttl)
+            .await
+            .unwrap();
+        assert_eq!(refetched.watchlist.missing.len(), 2);
+        let uncached = diff::compute_diff(
+            &fresh,
+            &dir,
+            Selection::default(),
+            std::time::Duration::ZERO,
+        )
+        .await
+        .unwrap();
+        assert_eq!(uncached.history.missing.len(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn edited_csv_export_imports() {
+        let dir = temp_dir("csv");
+        let source = source_profile();
+        export::run(
+            &source,
+            &dir,
+            Selection::default(),
+            ExportFormat::Csv,
+            None,
+            ui::Display::Headless,
+        )
+        .await
+        .unwrap();
+        assert!(dir.join("watch_history.csv").exists());
+        assert!(!dir.join("watch_history.json").exists());
+
+        let ratings = std::fs::read_to_string(dir.join("ratings.csv")).unwrap();
+        assert!(ratings.starts_with("content_id,content_type,title,rating\n"));
+        std::fs::write(
+            dir.join("ratings.csv"),
+            ratings.replace("FiveStars", "ThreeStars"),
+        )
+        .unwrap();
+        let lists = std::fs::read_to_string(dir.join("crunchylists.csv")).unwrap();
+        std::fs::write(
+            dir.join("crunchylists.csv"),
+            format!("{}Later,S1,Series S1\n", lists),
+        )
+        .unwrap();
+
+        let target = FakeBackend::new("target", FakeState::default()).with_catalog_of(&source);
+        import::run(&target, &dir, &ImportOptions::default())
+            .await
+            .unwrap();
+
+        let state = target.state();
+        assert_eq!(state.watchlist.len(), 2);
+        let e3 = state.history.iter().find(|i| i.content_id == "E3").unwrap();
+        assert_eq!((e3.playhead, e3.fully_watched), (300, false));
+        assert_eq!(state.crunchylists[0].items.len(), 2);
+        assert_eq!(state.ratings["S1"], "ThreeStars");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn selected_data_types_only() {
+        let dir = temp_dir("selection");
+        let source = source_profile();
+        let only = Selection::new(&[ExportFile::Watchlist, ExportFile::Ratings], &[]);
+        export::run(
+            &source,
+            &dir,
+            only,
+            ExportFormat::Json,
+            None,
+            ui::Display::Headless,
+        )
+        .await
+        .unwrap();
+        assert!(dir.join("watchlist.json").exists());
+        assert!(dir.join("ratings.json").exists());
+        assert!(!dir.join("watch_history.json").exists());
+        assert!(!dir.join("crunchylists.json").exists());
+
+        // Missing files are simply not imported
+        let target = FakeBackend::new("target", FakeState::default()).with_catalog_of(&source);
+        import::run(&target, &dir, &ImportOptions::default())
+            .await
+            .unwrap();
+        let state = target.state();
+        assert_eq!(state.watchlist.len(), 2);
+        assert_eq!(state.ratings.len(), 1);
+        assert!(state.history.is_empty());
+
+        let skip = ImportOptions {
+            selection: Selection::new(&[], &[ExportFile::Watchlist]),
+            ..ImportOptions::default()
+        };
+        let target = FakeBackend::new("target", FakeState::default()).with_catalog_of(&source);
+        import::run(&target, &dir, &skip).await.unwrap();
+        let state = target.state();
+        assert!(state.watchlist.is_empty());
+        assert_eq!(state.ratings.len(), 1);
+
+        let only_history = ImportOptions {
+            selection: Selection::new(&[ExportFile::History], &[]),
+            ..ImportOptions::default()
+        };
+        let err = import::run(&target, &dir, &only_history).await.unwrap_err();
+        assert!(err.to_string().contains("No export files"), "{}", err);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn import_skips_present_items_and_journals_failures() {
+        let dir = temp_dir("failures");
+        let source = source_profile();
+        export::run(
+            &source,
+            &dir,
+            Selection::default(),
+            ExportFormat::Json,
+            None,
+            ui::Display::Headless,
+        )
+        .await
+        .unwrap();
+
+        let target = FakeBackend::new(
+            "target",
+            FakeState {
+                watchlist: vec![series("S1", false)],
+                ..FakeState::default()
+            },
+        )
+        .with_catalog_of(&source)
+        .fail_writes_for("S2");
+        import::run(&target, &dir, &ImportOptions::default())
+            .await
+            .unwrap();
+
+        let state = target.state();
+        assert_eq!(state.watchlist.len(), 1);
+        assert!(state.watchlist[0].is_favourite);
+
+        let journal = Journal::resume(&dir).unwrap();
+        assert!(journal.is_done(DataType::Watchlist, "S1"));
+        assert!(!journal.is_done(DataType::Watchlist, "S2"));
+        assert!(journal.is_done(DataType::History, "E3"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_failed_clears_items_that_go_through() {
+        let dir = temp_dir("retry-failed");
+        let source = source_profile();
+        export::run(
+            &source,
+            &dir,
+            Selection::default(),
+            ExportFormat::Json,
+            None,
+            ui::Display::Headless,
+        )
+        .await
+        .unwrap();
+
+        let target = FakeBackend::new("target", FakeState::default())
+            .with_catalog_of(&source)
+            .fail_writes_for("S2")
+            .fail_writes_for("E1");
+        import::run(&target, &dir, &ImportOptions::default())
+            .await
+            .unwrap();
+
+        let failed = Failures::load(&dir).unwrap().items();
+        let keys: Vec<_> = failed.iter().map(|f| (f.data_type, f.key())).collect();
+        assert_eq!(
+            keys,
+            [
+                (DataType::Watchlist, "S2".to_string()),
+                (DataType::History, "E1".to_string())
+            ]
+        );
+        assert_eq!(failed[0].kind, ErrorKind::Rejected);
+
+        // S2 starts working; E1 still fails
+        let target = FakeBackend::new("target", target.state())
+            .with_catalog_of(&source)
+            .fail_writes_for("E1");
+        import::retry_failed(&target, &dir, Selection::default(), ui::Display::Headless)
+            .await
+            .unwrap();
+
+        assert!(
+            target
+                .state()
+                .watchlist
+                .iter()
+                .any(|i| i.content_id == "S2")
+        );
+        let failed = Failures::load(&dir).unwrap().items();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].content_id, "E1");
+        assert!(
+            Journal::resume(&dir)
+                .unwrap()
+                .is_done(DataType::Watchlist, "S2")
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resume_keeps_target_ratings_under_target_wins() {
+        let dir = temp_dir("resume-ratings");
+        let source = source_profile();
+        export::run(
+            &source,
+            &dir,
+            Selection::default(),
+            ExportFormat::Json,
+            None,
+            ui::Display::Headless,
+        )
+        .await
+        .unwrap();
+
+        // No cached snapshot, so resume has to look the target's ratings up itself
+        let target = FakeBackend::new(
+            "target",
+            FakeState {
+                ratings: HashMap::from([("S1".to_string(), "TwoStars".to_string())]),
+                ..FakeState::default()
+            },
+        )
+        .with_catalog_of(&source);
+        let options = ImportOptions {
+            resume: true,
+            ratings_policy: RatingsPolicy::TargetWins,
+            ..ImportOptions::default()
+        };
+        import::run(&target, &dir, &options).await.unwrap();
+
+        assert_eq!(target.state().ratings["S1"], "TwoStars");
+        assert_eq!(target.state().watchlist.len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn short_target_history_is_an_error() {
+        let source = source_profile();
+        let target = FakeBackend::new("target", source.state()).hide_history_entry("E2");
+        let err = import::fetch_target_state(&target, Selection::default(), &[])
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string().contains("only 2 of the target's 3"),
+            "{}",
+            err
+        );
+
+        let state = import::fetch_target_state(&source, Selection::default(), &[])
+            .await
+            .unwrap();
+        assert_eq!(state.history.len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn verify_reports_writes_that_did_not_stick() {
+        let dir = temp_dir("verify");
+        let source = source_profile();
+        export::run(
+            &source,
+            &dir,
+            Selection::default(),
+            ExportFormat::Json,
+            None,
+            ui::Display::Headless,
+        )
+        .await
+        .unwrap();
+
+        let target = FakeBackend::new("target", FakeState::default())
+            .with_catalog_of(&source)
+            .drop_writes_for("E2");
+        import::run(&target, &dir, &ImportOptions::default())
+            .await
+            .unwrap();
+
+        let report = verify::run(
+            &target,
+            &dir,
+            RatingsPolicy::default(),
+            Selection::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problems[0].content_id, "E2");
+        assert_eq!(report.problems[0].status, verify::Status::Missing);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+//! Export items shared by the unit tests.
+
+use crate::models::{
+    CrunchylistsExport, ExportMetadata, RatingsExport, SCHEMA_VERSION, WatchHistoryExport,
+    WatchHistoryItem, WatchlistExport,
+};
+use chrono::{DateTime, Utc};
+
+/// Metadata of a current-schema export of profile "test", made just now.
+pub fn metadata(total_count: usize) -> ExportMetadata {
+    ExportMetadata {
+        schema_version: SCHEMA_VERSION,
+        profile_name: "test".to_string(),
+        exported_at: Utc::now(),
+        total_count,
+        partial: false,
+    }
+}
+
+/// The four export files, with nothing in them.
+pub fn empty_exports() -> (
+    WatchlistExport,
+    WatchHistoryExport,
+    CrunchylistsExport,
+    RatingsExport,
+) {
+    (
+        WatchlistExport {
+            metadata: metadata(0),
+            items: vec![],
+        },
+        WatchHistoryExport {
+            metadata: metadata(0),
+            items: vec![],
+        },
+        CrunchylistsExport {
+            metadata: metadata(0),
+            lists: vec![],
+        },
+        RatingsExport {
+            metadata: metadata(0),
+            items: vec![],
+        },
+    )
+}
+
+/// History entry for episode `content_id` of series S1, played just now.
+pub fn history_item(content_id: &str, playhead: u32, fully_watched: bool) -> WatchHistoryItem {
+    WatchHistoryItem {
+        content_id: content_id.to_string(),
+        parent_id: "S1".to_string(),
+        parent_type: "series".to_string(),
+        title: format!("Episode {}", content_id),
+        series_title: "Show".to_string(),
+        date_played: Utc::now(),
+        playhead,
+        fully_watched,
+        partial: false,
+    }
+}
+
+/// Fully watched episode `content_id` of series `parent_id`, played at `date_played`.
+pub fn history_item_at(
+    content_id: &str,
+    parent_id: &str,
+    date_played: DateTime<Utc>,
+) -> WatchHistoryItem {
+    WatchHistoryItem {
+        parent_id: parent_id.to_string(),
+        date_played,
+        ..history_item(content_id, 0, true)
+    }
+}
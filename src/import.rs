@@ -1,174 +1,609 @@
+use crate::backend::{Backend, HISTORY_PAGE_SIZE};
+use crate::failures::{self, ErrorKind, Failure};
+use crate::journal::{self, Journal, Outcome};
 use crate::models::{
-    self, CrunchylistsExport, RatingItem, RatingsExport, WatchHistoryExport, WatchlistExport,
+    CrunchylistsExport, RatingItem, RatingsExport, WatchHistoryExport, WatchHistoryItem,
+    WatchlistExport,
 };
+use crate::plan;
+use crate::selection::{ExportFile, Exports, Selection};
+use crate::target_cache::{self, TargetCache};
 use crate::ui::{self, DataType, ProgressReporter, ProgressUpdate};
 use anyhow::{Context, Result};
-use crunchyroll_rs::{Crunchyroll, MediaCollection};
-use futures_util::{StreamExt, stream};
+use futures_util::{StreamExt, future, stream};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-const WRITE_DELAY: Duration = Duration::from_millis(500);
-const CONCURRENCY: usize = 5;
-const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
 const MAX_RETRIES: u32 = 5;
 
-struct Counts {
-    total: usize,
-    added: usize,
-    already_present: usize,
-    failed: usize,
+/// How to resolve a rating that differs between the export and the target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RatingsPolicy {
+    /// Overwrite the target's rating with the exported one
+    #[default]
+    SourceWins,
+    /// Keep whatever the target already has
+    TargetWins,
+    /// Keep the higher of the two ratings
+    Higher,
+}
+
+pub struct Counts {
+    pub total: usize,
+    pub added: usize,
+    pub already_present: usize,
+    /// Settled by a previous run, according to the journal
+    pub skipped: usize,
+    pub failed: usize,
 }
 
 impl Counts {
-    fn new(total: usize) -> Self {
+    pub fn new(total: usize) -> Self {
         Self {
             total,
             added: 0,
             already_present: 0,
+            skipped: 0,
             failed: 0,
         }
     }
 
     fn processed(&self) -> usize {
-        self.added + self.already_present + self.failed
+        self.added + self.already_present + self.skipped + self.failed
+    }
+
+    fn remaining(&self) -> usize {
+        self.total - self.skipped
     }
 
-    fn to_update(&self, data_type: DataType) -> ProgressUpdate {
+    pub fn to_update(&self, data_type: DataType) -> ProgressUpdate {
         ProgressUpdate {
             data_type,
             total: self.total,
             processed: self.processed(),
             added: self.added,
-            skipped: 0,
+            skipped: self.skipped,
             already_present: self.already_present,
             failed: self.failed,
         }
     }
 }
 
-pub async fn run(crunchy: &Crunchyroll, input_dir: &Path, dry_run: bool) -> Result<()> {
-    if dry_run {
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    /// Show the diff instead of writing anything
+    pub dry_run: bool,
+    /// Continue from the journal of an interrupted import
+    pub resume: bool,
+    pub ratings_policy: RatingsPolicy,
+    /// Replay history oldest-first so the target's ordering matches the source
+    pub ordered_history: bool,
+    /// Write the planned operations to this file instead of executing them
+    pub plan: Option<PathBuf>,
+    /// Reuse a target state snapshot younger than this; zero always fetches
+    pub target_cache_ttl: Duration,
+    /// Data types to import
+    pub selection: Selection,
+    /// Headless unless the caller asks for the dashboard
+    pub display: ui::Display,
+}
+
+pub async fn run<B: Backend>(crunchy: &B, input_dir: &Path, options: &ImportOptions) -> Result<()> {
+    if options.dry_run {
         println!("Dry run -- showing what would be imported:\n");
-        return crate::diff::run(crunchy, input_dir).await;
+        return crate::diff::run(
+            crunchy,
+            input_dir,
+            options.selection,
+            crate::diff::DiffFormat::Table,
+            false,
+            options.target_cache_ttl,
+        )
+        .await;
+    }
+    if let Some(plan_path) = &options.plan {
+        return plan::create(crunchy, input_dir, plan_path, options).await;
+    }
+
+    let exports = Exports::read(input_dir, options.selection)?;
+    let selection = exports.selection;
+
+    // On resume the journal says what's done; skip the (slow) target pre-filter unless a
+    // snapshot is still cached, and let 409 handling absorb anything that landed without
+    // being journaled.
+    let ttl = options.target_cache_ttl;
+    let (journal, mut target_state, cache) = if options.resume {
+        let journal = Journal::resume(input_dir)?;
+        print_resume_plan(&journal, &exports);
+        match TargetCache::load(crunchy, input_dir, selection, ttl).await? {
+            Some((cache, state)) => (journal, state, Some(cache)),
+            None => {
+                // Ratings can't be left to 409 handling: without the target's, every
+                // rating looks unrated and a policy that keeps target ratings overwrites them
+                let mut state = TargetState::default();
+                if selection.contains(ExportFile::Ratings)
+                    && options.ratings_policy != RatingsPolicy::SourceWins
+                {
+                    let outstanding = exports
+                        .ratings
+                        .items
+                        .iter()
+                        .filter(|r| !journal.is_done(DataType::Ratings, &r.content_id));
+                    state.ratings = fetch_ratings(crunchy, outstanding).await?;
+                }
+                (journal, state, None)
+            }
+        }
+    } else {
+        let (cache, state) =
+            TargetCache::load_or_fetch(crunchy, input_dir, selection, &exports.ratings.items, ttl)
+                .await?;
+        (Journal::create(input_dir)?, state, Some(cache))
+    };
+    // An import that errors out partway must not leave a snapshot missing its writes
+    target_cache::invalidate(input_dir)?;
+
+    let profile = crunchy.profile_id().await;
+    let (reporter, dashboard) = ui::start_dashboard(
+        options.display,
+        "Import",
+        "",
+        &profile,
+        selection.phases(),
+        crunchy.limiter().clone(),
+    );
+
+    let target = &mut target_state;
+    let mut counts = Vec::new();
+    if selection.contains(ExportFile::Watchlist) {
+        let wl = import_watchlist(crunchy, &exports.watchlist, target, &journal, &reporter).await?;
+        counts.push((DataType::Watchlist, wl));
+        let fv =
+            import_favourites(crunchy, &exports.watchlist, target, &journal, &reporter).await?;
+        counts.push((DataType::Favourites, fv));
+    }
+    if selection.contains(ExportFile::Crunchylists) {
+        let cl = import_crunchylists(crunchy, &exports.crunchylists, target, &journal, &reporter)
+            .await?;
+        counts.push((DataType::Crunchylists, cl));
+    }
+    if selection.contains(ExportFile::Ratings) {
+        let rt = import_ratings(
+            crunchy,
+            &exports.ratings,
+            target,
+            options.ratings_policy,
+            &journal,
+            &reporter,
+        )
+        .await?;
+        counts.push((DataType::Ratings, rt));
+    }
+    if selection.contains(ExportFile::History) {
+        let hi = import_history(
+            crunchy,
+            &exports.history,
+            target,
+            options.ordered_history,
+            &journal,
+            &reporter,
+        )
+        .await?;
+        counts.push((DataType::History, hi));
+    }
+
+    reporter.done();
+    dashboard.wait();
+
+    // Keep the snapshot in step with what was just written, for the next diff or import
+    if let Some(cache) = &cache {
+        cache.save(&target_state)?;
+    }
+
+    if !reporter.is_tty() {
+        print_summary(&counts);
+    }
+
+    print_failures_hint(&journal, input_dir);
+    if reporter.is_cancelled() {
+        println!(
+            "Import cancelled. Run again with --resume to continue from {}.",
+            journal::JOURNAL_FILE
+        );
+        crunchy.limiter().check()?;
+        return Err(ui::Cancelled.into());
     }
+    Ok(())
+}
 
-    let watchlist: WatchlistExport = models::read_export(input_dir, "watchlist.json")?;
-    let history: WatchHistoryExport = models::read_export(input_dir, "watch_history.json")?;
-    let crunchylists: CrunchylistsExport = models::read_export(input_dir, "crunchylists.json")?;
-    let ratings: RatingsExport = models::read_export(input_dir, "ratings.json")?;
+/// Point at `retry-failed` when some items are still outstanding.
+fn print_failures_hint(journal: &Journal, input_dir: &Path) {
+    let failed = journal.failures().items().len();
+    if failed > 0 {
+        println!(
+            "{} items failed and are listed in {}. Run `retry-failed --input-dir {}` to retry just those.",
+            failed,
+            input_dir.join(failures::FAILURES_FILE).display(),
+            input_dir.display()
+        );
+    }
+}
 
-    println!("Fetching target account state for pre-filtering...");
-    let target_state = fetch_target_state(crunchy).await?;
+/// Re-attempt only the items listed in `failures.json`, looking their details up in the
+/// export. Items that go through (or turn out to be there already) drop out of the file;
+/// the rest stay with their latest error.
+pub async fn retry_failed<B: Backend>(
+    crunchy: &B,
+    input_dir: &Path,
+    selection: Selection,
+    display: ui::Display,
+) -> Result<()> {
+    let journal = Journal::resume(input_dir)?;
+    let mut pending = journal.failures().items();
+    pending.retain(|f| selection.includes(f.data_type));
+    if pending.is_empty() {
+        println!(
+            "Nothing to retry: no failed items in {}.",
+            input_dir.join(failures::FAILURES_FILE).display()
+        );
+        return Ok(());
+    }
+    pending.sort_by_key(|f| ui::phase_index(f.data_type));
+
+    let exports = Exports::read(input_dir, selection)?;
+    println!("Retrying {} failed items...", pending.len());
 
     let profile = crunchy.profile_id().await;
-    let (reporter, dashboard) = ui::start_dashboard("Import", "", &profile);
+    let (reporter, dashboard) = ui::start_dashboard(
+        display,
+        "Retry failed",
+        "",
+        &profile,
+        selection.phases(),
+        crunchy.limiter().clone(),
+    );
 
-    let wl = import_watchlist(crunchy, &watchlist, &target_state, &reporter).await?;
-    let cl = import_crunchylists(crunchy, &crunchylists, &target_state, &reporter).await?;
-    let rt = import_ratings(crunchy, &ratings, &reporter).await?;
-    let hi = import_history(crunchy, &history, &target_state, &reporter).await?;
+    let mut counts: Vec<Counts> = ui::PHASES
+        .iter()
+        .map(|&d| Counts::new(pending.iter().filter(|f| f.data_type == d).count()))
+        .collect();
+    // These writes aren't tracked in the snapshot
+    target_cache::invalidate(input_dir)?;
+    let mut lists = None;
+    for failure in pending {
+        if reporter.is_cancelled() {
+            break;
+        }
+        let c = &mut counts[ui::phase_index(failure.data_type)];
+        match retry_one(crunchy, &failure, &exports, &mut lists).await {
+            Ok(()) => {
+                reporter.log_success(&failure.title);
+                journal.record(failure.data_type, &failure.key(), Outcome::Added, None)?;
+                c.added += 1;
+            }
+            Err(e) if is_conflict(&e) => {
+                reporter.log_skip(&failure.title);
+                journal.record(
+                    failure.data_type,
+                    &failure.key(),
+                    Outcome::AlreadyPresent,
+                    None,
+                )?;
+                c.already_present += 1;
+            }
+            Err(e) => {
+                reporter.log_error(&format!("{} -- {}", failure.title, e));
+                let mut again =
+                    Failure::new(failure.data_type, &failure.content_id, &failure.title, &e);
+                again.list = failure.list.clone();
+                journal.fail(again)?;
+                c.failed += 1;
+            }
+        }
+        reporter.progress(c.to_update(failure.data_type));
+    }
 
     reporter.done();
     dashboard.wait();
 
-    if !ui::is_tty() {
-        print_summary(&[
-            ("Watchlist", &wl),
-            ("Crunchylists", &cl),
-            ("Ratings", &rt),
-            ("History", &hi),
-        ]);
+    if !reporter.is_tty() {
+        let sections: Vec<(DataType, Counts)> = ui::PHASES
+            .into_iter()
+            .zip(counts)
+            .filter(|(_, c)| c.total > 0)
+            .collect();
+        print_summary(&sections);
+    }
+
+    let remaining = journal.failures().items().len();
+    if remaining == 0 {
+        println!("All failed items went through.");
+    } else {
+        println!(
+            "{} items still failing; see {}.",
+            remaining,
+            input_dir.join(failures::FAILURES_FILE).display()
+        );
+    }
+    if reporter.is_cancelled() {
+        crunchy.limiter().check()?;
+        return Err(ui::Cancelled.into());
     }
     Ok(())
 }
 
+/// Repeat the write that failed for one item, looking it up in `exports`; crunchylist
+/// items carry everything they need in the failure itself. `lists` caches the target's
+/// list names.
+async fn retry_one<B: Backend>(
+    crunchy: &B,
+    failure: &Failure,
+    exports: &Exports,
+    lists: &mut Option<Vec<String>>,
+) -> Result<()> {
+    let id = failure.content_id.as_str();
+    let missing = || anyhow::anyhow!("{} is no longer in the export", id);
+    match failure.data_type {
+        DataType::Watchlist | DataType::Favourites => {
+            let item = exports
+                .watchlist
+                .items
+                .iter()
+                .find(|i| i.content_id == id)
+                .ok_or_else(missing)?;
+            if failure.data_type == DataType::Watchlist {
+                retry_with_backoff(|| crunchy.add_to_watchlist(id, &item.content_type)).await
+            } else {
+                retry_with_backoff(|| crunchy.mark_favourite(id, &item.content_type)).await
+            }
+        }
+        DataType::Crunchylists => {
+            let list = failure
+                .list
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("No crunchylist recorded for {}", id))?;
+            let names = match lists {
+                Some(names) => names,
+                None => lists.insert(crunchy.crunchylist_names().await?),
+            };
+            if !names.iter().any(|n| n == list) {
+                crunchy.create_crunchylist(list).await?;
+                names.push(list.to_string());
+            }
+            retry_with_backoff(|| crunchy.add_to_crunchylist(list, id)).await
+        }
+        DataType::Ratings => {
+            let item = exports
+                .ratings
+                .items
+                .iter()
+                .find(|i| i.content_id == id)
+                .ok_or_else(missing)?;
+            retry_with_backoff(|| crunchy.rate(id, &item.content_type, &item.rating)).await
+        }
+        DataType::History => {
+            let item = exports
+                .history
+                .items
+                .iter()
+                .find(|i| i.content_id == id)
+                .ok_or_else(missing)?;
+            write_history_entry(crunchy, item, false).await.2
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayheadState {
+    pub playhead: u32,
+    pub fully_watched: bool,
+}
+
+impl PlayheadState {
+    /// Whether the exported progress is further along than this (target) progress.
+    /// Completion beats any playhead; otherwise the larger playhead wins.
+    pub fn is_behind(&self, source: &WatchHistoryItem) -> bool {
+        if self.fully_watched {
+            return false;
+        }
+        source.fully_watched || source.playhead > self.playhead
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
 pub struct TargetState {
     pub watchlist_ids: HashSet<String>,
-    pub history_ids: HashSet<String>,
+    /// Subset of `watchlist_ids` marked as favourite
+    pub watchlist_favourites: HashSet<String>,
+    /// Map from episode/movie content_id -> how far the target has watched it
+    pub history: HashMap<String, PlayheadState>,
     /// Map from list name -> set of content_ids already in that list
     pub crunchylists: HashMap<String, HashSet<String>>,
+    /// Map from content_id -> rating (e.g. "FourStars"). Only covers the exported
+    /// ratings' content, since there is no endpoint listing all of a profile's ratings.
+    pub ratings: HashMap<String, String>,
 }
 
-pub async fn fetch_target_state(crunchy: &Crunchyroll) -> Result<TargetState> {
-    use crunchyroll_rs::list::WatchlistOptions;
+/// Snapshot the selected parts of the target profile; the rest stay empty.
+/// `rating_candidates` are the exported ratings whose content should be looked up on the
+/// target.
+pub async fn fetch_target_state<B: Backend>(
+    crunchy: &B,
+    selection: Selection,
+    rating_candidates: &[RatingItem],
+) -> Result<TargetState> {
+    let mut state = TargetState::default();
+
+    if selection.contains(ExportFile::Watchlist) {
+        let watchlist = crunchy.watchlist().await?;
+        state.watchlist_ids = watchlist.iter().map(|i| i.content_id.clone()).collect();
+        state.watchlist_favourites = watchlist
+            .iter()
+            .filter(|i| i.is_favourite)
+            .map(|i| i.content_id.clone())
+            .collect();
+    }
 
-    let watchlist = crunchy.watchlist(WatchlistOptions::default()).await?;
-    let watchlist_ids: HashSet<String> = watchlist
-        .iter()
-        .filter_map(|e| crate::export::extract_series_info(&e.panel).map(|(id, _, _, _)| id))
-        .collect();
+    if selection.contains(ExportFile::History) {
+        state.history = fetch_history(crunchy)
+            .await?
+            .into_iter()
+            .map(|entry| {
+                let playhead = PlayheadState {
+                    playhead: entry.playhead,
+                    fully_watched: entry.fully_watched,
+                };
+                (entry.content_id, playhead)
+            })
+            .collect();
+    }
 
-    let mut history_ids = HashSet::new();
-    let mut stream = crunchy.watch_history();
-    while let Some(Ok(entry)) = stream.next().await {
-        history_ids.insert(entry.id.clone());
+    if selection.contains(ExportFile::Crunchylists) {
+        for name in crunchy.crunchylist_names().await? {
+            let item_ids: HashSet<String> = crunchy
+                .crunchylist(&name)
+                .await?
+                .into_iter()
+                .map(|i| i.content_id)
+                .collect();
+            state.crunchylists.insert(name, item_ids);
+        }
     }
 
-    let lists = crunchy.crunchylists().await?;
-    let mut crunchylists = HashMap::new();
-    for preview in &lists.items {
-        let full_list = preview.crunchylist().await?;
-        let item_ids: HashSet<String> = full_list
-            .items
-            .iter()
-            .filter_map(|e| crate::export::extract_series_info(&e.panel).map(|(id, _, _, _)| id))
-            .collect();
-        crunchylists.insert(preview.title.clone(), item_ids);
+    if selection.contains(ExportFile::Ratings) {
+        state.ratings = fetch_ratings(crunchy, rating_candidates.iter()).await?;
     }
+    Ok(state)
+}
 
-    Ok(TargetState {
-        watchlist_ids,
-        history_ids,
-        crunchylists,
-    })
+/// The target's ratings for `candidates`. Unrated content, and content the target
+/// doesn't have, is left out; any other failed lookup fails the fetch, since leaving it
+/// out would make a rating policy that keeps target ratings overwrite it.
+pub async fn fetch_ratings<'a, B: Backend>(
+    crunchy: &B,
+    candidates: impl Iterator<Item = &'a RatingItem>,
+) -> Result<HashMap<String, String>> {
+    let lookups: Vec<_> = stream::iter(candidates)
+        .map(|item| async move { (item, fetch_target_rating(crunchy, item).await) })
+        .buffer_unordered(crunchy.limiter().concurrency())
+        .collect()
+        .await;
+    let mut ratings = HashMap::new();
+    for (item, rating) in lookups {
+        let rating = rating.with_context(|| {
+            format!(
+                "Looking up the target's rating for {} ({})",
+                item.title, item.content_id
+            )
+        })?;
+        if let Some(rating) = rating {
+            ratings.insert(item.content_id.clone(), rating);
+        }
+    }
+    Ok(ratings)
+}
+
+/// One rating lookup on the target, retried like a write.
+async fn fetch_target_rating<B: Backend>(crunchy: &B, item: &RatingItem) -> Result<Option<String>> {
+    match retry_with_backoff(|| crunchy.rating(&item.content_id, &item.content_type)).await {
+        Err(e) if failures::classify(&e) == ErrorKind::NotFound => Ok(None),
+        result => result,
+    }
+}
+
+/// Read the whole watch history a page at a time, retrying each page like a write. A
+/// history that comes up short of the total the API reports is an error: the import
+/// would take the missing entries for unwatched and write them again.
+pub async fn fetch_history<B: Backend>(crunchy: &B) -> Result<Vec<WatchHistoryItem>> {
+    let mut items = Vec::new();
+    let mut total = 0;
+    for page in 1.. {
+        let fetched = retry_with_backoff(|| crunchy.watch_history_page(page, HISTORY_PAGE_SIZE))
+            .await
+            .with_context(|| format!("Fetching page {} of the target's watch history", page))?;
+        total = fetched.total as usize;
+        let empty = fetched.items.is_empty();
+        items.extend(fetched.items);
+        if empty || page as usize * HISTORY_PAGE_SIZE as usize >= total {
+            break;
+        }
+    }
+    if items.len() < total {
+        anyhow::bail!(
+            "Fetched only {} of the target's {} watch history entries; try again later",
+            items.len(),
+            total
+        );
+    }
+    Ok(items)
 }
 
-async fn import_watchlist(
-    crunchy: &Crunchyroll,
+async fn import_watchlist<B: Backend>(
+    crunchy: &B,
     export: &WatchlistExport,
-    target: &TargetState,
+    target: &mut TargetState,
+    journal: &Journal,
     reporter: &ProgressReporter,
 ) -> Result<Counts> {
     let mut c = Counts::new(export.items.len());
 
-    let to_import: Vec<_> = export
-        .items
-        .iter()
-        .filter(|item| !target.watchlist_ids.contains(&item.content_id))
-        .collect();
-    c.already_present = export.items.len() - to_import.len();
+    let mut to_import = Vec::new();
+    for item in &export.items {
+        if journal.is_done(DataType::Watchlist, &item.content_id) {
+            c.skipped += 1;
+        } else if target.watchlist_ids.contains(&item.content_id) {
+            journal.record(
+                DataType::Watchlist,
+                &item.content_id,
+                Outcome::AlreadyPresent,
+                None,
+            )?;
+            c.already_present += 1;
+        } else {
+            to_import.push(item);
+        }
+    }
     reporter.progress(c.to_update(DataType::Watchlist));
 
+    // Once cancelled, stop pulling new items; buffer_unordered still drains in-flight ones
+    let cancel = reporter.cancellation();
     let mut results = stream::iter(to_import)
-        .map(|item| {
-            let cr = crunchy.clone();
-            let content_id = item.content_id.clone();
-            let content_type = item.content_type.clone();
-            let title = item.title.clone();
-            async move {
-                let result =
-                    retry_with_backoff(|| add_to_watchlist(&cr, &content_id, &content_type)).await;
-                tokio::time::sleep(WRITE_DELAY).await;
-                (title, result)
-            }
+        .take_while(|_| future::ready(!cancel.is_cancelled()))
+        .map(|item| async move {
+            let result = retry_with_backoff(|| {
+                crunchy.add_to_watchlist(&item.content_id, &item.content_type)
+            })
+            .await;
+            (&item.content_id, &item.title, result)
         })
-        .buffer_unordered(CONCURRENCY);
+        .buffer_unordered(crunchy.limiter().concurrency());
 
-    while let Some((title, result)) = results.next().await {
+    while let Some((content_id, title, result)) = results.next().await {
+        if result.as_ref().is_ok() || result.as_ref().is_err_and(is_conflict) {
+            target.watchlist_ids.insert(content_id.clone());
+        }
         match result {
             Ok(()) => {
-                reporter.log_success(&title);
+                reporter.log_success(title);
+                journal.record(DataType::Watchlist, content_id, Outcome::Added, None)?;
                 c.added += 1;
             }
             Err(e) if is_conflict(&e) => {
-                reporter.log_skip(&title);
+                reporter.log_skip(title);
+                journal.record(
+                    DataType::Watchlist,
+                    content_id,
+                    Outcome::AlreadyPresent,
+                    None,
+                )?;
                 c.already_present += 1;
             }
             Err(e) => {
                 reporter.log_error(&format!("{} -- {}", title, e));
+                journal.fail(Failure::new(DataType::Watchlist, content_id, title, &e))?;
                 c.failed += 1;
             }
         }
@@ -178,29 +613,73 @@ async fn import_watchlist(
     Ok(c)
 }
 
-async fn add_to_watchlist(
-    crunchy: &Crunchyroll,
-    content_id: &str,
-    content_type: &str,
-) -> Result<()> {
-    match content_type {
-        "series" => {
-            let series: crunchyroll_rs::Series = crunchy.media_from_id(content_id).await?;
-            series.add_to_watchlist().await?;
+/// Mark exported favourites as favourite on the target. Runs after `import_watchlist` so
+/// newly added entries exist, and covers entries that were already on the target too.
+/// Favourites that exist only on the target are left alone.
+async fn import_favourites<B: Backend>(
+    crunchy: &B,
+    export: &WatchlistExport,
+    target: &mut TargetState,
+    journal: &Journal,
+    reporter: &ProgressReporter,
+) -> Result<Counts> {
+    let favourites: Vec<_> = export.items.iter().filter(|i| i.is_favourite).collect();
+    let mut c = Counts::new(favourites.len());
+    reporter.progress(c.to_update(DataType::Favourites));
+
+    for item in favourites {
+        if reporter.is_cancelled() {
+            break;
+        }
+        if journal.is_done(DataType::Favourites, &item.content_id) {
+            c.skipped += 1;
+            reporter.progress(c.to_update(DataType::Favourites));
+            continue;
         }
-        "movie_listing" => {
-            let ml: crunchyroll_rs::MovieListing = crunchy.media_from_id(content_id).await?;
-            ml.add_to_watchlist().await?;
+        if target.watchlist_favourites.contains(&item.content_id) {
+            journal.record(
+                DataType::Favourites,
+                &item.content_id,
+                Outcome::AlreadyPresent,
+                None,
+            )?;
+            c.already_present += 1;
+            reporter.progress(c.to_update(DataType::Favourites));
+            continue;
+        }
+
+        match retry_with_backoff(|| crunchy.mark_favourite(&item.content_id, &item.content_type))
+            .await
+        {
+            Ok(()) => {
+                target.watchlist_favourites.insert(item.content_id.clone());
+                reporter.log_success(&format!("\u{2605} {}", item.title));
+                journal.record(DataType::Favourites, &item.content_id, Outcome::Added, None)?;
+                c.added += 1;
+            }
+            Err(e) => {
+                reporter.log_error(&format!("{} -- {}", item.title, e));
+                journal.fail(Failure::new(
+                    DataType::Favourites,
+                    &item.content_id,
+                    &item.title,
+                    &e,
+                ))?;
+                c.failed += 1;
+            }
         }
-        _ => anyhow::bail!("Unknown content type: {}", content_type),
+
+        reporter.progress(c.to_update(DataType::Favourites));
     }
-    Ok(())
+
+    Ok(c)
 }
 
-async fn import_crunchylists(
-    crunchy: &Crunchyroll,
+async fn import_crunchylists<B: Backend>(
+    crunchy: &B,
     export: &CrunchylistsExport,
-    target: &TargetState,
+    target: &mut TargetState,
+    journal: &Journal,
     reporter: &ProgressReporter,
 ) -> Result<Counts> {
     let total_items: usize = export.lists.iter().map(|l| l.items.len()).sum();
@@ -208,275 +687,356 @@ async fn import_crunchylists(
     reporter.progress(c.to_update(DataType::Crunchylists));
 
     for list_data in &export.lists {
-        let existing_items = target.crunchylists.get(&list_data.name);
+        if reporter.is_cancelled() {
+            break;
+        }
+        let existing_items = target.crunchylists.get(&list_data.name).cloned();
 
-        // Get or create the list on the target
-        let full_list = if existing_items.is_some() {
-            let lists = crunchy.crunchylists().await?;
-            let preview = lists
-                .items
-                .iter()
-                .find(|p| p.title == list_data.name)
-                .with_context(|| format!("Finding crunchylist '{}'", list_data.name))?;
+        let pending: Vec<_> = list_data
+            .items
+            .iter()
+            .filter(|item| {
+                let key = journal::list_item_key(&list_data.name, &item.content_id);
+                !journal.is_done(DataType::Crunchylists, &key)
+            })
+            .collect();
+        c.skipped += list_data.items.len() - pending.len();
+        if pending.is_empty() {
+            reporter.progress(c.to_update(DataType::Crunchylists));
+            continue;
+        }
+
+        // Get or create the list on the target. Looked up by name rather than via the
+        // target state, which is empty when resuming.
+        if crunchy.crunchylist_names().await?.contains(&list_data.name) {
             reporter.log_skip(&format!(
                 "'{}' already exists, checking items",
                 list_data.name
             ));
-            preview.crunchylist().await?
         } else {
-            let lists = crunchy.crunchylists().await?;
-            let preview = lists
-                .create(&list_data.name)
-                .await
-                .with_context(|| format!("Creating crunchylist '{}'", list_data.name))?;
+            crunchy.create_crunchylist(&list_data.name).await?;
             reporter.log_success(&format!("Created list '{}'", list_data.name));
-            preview.crunchylist().await?
-        };
-
-        for item in &list_data.items {
-            if existing_items.is_some_and(|ids| ids.contains(&item.content_id)) {
+        }
+        let on_target = target
+            .crunchylists
+            .entry(list_data.name.clone())
+            .or_default();
+
+        for item in pending {
+            if reporter.is_cancelled() {
+                break;
+            }
+            let key = journal::list_item_key(&list_data.name, &item.content_id);
+            if existing_items
+                .as_ref()
+                .is_some_and(|ids| ids.contains(&item.content_id))
+            {
+                journal.record(DataType::Crunchylists, &key, Outcome::AlreadyPresent, None)?;
                 c.already_present += 1;
                 reporter.progress(c.to_update(DataType::Crunchylists));
                 continue;
             }
 
-            match retry_with_backoff(|| add_to_crunchylist(crunchy, &full_list, &item.content_id))
-                .await
+            match retry_with_backoff(|| {
+                crunchy.add_to_crunchylist(&list_data.name, &item.content_id)
+            })
+            .await
             {
                 Ok(()) => {
                     reporter.log_success(&format!("  {} -> {}", list_data.name, item.title));
+                    journal.record(DataType::Crunchylists, &key, Outcome::Added, None)?;
+                    on_target.insert(item.content_id.clone());
                     c.added += 1;
                 }
                 Err(e) if is_conflict(&e) => {
+                    journal.record(DataType::Crunchylists, &key, Outcome::AlreadyPresent, None)?;
+                    on_target.insert(item.content_id.clone());
                     c.already_present += 1;
                 }
                 Err(e) => {
                     reporter.log_error(&format!("{} -- {}", item.title, e));
+                    journal.fail(
+                        Failure::new(DataType::Crunchylists, &item.content_id, &item.title, &e)
+                            .in_list(&list_data.name),
+                    )?;
                     c.failed += 1;
                 }
             }
 
             reporter.progress(c.to_update(DataType::Crunchylists));
-            tokio::time::sleep(WRITE_DELAY).await;
         }
     }
 
     Ok(c)
 }
 
-async fn add_to_crunchylist(
-    crunchy: &Crunchyroll,
-    list: &crunchyroll_rs::list::Crunchylist,
-    content_id: &str,
-) -> Result<()> {
-    if let Ok(series) = crunchy
-        .media_from_id::<crunchyroll_rs::Series>(content_id)
-        .await
-    {
-        list.add(MediaCollection::from(series)).await?;
-    } else if let Ok(ml) = crunchy
-        .media_from_id::<crunchyroll_rs::MovieListing>(content_id)
-        .await
-    {
-        list.add(MediaCollection::from(ml)).await?;
-    } else {
-        anyhow::bail!(
-            "Content {} not found as series or movie_listing",
-            content_id
-        );
-    }
-    Ok(())
-}
-
-async fn import_ratings(
-    crunchy: &Crunchyroll,
+async fn import_ratings<B: Backend>(
+    crunchy: &B,
     export: &RatingsExport,
+    target: &mut TargetState,
+    policy: RatingsPolicy,
+    journal: &Journal,
     reporter: &ProgressReporter,
 ) -> Result<Counts> {
     let mut c = Counts::new(export.items.len());
     reporter.progress(c.to_update(DataType::Ratings));
 
     for item in &export.items {
-        match retry_with_backoff(|| set_rating(crunchy, item)).await {
+        if reporter.is_cancelled() {
+            break;
+        }
+        if journal.is_done(DataType::Ratings, &item.content_id) {
+            c.skipped += 1;
+            reporter.progress(c.to_update(DataType::Ratings));
+            continue;
+        }
+
+        let existing = target.ratings.get(&item.content_id).map(String::as_str);
+        if !should_write_rating(policy, &item.rating, existing) {
+            if existing.is_some_and(|r| r != item.rating) {
+                reporter.log_skip(&format!(
+                    "{} (kept {} on target)",
+                    item.title,
+                    existing.unwrap_or_default()
+                ));
+            }
+            journal.record(
+                DataType::Ratings,
+                &item.content_id,
+                Outcome::AlreadyPresent,
+                None,
+            )?;
+            c.already_present += 1;
+            reporter.progress(c.to_update(DataType::Ratings));
+            continue;
+        }
+
+        match retry_with_backoff(|| {
+            crunchy.rate(&item.content_id, &item.content_type, &item.rating)
+        })
+        .await
+        {
             Ok(()) => {
+                target
+                    .ratings
+                    .insert(item.content_id.clone(), item.rating.clone());
                 reporter.log_success(&format!("{} ({})", item.title, item.rating));
+                journal.record(DataType::Ratings, &item.content_id, Outcome::Added, None)?;
                 c.added += 1;
             }
             Err(e) => {
                 reporter.log_error(&format!("{} -- {}", item.title, e));
+                journal.fail(Failure::new(
+                    DataType::Ratings,
+                    &item.content_id,
+                    &item.title,
+                    &e,
+                ))?;
                 c.failed += 1;
             }
         }
 
         reporter.progress(c.to_update(DataType::Ratings));
-        tokio::time::sleep(WRITE_DELAY).await;
     }
 
     Ok(c)
 }
 
-async fn set_rating(crunchy: &Crunchyroll, item: &RatingItem) -> Result<()> {
-    use crunchyroll_rs::media::RatingStar;
-
-    let stars = match item.rating.as_str() {
-        "OneStar" => RatingStar::OneStar,
-        "TwoStars" => RatingStar::TwoStars,
-        "ThreeStars" => RatingStar::ThreeStars,
-        "FourStars" => RatingStar::FourStars,
-        "FiveStars" => RatingStar::FiveStars,
-        other => anyhow::bail!("Unknown rating: {}", other),
+/// Decide whether the exported rating should be written, given what the target has.
+pub fn should_write_rating(policy: RatingsPolicy, source: &str, target: Option<&str>) -> bool {
+    let Some(target) = target else {
+        return true;
     };
+    if source == target {
+        return false;
+    }
+    match policy {
+        RatingsPolicy::SourceWins => true,
+        RatingsPolicy::TargetWins => false,
+        RatingsPolicy::Higher => star_count(source) > star_count(target),
+    }
+}
 
-    match item.content_type.as_str() {
-        "series" => {
-            let series: crunchyroll_rs::Series = crunchy.media_from_id(&item.content_id).await?;
-            series.rate(stars).await?;
-        }
-        "movie_listing" => {
-            let ml: crunchyroll_rs::MovieListing = crunchy.media_from_id(&item.content_id).await?;
-            ml.rate(stars).await?;
-        }
-        _ => anyhow::bail!("Unknown content type: {}", item.content_type),
+/// Number of stars in a rating string as exported (e.g. "FourStars" -> 4).
+fn star_count(rating: &str) -> u8 {
+    match rating {
+        "OneStar" => 1,
+        "TwoStars" => 2,
+        "ThreeStars" => 3,
+        "FourStars" => 4,
+        "FiveStars" => 5,
+        _ => 0,
     }
-    Ok(())
 }
 
-async fn import_history(
-    crunchy: &Crunchyroll,
+async fn import_history<B: Backend>(
+    crunchy: &B,
     export: &WatchHistoryExport,
-    target: &TargetState,
+    target: &mut TargetState,
+    ordered: bool,
+    journal: &Journal,
     reporter: &ProgressReporter,
 ) -> Result<Counts> {
     let mut c = Counts::new(export.items.len());
 
-    let to_import: Vec<_> = export
-        .items
-        .iter()
-        .filter(|item| !target.history_ids.contains(&item.content_id))
-        .collect();
-    c.already_present = export.items.len() - to_import.len();
+    // Entries the target already has are only rewritten when the export is further along;
+    // progress on the target is never moved backwards.
+    let mut to_import = Vec::new();
+    for item in &export.items {
+        if journal.is_done(DataType::History, &item.content_id) {
+            c.skipped += 1;
+            continue;
+        }
+        match target.history.get(&item.content_id) {
+            Some(existing) if existing.is_behind(item) => to_import.push((item, true)),
+            Some(_) => {
+                journal.record(
+                    DataType::History,
+                    &item.content_id,
+                    Outcome::AlreadyPresent,
+                    None,
+                )?;
+                c.already_present += 1;
+            }
+            None => to_import.push((item, false)),
+        }
+    }
     reporter.progress(c.to_update(DataType::History));
 
-    // Pre-fetch account_id once instead of per-request
-    let account_id: Arc<str> = crunchy.account().await?.account_id.into();
-
-    let mut results = stream::iter(to_import)
-        .map(|item| {
-            let cr = crunchy.clone();
-            let account_id = account_id.clone();
-            let content_id = item.content_id.clone();
-            let playhead = item.playhead;
-            let fully_watched = item.fully_watched;
-            let label = if item.title.is_empty() {
-                format!("{} - {}", item.series_title, item.content_id)
-            } else {
-                format!("{} - {}", item.series_title, item.title)
-            };
-            async move {
-                let result = if fully_watched {
-                    retry_with_backoff(|| mark_as_watched(&cr, &account_id, &content_id)).await
-                } else if playhead > 0 {
-                    // Restore playhead so the user can resume where they left off
-                    retry_with_backoff(|| set_playhead(&cr, &account_id, &content_id, playhead))
-                        .await
-                } else {
-                    Ok(())
-                };
-                tokio::time::sleep(WRITE_DELAY).await;
-                (label, result)
-            }
-        })
-        .buffer_unordered(CONCURRENCY);
+    // Once cancelled, stop pulling new items; in-flight writes still drain
+    let cancel = reporter.cancellation();
+    let keep_going = |_: &PendingWrite| future::ready(!cancel.is_cancelled());
+    let write = |(item, upgrade)| write_history_entry(crunchy, item, upgrade);
+    let concurrency = crunchy.limiter().concurrency();
+
+    let mut results = if ordered {
+        // Each series is its own sequential pipeline (pipelines run concurrently), then
+        // every series' latest entry is written one at a time, oldest first. The target
+        // orders shows by their latest activity, so that final pass decides the ordering.
+        let (bodies, heads) = ordered_batches(to_import);
+        let pipelines = stream::iter(bodies)
+            .map(|body| {
+                stream::iter(body)
+                    .take_while(keep_going)
+                    .then(write)
+                    .boxed_local()
+            })
+            .flatten_unordered(concurrency);
+        let finals = stream::iter(heads).take_while(keep_going).then(write);
+        pipelines.chain(finals).boxed_local()
+    } else {
+        stream::iter(to_import)
+            .take_while(keep_going)
+            .map(write)
+            .buffer_unordered(concurrency)
+            .boxed_local()
+    };
 
-    while let Some((label, result)) = results.next().await {
+    let mut written = HashSet::new();
+    while let Some((content_id, label, result)) = results.next().await {
         match result {
             Ok(()) => {
                 reporter.log_success(&label);
+                journal.record(DataType::History, &content_id, Outcome::Added, None)?;
+                written.insert(content_id);
                 c.added += 1;
             }
             Err(e) => {
                 reporter.log_error(&format!("{} -- {}", label, e));
+                journal.fail(Failure::new(DataType::History, &content_id, &label, &e))?;
                 c.failed += 1;
             }
         }
         reporter.progress(c.to_update(DataType::History));
     }
+    drop(results);
+
+    // Written entries now match the export; nothing is written unless it moves progress on
+    for item in export
+        .items
+        .iter()
+        .filter(|i| written.contains(&i.content_id))
+    {
+        let state = PlayheadState {
+            playhead: item.playhead,
+            fully_watched: item.fully_watched,
+        };
+        target.history.insert(item.content_id.clone(), state);
+    }
 
     Ok(c)
 }
 
-async fn set_playhead(
-    crunchy: &Crunchyroll,
-    account_id: &str,
-    content_id: &str,
-    playhead: u32,
-) -> Result<()> {
-    let url = format!(
-        "https://www.crunchyroll.com/content/v2/{}/playheads",
-        account_id
-    );
-    let status = crunchy
-        .client()
-        .post(&url)
-        .header(
-            "Authorization",
-            format!("Bearer {}", crunchy.access_token().await),
-        )
-        .json(&serde_json::json!({
-            "content_id": content_id,
-            "playhead": playhead
-        }))
-        .send()
-        .await?
-        .status()
-        .as_u16();
-
-    check_status(status, "set_playhead", content_id)
-}
-
-async fn mark_as_watched(crunchy: &Crunchyroll, account_id: &str, content_id: &str) -> Result<()> {
-    let url = format!(
-        "https://www.crunchyroll.com/content/v2/discover/{}/mark_as_watched/{}",
-        account_id, content_id
-    );
-    let status = crunchy
-        .client()
-        .post(&url)
-        .header(
-            "Authorization",
-            format!("Bearer {}", crunchy.access_token().await),
-        )
-        .json(&serde_json::json!({}))
-        .send()
-        .await?
-        .status()
-        .as_u16();
+/// A history entry still to be written, and whether it upgrades progress the target
+/// already has.
+pub type PendingWrite<'a> = (&'a WatchHistoryItem, bool);
+
+/// Split pending history writes for ordered replay: per-series runs of entries (oldest
+/// first, excluding each series' latest entry), and the latest entry of every series
+/// sorted oldest first.
+pub fn ordered_batches(
+    items: Vec<PendingWrite<'_>>,
+) -> (Vec<Vec<PendingWrite<'_>>>, Vec<PendingWrite<'_>>) {
+    let mut items = items;
+    items.sort_by_key(|(item, _)| item.date_played);
+
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    let mut groups: Vec<Vec<PendingWrite>> = Vec::new();
+    for entry in items {
+        let i = *index.entry(entry.0.parent_id.as_str()).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[i].push(entry);
+    }
 
-    check_status(status, "mark_as_watched", content_id)
+    let mut heads: Vec<_> = groups.iter_mut().filter_map(|g| g.pop()).collect();
+    heads.sort_by_key(|(item, _)| item.date_played);
+    groups.retain(|g| !g.is_empty());
+    (groups, heads)
 }
 
-/// Treat 2xx and 409 (already exists) as OK; fail on anything else.
-fn check_status(status: u16, operation: &str, content_id: &str) -> Result<()> {
-    if (200..300).contains(&status) || status == 409 {
-        Ok(())
+/// Restore one history entry on the target. Returns (content_id, log label, result).
+async fn write_history_entry<B: Backend>(
+    crunchy: &B,
+    item: &WatchHistoryItem,
+    upgrade: bool,
+) -> (String, String, Result<()>) {
+    let content_id = item.content_id.as_str();
+    let mut label = if item.title.is_empty() {
+        format!("{} - {}", item.series_title, item.content_id)
     } else {
-        anyhow::bail!("{} returned {} for {}", operation, status, content_id)
+        format!("{} - {}", item.series_title, item.title)
+    };
+    if upgrade {
+        label.push_str(" (caught up)");
     }
+
+    let result = if item.fully_watched {
+        retry_with_backoff(|| crunchy.mark_as_watched(content_id)).await
+    } else if item.playhead > 0 {
+        // Restore playhead so the user can resume where they left off
+        retry_with_backoff(|| crunchy.set_playhead(content_id, item.playhead)).await
+    } else {
+        Ok(())
+    };
+    (content_id.to_string(), label, result)
 }
 
-async fn retry_with_backoff<F, Fut>(mut f: F) -> Result<()>
+pub async fn retry_with_backoff<T, F, Fut>(mut f: F) -> Result<T>
 where
     F: FnMut() -> Fut,
-    Fut: std::future::Future<Output = Result<()>>,
+    Fut: std::future::Future<Output = Result<T>>,
 {
     let mut delay = INITIAL_BACKOFF;
 
     for attempt in 0..=MAX_RETRIES {
         match f().await {
-            Ok(()) => return Ok(()),
-            Err(e) if attempt < MAX_RETRIES && is_cloudflare_block(&e) => {
-                eprintln!("  Cloudflare block detected, waiting 60s before retry...");
-                tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(value) => return Ok(value),
+            // The limiter has already slowed down or paused every request, Retry-After
+            // included, so waiting for its next token is all the backoff needed (or it
+            // tripped its breaker, which fails the retry straight away)
+            Err(e) if attempt < MAX_RETRIES && (is_cloudflare_block(&e) || is_rate_limited(&e)) => {
             }
             Err(e) if attempt < MAX_RETRIES && is_transient(&e) => {
                 tokio::time::sleep(delay).await;
@@ -488,31 +1048,40 @@ where
     unreachable!()
 }
 
-fn is_cloudflare_block(e: &anyhow::Error) -> bool {
+pub fn is_cloudflare_block(e: &anyhow::Error) -> bool {
     e.downcast_ref::<crunchyroll_rs::error::Error>()
         .is_some_and(|ce| matches!(ce, crunchyroll_rs::error::Error::Block { .. }))
 }
 
+pub fn is_rate_limited(e: &anyhow::Error) -> bool {
+    if let Some(crunchyroll_rs::error::Error::Request {
+        status: Some(code), ..
+    }) = e.downcast_ref::<crunchyroll_rs::error::Error>()
+    {
+        return code.as_u16() == 429;
+    }
+    e.to_string().contains("429")
+}
+
+/// Server errors and timeouts, which get a backoff of their own.
 fn is_transient(e: &anyhow::Error) -> bool {
     // Check structured error first for status code
     if let Some(crunchyroll_rs::error::Error::Request {
         status: Some(code), ..
     }) = e.downcast_ref::<crunchyroll_rs::error::Error>()
     {
-        let n = code.as_u16();
-        return n == 429 || (500..=504).contains(&n);
+        return (500..=504).contains(&code.as_u16());
     }
     // Fallback to string matching for non-library errors (e.g. reqwest)
     let msg = e.to_string();
-    msg.contains("429")
-        || msg.contains("500")
+    msg.contains("500")
         || msg.contains("502")
         || msg.contains("503")
         || msg.contains("504")
         || msg.contains("timeout")
 }
 
-fn is_conflict(e: &anyhow::Error) -> bool {
+pub fn is_conflict(e: &anyhow::Error) -> bool {
     if let Some(crunchyroll_rs::error::Error::Request {
         status: Some(code), ..
     }) = e.downcast_ref::<crunchyroll_rs::error::Error>()
@@ -522,7 +1091,77 @@ fn is_conflict(e: &anyhow::Error) -> bool {
     false
 }
 
-fn print_summary(sections: &[(&str, &Counts)]) {
+/// Before a resumed import, report what the journal says is still left to do.
+fn print_resume_plan(journal: &Journal, exports: &Exports) {
+    let Exports {
+        watchlist,
+        history,
+        crunchylists,
+        ratings,
+        selection,
+    } = exports;
+    let mut wl = Counts::new(watchlist.items.len());
+    wl.skipped = watchlist
+        .items
+        .iter()
+        .filter(|i| journal.is_done(DataType::Watchlist, &i.content_id))
+        .count();
+
+    let favourites: Vec<_> = watchlist.items.iter().filter(|i| i.is_favourite).collect();
+    let mut fv = Counts::new(favourites.len());
+    fv.skipped = favourites
+        .iter()
+        .filter(|i| journal.is_done(DataType::Favourites, &i.content_id))
+        .count();
+
+    let mut cl = Counts::new(crunchylists.lists.iter().map(|l| l.items.len()).sum());
+    cl.skipped = crunchylists
+        .lists
+        .iter()
+        .flat_map(|l| {
+            l.items
+                .iter()
+                .map(|i| journal::list_item_key(&l.name, &i.content_id))
+        })
+        .filter(|key| journal.is_done(DataType::Crunchylists, key))
+        .count();
+
+    let mut rt = Counts::new(ratings.items.len());
+    rt.skipped = ratings
+        .items
+        .iter()
+        .filter(|i| journal.is_done(DataType::Ratings, &i.content_id))
+        .count();
+
+    let mut hi = Counts::new(history.items.len());
+    hi.skipped = history
+        .items
+        .iter()
+        .filter(|i| journal.is_done(DataType::History, &i.content_id))
+        .count();
+
+    println!("Resuming from {}:", journal::JOURNAL_FILE);
+    for (data_type, c) in [
+        (DataType::Watchlist, &wl),
+        (DataType::Favourites, &fv),
+        (DataType::Crunchylists, &cl),
+        (DataType::Ratings, &rt),
+        (DataType::History, &hi),
+    ] {
+        if !selection.includes(data_type) {
+            continue;
+        }
+        println!(
+            "  {:14} {} done, {} remaining",
+            data_type,
+            c.skipped,
+            c.remaining()
+        );
+    }
+    println!();
+}
+
+pub fn print_summary(sections: &[(DataType, Counts)]) {
     println!("\n  Import Summary");
     println!("  {}", "\u{2500}".repeat(50));
 
@@ -530,13 +1169,16 @@ fn print_summary(sections: &[(&str, &Counts)]) {
     let mut total_already = 0;
     let mut total_failed = 0;
 
-    for (name, c) in sections {
+    for (data_type, c) in sections {
         println!(
             "  {:14} {} added, {} already there, {} failed",
-            name, c.added, c.already_present, c.failed
+            data_type,
+            c.added,
+            c.already_present + c.skipped,
+            c.failed
         );
         total_added += c.added;
-        total_already += c.already_present;
+        total_already += c.already_present + c.skipped;
         total_failed += c.failed;
     }
 
@@ -550,6 +1192,7 @@ fn print_summary(sections: &[(&str, &Counts)]) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixtures::{history_item, history_item_at};
 
     #[test]
     fn counts_new_starts_at_zero() {
@@ -557,6 +1200,7 @@ mod tests {
         assert_eq!(c.total, 42);
         assert_eq!(c.added, 0);
         assert_eq!(c.already_present, 0);
+        assert_eq!(c.skipped, 0);
         assert_eq!(c.failed, 0);
         assert_eq!(c.processed(), 0);
     }
@@ -570,6 +1214,16 @@ mod tests {
         assert_eq!(c.processed(), 9);
     }
 
+    #[test]
+    fn counts_skipped_are_processed_but_not_remaining() {
+        let mut c = Counts::new(10);
+        c.skipped = 6;
+        c.added = 1;
+        assert_eq!(c.processed(), 7);
+        assert_eq!(c.remaining(), 4);
+        assert_eq!(c.to_update(crate::ui::DataType::History).skipped, 6);
+    }
+
     #[test]
     fn counts_to_update_maps_correctly() {
         let mut c = Counts::new(10);
@@ -584,10 +1238,94 @@ mod tests {
         assert_eq!(u.failed, 1);
     }
 
+    #[test]
+    fn ratings_missing_on_target_are_always_written() {
+        for policy in [
+            RatingsPolicy::SourceWins,
+            RatingsPolicy::TargetWins,
+            RatingsPolicy::Higher,
+        ] {
+            assert!(should_write_rating(policy, "ThreeStars", None));
+        }
+    }
+
+    #[test]
+    fn ratings_identical_on_target_are_skipped() {
+        for policy in [
+            RatingsPolicy::SourceWins,
+            RatingsPolicy::TargetWins,
+            RatingsPolicy::Higher,
+        ] {
+            assert!(!should_write_rating(policy, "FourStars", Some("FourStars")));
+        }
+    }
+
+    #[test]
+    fn ratings_conflicts_follow_policy() {
+        let (src, tgt) = ("TwoStars", Some("FiveStars"));
+        assert!(should_write_rating(RatingsPolicy::SourceWins, src, tgt));
+        assert!(!should_write_rating(RatingsPolicy::TargetWins, src, tgt));
+        assert!(!should_write_rating(RatingsPolicy::Higher, src, tgt));
+        assert!(should_write_rating(
+            RatingsPolicy::Higher,
+            "FiveStars",
+            Some("OneStar")
+        ));
+    }
+
+    #[test]
+    fn playhead_behind_when_source_further_along() {
+        let target = PlayheadState {
+            playhead: 10,
+            fully_watched: false,
+        };
+        assert!(target.is_behind(&history_item("E1", 600, false)));
+        assert!(target.is_behind(&history_item("E1", 0, true)));
+    }
+
+    #[test]
+    fn playhead_never_downgrades() {
+        let partial = PlayheadState {
+            playhead: 600,
+            fully_watched: false,
+        };
+        assert!(!partial.is_behind(&history_item("E1", 10, false)));
+        assert!(!partial.is_behind(&history_item("E1", 600, false)));
+
+        let finished = PlayheadState {
+            playhead: 0,
+            fully_watched: true,
+        };
+        assert!(!finished.is_behind(&history_item("E1", 1400, true)));
+        assert!(!finished.is_behind(&history_item("E1", 900, false)));
+    }
+
+    #[test]
+    fn ordered_batches_keeps_series_order_and_sorts_heads() {
+        use chrono::TimeZone;
+        let day = |d| chrono::Utc.with_ymd_and_hms(2026, 1, d, 12, 0, 0).unwrap();
+        let items = [
+            history_item_at("B2", "B", day(5)),
+            history_item_at("A1", "A", day(1)),
+            history_item_at("B1", "B", day(2)),
+            history_item_at("A2", "A", day(3)),
+            history_item_at("A3", "A", day(4)),
+            history_item_at("C1", "C", day(6)),
+        ];
+        let (bodies, heads) = ordered_batches(items.iter().map(|i| (i, false)).collect());
+
+        let ids = |v: &[PendingWrite]| -> Vec<String> {
+            v.iter().map(|(i, _)| i.content_id.clone()).collect()
+        };
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(ids(&bodies[0]), ["A1", "A2"]);
+        assert_eq!(ids(&bodies[1]), ["B1"]);
+        assert_eq!(ids(&heads), ["A3", "B2", "C1"]);
+    }
+
     #[test]
     fn is_transient_matches_status_strings() {
         // The string-matching fallback path for non-crunchyroll-rs errors
-        assert!(is_transient(&anyhow::anyhow!("server returned 429")));
         assert!(is_transient(&anyhow::anyhow!("got 500 internal")));
         assert!(is_transient(&anyhow::anyhow!("502 bad gateway")));
         assert!(is_transient(&anyhow::anyhow!("503 unavailable")));
@@ -602,6 +1340,13 @@ mod tests {
         assert!(!is_transient(&anyhow::anyhow!("400 bad request")));
     }
 
+    #[test]
+    fn rate_limits_are_not_transient() {
+        let e = anyhow::anyhow!("server returned 429");
+        assert!(is_rate_limited(&e));
+        assert!(!is_transient(&e));
+    }
+
     #[test]
     fn is_conflict_rejects_plain_errors() {
         // Plain anyhow errors don't contain crunchyroll_rs error types
@@ -0,0 +1,212 @@
+use crate::crypt;
+use crate::failures::{Failure, Failures};
+use crate::ui::DataType;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+pub const JOURNAL_FILE: &str = "import_journal.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Added,
+    AlreadyPresent,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    data_type: DataType,
+    key: String,
+    outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    at: DateTime<Utc>,
+}
+
+/// Append-only log of per-item import outcomes, one JSON object per line.
+/// Every line is written and flushed as soon as the item settles, so an interrupted
+/// import leaves behind an accurate record that `import --resume` can pick up from.
+/// Failed items are also kept in `failures.json` until a later attempt settles them.
+pub struct Journal {
+    /// Latest outcome per (data type, key) from previous runs
+    previous: HashMap<(DataType, String), Outcome>,
+    file: Mutex<File>,
+    failures: Failures,
+}
+
+impl Journal {
+    /// Start a fresh journal, discarding any previous one in `dir`.
+    pub fn create(dir: &Path) -> Result<Self> {
+        let path = dir.join(JOURNAL_FILE);
+        let file = File::create(&path).with_context(|| format!("Creating {}", path.display()))?;
+        Ok(Self {
+            previous: HashMap::new(),
+            file: Mutex::new(file),
+            failures: Failures::create(dir)?,
+        })
+    }
+
+    /// Load the existing journal in `dir` (if any) and append to it.
+    pub fn resume(dir: &Path) -> Result<Self> {
+        let path = dir.join(JOURNAL_FILE);
+        let previous = match std::fs::read_to_string(&path) {
+            Ok(content) => {
+                parse(&content).with_context(|| format!("Reading {}", path.display()))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Opening {}", path.display()))?;
+        Ok(Self {
+            previous,
+            file: Mutex::new(file),
+            failures: Failures::load(dir)?,
+        })
+    }
+
+    /// Whether a previous run already settled this item (added or already present).
+    pub fn is_done(&self, data_type: DataType, key: &str) -> bool {
+        matches!(
+            self.previous.get(&(data_type, key.to_string())),
+            Some(Outcome::Added | Outcome::AlreadyPresent)
+        )
+    }
+
+    pub fn record(
+        &self,
+        data_type: DataType,
+        key: &str,
+        outcome: Outcome,
+        error: Option<String>,
+    ) -> Result<()> {
+        if outcome != Outcome::Failed {
+            self.failures.resolve(data_type, key)?;
+        }
+        let entry = Entry {
+            data_type,
+            key: key.to_string(),
+            outcome,
+            error,
+            at: Utc::now(),
+        };
+        let mut line = crypt::seal_if_encrypted(serde_json::to_string(&entry)?)?;
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())
+            .and_then(|()| file.flush())
+            .context("Writing import journal")
+    }
+
+    /// Record a failed item in the journal and the failures file.
+    pub fn fail(&self, failure: Failure) -> Result<()> {
+        self.record(
+            failure.data_type,
+            &failure.key(),
+            Outcome::Failed,
+            Some(failure.error.clone()),
+        )?;
+        self.failures.add(failure)
+    }
+
+    pub fn failures(&self) -> &Failures {
+        &self.failures
+    }
+}
+
+/// Journal key for an item inside a crunchylist; the same series can appear in several lists.
+pub fn list_item_key(list_name: &str, content_id: &str) -> String {
+    format!("{}/{}", list_name, content_id)
+}
+
+fn parse(content: &str) -> Result<HashMap<(DataType, String), Outcome>> {
+    let mut entries = HashMap::new();
+    for line in content.lines() {
+        // A line torn by an interrupted write fails to parse and is ignored, but a whole
+        // line that can't be decrypted means the wrong passphrase
+        let Ok(doc) = serde_json::from_str::<serde_json::Value>(line) else {
+            continue;
+        };
+        let line = if crypt::is_sealed(&doc) {
+            String::from_utf8(crypt::open(doc)?)?
+        } else {
+            line.to_string()
+        };
+        if let Ok(e) = serde_json::from_str::<Entry>(&line) {
+            entries.insert((e.data_type, e.key), e.outcome);
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_keeps_latest_outcome() {
+        let content = concat!(
+            r#"{"data_type":"history","key":"E1","outcome":"failed","error":"503","at":"2026-01-01T00:00:00Z"}"#,
+            "\n",
+            r#"{"data_type":"history","key":"E1","outcome":"added","at":"2026-01-01T00:01:00Z"}"#,
+            "\n",
+        );
+        let parsed = parse(content).unwrap();
+        assert_eq!(
+            parsed.get(&(DataType::History, "E1".to_string())),
+            Some(&Outcome::Added)
+        );
+    }
+
+    #[test]
+    fn parse_ignores_torn_last_line() {
+        let content = concat!(
+            r#"{"data_type":"watchlist","key":"S1","outcome":"added","at":"2026-01-01T00:00:00Z"}"#,
+            "\n",
+            r#"{"data_type":"watchlist","key":"S2","outc"#,
+        );
+        let parsed = parse(content).unwrap();
+        assert_eq!(parsed.len(), 1);
+    }
+
+    #[test]
+    fn resume_skips_only_settled_items() {
+        let dir = std::env::temp_dir().join(format!("journal-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let journal = Journal::create(&dir).unwrap();
+        journal
+            .record(DataType::Watchlist, "S1", Outcome::Added, None)
+            .unwrap();
+        journal
+            .record(DataType::Watchlist, "S2", Outcome::AlreadyPresent, None)
+            .unwrap();
+        journal
+            .record(
+                DataType::Watchlist,
+                "S3",
+                Outcome::Failed,
+                Some("boom".to_string()),
+            )
+            .unwrap();
+        drop(journal);
+
+        let resumed = Journal::resume(&dir).unwrap();
+        assert!(resumed.is_done(DataType::Watchlist, "S1"));
+        assert!(resumed.is_done(DataType::Watchlist, "S2"));
+        assert!(!resumed.is_done(DataType::Watchlist, "S3"));
+        assert!(!resumed.is_done(DataType::History, "S1"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
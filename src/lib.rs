@@ -1 +1,3 @@
+pub mod crypt;
+pub mod csv_format;
 pub mod models;
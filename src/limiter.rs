@@ -0,0 +1,375 @@
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::Mutex;
+use tokio::sync::{Semaphore, SemaphorePermit, watch};
+use tokio::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+pub const DEFAULT_RATE: f64 = 5.0;
+pub const DEFAULT_CONCURRENCY: usize = 5;
+
+/// Never slow down below one request every 10 seconds
+const MIN_RATE: f64 = 0.1;
+/// Consecutive successes before speeding back up
+const SPEED_UP_AFTER: u32 = 20;
+const SPEED_UP_FACTOR: f64 = 1.25;
+/// Rate limits reported this soon after slowing down came from requests that were already
+/// in flight, so they don't slow down further
+const SLOW_DOWN_COOLDOWN: Duration = Duration::from_secs(2);
+/// How long everything waits after Cloudflare blocks a request
+pub const BLOCK_PAUSE: Duration = Duration::from_secs(60);
+/// Cloudflare blocks in a row (with no successful request between them) before giving up
+pub const MAX_BLOCKS: u32 = 3;
+/// Waiters wake up to this fraction of the pause late, so they don't all fire at once
+const PAUSE_JITTER: f64 = 0.1;
+
+/// How a request went, as far as pacing is concerned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Success,
+    /// HTTP 429, with the server's Retry-After when it sent one
+    RateLimited(Option<Duration>),
+    /// Cloudflare bot protection page
+    Blocked,
+    /// Any other error; doesn't change the pace
+    Failed,
+}
+
+/// Why every request is on hold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PauseReason {
+    RateLimited,
+    Blocked { count: u32 },
+}
+
+impl fmt::Display for PauseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PauseReason::RateLimited => write!(f, "rate limited (Retry-After)"),
+            PauseReason::Blocked { count } => {
+                write!(f, "Cloudflare block {}/{}", count, MAX_BLOCKS)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pause {
+    pub until: Instant,
+    pub reason: PauseReason,
+}
+
+/// Error returned once the circuit breaker has tripped: Cloudflare keeps blocking us, so
+/// every further request would only dig the hole deeper.
+#[derive(Debug)]
+pub struct CircuitOpen;
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Stopped after {} Cloudflare blocks in a row. Wait a while (or switch networks) before trying again",
+            MAX_BLOCKS
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+/// Token bucket shared by every API request in a run. Requests are spaced out to the
+/// current rate and at most `concurrency` are in flight at once. The rate halves on a 429
+/// and creeps back up towards the configured maximum while requests keep succeeding.
+///
+/// A Retry-After or a Cloudflare block pauses every request, not just the one that hit it.
+/// Too many blocks in a row trip a circuit breaker that fails all further requests.
+pub struct RateLimiter {
+    max_rate: f64,
+    concurrency: usize,
+    in_flight: Semaphore,
+    bucket: Mutex<Bucket>,
+    pause: watch::Sender<Option<Pause>>,
+    breaker: CancellationToken,
+}
+
+struct Bucket {
+    /// Requests per second
+    rate: f64,
+    /// Holds at most one token, so requests never burst
+    tokens: f64,
+    refilled: Instant,
+    successes: u32,
+    slowed_down: Option<Instant>,
+    /// Cloudflare blocks since the last successful request
+    blocks: u32,
+}
+
+impl Bucket {
+    /// Take a token, or say how long until one is available.
+    fn take(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        let earned = now.duration_since(self.refilled).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + earned).min(1.0);
+        self.refilled = now;
+        if self.tokens >= 1.0 - f64::EPSILON {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(max_rate: f64, concurrency: usize) -> Self {
+        Self {
+            max_rate,
+            concurrency,
+            in_flight: Semaphore::new(concurrency),
+            bucket: Mutex::new(Bucket {
+                rate: max_rate,
+                tokens: 1.0,
+                refilled: Instant::now(),
+                successes: 0,
+                slowed_down: None,
+                blocks: 0,
+            }),
+            pause: watch::Sender::new(None),
+            breaker: CancellationToken::new(),
+        }
+    }
+
+    /// Wait for a free slot, the end of any pause, and a token. Hold the permit until the
+    /// request completes.
+    pub async fn acquire(&self) -> Result<SemaphorePermit<'_>, CircuitOpen> {
+        let permit = self
+            .in_flight
+            .acquire()
+            .await
+            .expect("limiter semaphore is never closed");
+        loop {
+            self.check()?;
+            if let Some(pause) = self.paused() {
+                let length = pause.until.saturating_duration_since(Instant::now());
+                tokio::time::sleep_until(pause.until + jitter(length)).await;
+                continue;
+            }
+            let wait = self.bucket.lock().unwrap().take();
+            match wait {
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => return Ok(permit),
+            }
+        }
+    }
+
+    /// Adapt the pace to how a request went.
+    pub fn record(&self, outcome: Outcome) {
+        let mut bucket = self.bucket.lock().unwrap();
+        match outcome {
+            Outcome::Success => {
+                bucket.blocks = 0;
+                bucket.successes += 1;
+                if bucket.successes >= SPEED_UP_AFTER {
+                    bucket.rate = (bucket.rate * SPEED_UP_FACTOR).min(self.max_rate);
+                    bucket.successes = 0;
+                }
+            }
+            Outcome::RateLimited(retry_after) => {
+                bucket.successes = 0;
+                if let Some(wait) = retry_after {
+                    self.pause_for(wait, PauseReason::RateLimited);
+                }
+                if bucket
+                    .slowed_down
+                    .is_some_and(|at| at.elapsed() < SLOW_DOWN_COOLDOWN)
+                {
+                    return;
+                }
+                bucket.rate = (bucket.rate / 2.0).max(MIN_RATE);
+                bucket.slowed_down = Some(Instant::now());
+            }
+            Outcome::Blocked => {
+                bucket.successes = 0;
+                // Requests that were already in flight when the pause started get blocked
+                // too; they're the same block, not another one
+                if self.paused().is_some() {
+                    return;
+                }
+                bucket.blocks += 1;
+                if bucket.blocks >= MAX_BLOCKS {
+                    self.breaker.cancel();
+                } else {
+                    let count = bucket.blocks;
+                    self.pause_for(BLOCK_PAUSE, PauseReason::Blocked { count });
+                }
+            }
+            Outcome::Failed => {}
+        }
+    }
+
+    /// Hold every request until `wait` has passed. Never shortens a longer pause.
+    fn pause_for(&self, wait: Duration, reason: PauseReason) {
+        let until = Instant::now() + wait;
+        self.pause.send_if_modified(|pause| {
+            if pause.is_some_and(|p| p.until >= until) {
+                return false;
+            }
+            *pause = Some(Pause { until, reason });
+            true
+        });
+    }
+
+    /// The pause in effect right now, if any.
+    pub fn paused(&self) -> Option<Pause> {
+        let pause = (*self.pause.borrow())?;
+        (pause.until > Instant::now()).then_some(pause)
+    }
+
+    /// Notified every time a new pause starts.
+    pub fn subscribe(&self) -> watch::Receiver<Option<Pause>> {
+        self.pause.subscribe()
+    }
+
+    /// Fail once the circuit breaker has tripped.
+    pub fn check(&self) -> Result<(), CircuitOpen> {
+        if self.breaker.is_cancelled() {
+            Err(CircuitOpen)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Cancelled when the circuit breaker trips, so pipelines stop scheduling new work.
+    pub fn breaker(&self) -> &CancellationToken {
+        &self.breaker
+    }
+
+    /// Current requests per second.
+    pub fn rate(&self) -> f64 {
+        self.bucket.lock().unwrap().rate
+    }
+
+    pub fn max_rate(&self) -> f64 {
+        self.max_rate
+    }
+
+    /// Maximum requests in flight; pipelines size their buffers to this.
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+}
+
+/// Random delay of up to `PAUSE_JITTER` of `length`.
+fn jitter(length: Duration) -> Duration {
+    // Every RandomState is seeded differently, which is all the randomness this needs
+    let random = std::collections::hash_map::RandomState::new().hash_one(0u8);
+    length.mul_f64(PAUSE_JITTER * (random % 1000) as f64 / 1000.0)
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(DEFAULT_RATE, DEFAULT_CONCURRENCY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn spaces_requests_to_the_rate() {
+        let limiter = RateLimiter::new(2.0, 5);
+        let start = Instant::now();
+        for _ in 0..5 {
+            drop(limiter.acquire().await.unwrap());
+        }
+        // The first request goes out immediately, then one every 500ms
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caps_requests_in_flight() {
+        let limiter = RateLimiter::new(1000.0, 2);
+        let first = limiter.acquire().await.unwrap();
+        let _second = limiter.acquire().await.unwrap();
+        let third = tokio::time::timeout(Duration::from_secs(5), limiter.acquire()).await;
+        assert!(third.is_err());
+
+        drop(first);
+        let third = tokio::time::timeout(Duration::from_secs(5), limiter.acquire()).await;
+        assert!(third.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slows_down_on_rate_limits_and_recovers() {
+        let limiter = RateLimiter::new(4.0, 5);
+        limiter.record(Outcome::RateLimited(None));
+        assert_eq!(limiter.rate(), 2.0);
+        // Other requests from the same burst don't compound the slowdown
+        limiter.record(Outcome::RateLimited(None));
+        assert_eq!(limiter.rate(), 2.0);
+
+        tokio::time::advance(SLOW_DOWN_COOLDOWN).await;
+        limiter.record(Outcome::RateLimited(None));
+        assert_eq!(limiter.rate(), 1.0);
+
+        for _ in 0..SPEED_UP_AFTER {
+            limiter.record(Outcome::Success);
+        }
+        assert_eq!(limiter.rate(), 1.25);
+        for _ in 0..SPEED_UP_AFTER * 20 {
+            limiter.record(Outcome::Success);
+        }
+        assert_eq!(limiter.rate(), 4.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_after_pauses_every_request() {
+        let limiter = RateLimiter::new(1000.0, 5);
+        let start = Instant::now();
+        limiter.record(Outcome::RateLimited(Some(Duration::from_secs(30))));
+        assert_eq!(limiter.paused().unwrap().reason, PauseReason::RateLimited);
+
+        // A shorter Retry-After from the same burst doesn't cut the pause short
+        limiter.record(Outcome::RateLimited(Some(Duration::from_secs(5))));
+        let (a, b) = tokio::join!(limiter.acquire(), limiter.acquire());
+        assert!(a.is_ok() && b.is_ok());
+        let waited = start.elapsed();
+        assert!(waited >= Duration::from_secs(30), "{:?}", waited);
+        assert!(waited <= Duration::from_secs(34), "{:?}", waited);
+        assert!(limiter.paused().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repeated_blocks_trip_the_breaker() {
+        let limiter = RateLimiter::new(1000.0, 5);
+        limiter.record(Outcome::Blocked);
+        // In-flight requests blocked during the pause count as the same block
+        limiter.record(Outcome::Blocked);
+        assert_eq!(
+            limiter.paused().unwrap().reason,
+            PauseReason::Blocked { count: 1 }
+        );
+
+        // A success in between resets the count
+        drop(limiter.acquire().await.unwrap());
+        limiter.record(Outcome::Success);
+        limiter.record(Outcome::Blocked);
+        assert_eq!(
+            limiter.paused().unwrap().reason,
+            PauseReason::Blocked { count: 1 }
+        );
+
+        for count in 2..MAX_BLOCKS {
+            drop(limiter.acquire().await.unwrap());
+            limiter.record(Outcome::Blocked);
+            assert_eq!(
+                limiter.paused().unwrap().reason,
+                PauseReason::Blocked { count }
+            );
+        }
+        drop(limiter.acquire().await.unwrap());
+        limiter.record(Outcome::Blocked);
+        assert!(limiter.breaker().is_cancelled());
+        assert!(limiter.acquire().await.is_err());
+    }
+}
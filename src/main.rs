@@ -1,15 +1,39 @@
+mod archive;
 mod auth;
+mod backend;
+mod cassette;
+mod changes;
+mod client;
+mod crypt;
+mod csv_format;
 mod diff;
 mod export;
+mod failures;
+#[cfg(test)]
+mod fake_backend;
+#[cfg(test)]
+mod fixtures;
 mod import;
+mod journal;
+mod limiter;
+#[cfg(feature = "mock-server")]
+mod mock_server;
 mod models;
+mod plan;
+mod selection;
+mod target_cache;
 mod ui;
+mod validate;
+mod verify;
 
 use anyhow::Context;
+use backend::CrunchyrollBackend;
 use clap::{Parser, Subcommand};
+use client::ClientOptions;
 use crunchyroll_rs::list::WatchlistOptions;
 use futures_util::StreamExt;
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "crunchyroll-migrate")]
@@ -17,6 +41,58 @@ use std::path::PathBuf;
 struct Cli {
     #[command(subcommand)]
     command: Option<Command>,
+
+    /// Send API requests here instead of https://www.crunchyroll.com (reverse proxy or local stand-in).
+    /// Requests then go out one at a time, whatever --concurrency says
+    #[arg(long, global = true, env = "CRUNCHYROLL_API_BASE", value_name = "URL")]
+    api_base: Option<String>,
+
+    /// Don't check the certificate of a stand-in server at a loopback --api-base (the mock server)
+    #[arg(long, global = true, hide = true, requires = "api_base")]
+    insecure: bool,
+
+    /// Save every API request and response to DIR/cassette.jsonl, credentials and tokens redacted.
+    /// Requests then go out one at a time, whatever --concurrency says
+    #[arg(long, global = true, value_name = "DIR")]
+    record: Option<PathBuf>,
+
+    /// Answer API requests from a cassette saved with --record instead of the network
+    #[cfg(feature = "mock-server")]
+    #[arg(long, global = true, value_name = "DIR", conflicts_with_all = ["api_base", "record"])]
+    replay: Option<PathBuf>,
+
+    /// Maximum API requests per second; halves on rate limits and recovers gradually
+    #[arg(long, global = true, value_name = "REQ_PER_SEC", default_value_t = limiter::DEFAULT_RATE)]
+    rate: f64,
+
+    /// Maximum API requests in flight at once
+    #[arg(long, global = true, value_name = "N", default_value_t = limiter::DEFAULT_CONCURRENCY,
+          value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    concurrency: usize,
+
+    /// Passphrase for encrypted exports, from the first line of this file
+    #[arg(long, global = true, value_name = "FILE")]
+    passphrase_file: Option<PathBuf>,
+
+    /// Only work on these data types (comma-separated)
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_delimiter = ',',
+        value_name = "TYPES"
+    )]
+    only: Vec<selection::ExportFile>,
+
+    /// Leave these data types out (comma-separated)
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_delimiter = ',',
+        value_name = "TYPES"
+    )]
+    skip: Vec<selection::ExportFile>,
 }
 
 #[derive(Subcommand)]
@@ -41,6 +117,15 @@ enum Command {
         profile: Option<String>,
         #[arg(long, default_value = "./export")]
         output_dir: PathBuf,
+        /// Also bundle the files into this .tar.zst with a checksum manifest
+        #[arg(long, value_name = "FILE")]
+        archive: Option<PathBuf>,
+        /// File format; csv writes one spreadsheet-friendly file per data type
+        #[arg(long, value_enum, default_value_t)]
+        format: export::ExportFormat,
+        /// Encrypt the export files with a passphrase (JSON only)
+        #[arg(long)]
+        encrypt: bool,
     },
 
     /// Import from JSON files into a profile
@@ -53,11 +138,52 @@ enum Command {
         profile: Option<String>,
         #[arg(long, default_value = "./export")]
         input_dir: PathBuf,
+        /// Read the export from an archive written by `export --archive`, unpacked into the input directory
+        #[arg(long, value_name = "FILE")]
+        archive: Option<PathBuf>,
         #[arg(long)]
         dry_run: bool,
+        /// Continue an interrupted import, skipping items the journal already settled
+        #[arg(long, conflicts_with = "dry_run")]
+        resume: bool,
+        /// How to resolve ratings that differ between the export and the target
+        #[arg(long, value_enum, default_value_t)]
+        ratings_policy: import::RatingsPolicy,
+        /// Replay history oldest-first so recently watched shows end up on top (slower)
+        #[arg(long)]
+        ordered_history: bool,
+        /// Write the operations to this file for review instead of importing
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["dry_run", "resume"])]
+        plan: Option<PathBuf>,
+        /// Reuse a target state snapshot from the data directory younger than this; 0 always fetches
+        #[arg(long, value_name = "SECS", default_value_t = target_cache::DEFAULT_TTL_SECS)]
+        target_cache_ttl: u64,
     },
 
-    /// Compare exported data against target account
+    /// Retry just the items a previous import listed in failures.json
+    RetryFailed {
+        #[arg(long)]
+        email: Option<String>,
+        #[arg(long)]
+        password: Option<String>,
+        #[arg(long)]
+        profile: Option<String>,
+        #[arg(long, default_value = "./export")]
+        input_dir: PathBuf,
+    },
+
+    /// Execute a plan written by `import --plan`
+    Apply {
+        #[arg(long)]
+        email: Option<String>,
+        #[arg(long)]
+        password: Option<String>,
+        #[arg(long)]
+        profile: Option<String>,
+        plan: PathBuf,
+    },
+
+    /// Compare exported data against target account, or two exports against each other
     Diff {
         #[arg(long)]
         email: Option<String>,
@@ -67,6 +193,57 @@ enum Command {
         profile: Option<String>,
         #[arg(long, short = 'i', default_value = "./export")]
         input_dir: PathBuf,
+        /// Read the export from an archive written by `export --archive`, unpacked into the input directory
+        #[arg(long, value_name = "FILE", conflicts_with = "from")]
+        archive: Option<PathBuf>,
+        /// Older export directory to compare offline (no login)
+        #[arg(long, requires = "to", conflicts_with = "input_dir")]
+        from: Option<PathBuf>,
+        /// Newer export directory to compare offline (no login)
+        #[arg(long, requires = "from")]
+        to: Option<PathBuf>,
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        format: diff::DiffFormat,
+        /// List the individual items, not just counts
+        #[arg(long)]
+        details: bool,
+        /// Reuse a target state snapshot from the data directory younger than this; 0 always fetches
+        #[arg(long, value_name = "SECS", default_value_t = target_cache::DEFAULT_TTL_SECS)]
+        target_cache_ttl: u64,
+    },
+
+    /// Rewrite export files written by an older version to the current format (no login)
+    UpgradeExport {
+        #[arg(long, short = 'i', default_value = "./export")]
+        input_dir: PathBuf,
+    },
+
+    /// Check an export directory for problems before importing it (no login)
+    Validate {
+        #[arg(long, short = 'i', default_value = "./export")]
+        input_dir: PathBuf,
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        format: diff::DiffFormat,
+    },
+
+    /// Check that exported data actually made it onto the target account
+    Verify {
+        #[arg(long)]
+        email: Option<String>,
+        #[arg(long)]
+        password: Option<String>,
+        #[arg(long)]
+        profile: Option<String>,
+        #[arg(long, short = 'i', default_value = "./export")]
+        input_dir: PathBuf,
+        /// Read the export from an archive written by `export --archive`, unpacked into the input directory
+        #[arg(long, value_name = "FILE")]
+        archive: Option<PathBuf>,
+        /// Ratings policy the import used; ratings it kept on the target aren't reported
+        #[arg(long, value_enum, default_value_t)]
+        ratings_policy: import::RatingsPolicy,
     },
 
     /// Rename a profile on the account
@@ -97,6 +274,29 @@ enum Command {
         target_profile: Option<String>,
         #[arg(long, default_value = "./migration")]
         data_dir: PathBuf,
+        /// How to resolve ratings that differ between source and target
+        #[arg(long, value_enum, default_value_t)]
+        ratings_policy: import::RatingsPolicy,
+        /// Replay history oldest-first so recently watched shows end up on top (slower)
+        #[arg(long)]
+        ordered_history: bool,
+        /// Import without asking for confirmation after the diff
+        #[arg(long)]
+        yes: bool,
+        /// Reuse a target state snapshot from the data directory younger than this; 0 always fetches
+        #[arg(long, value_name = "SECS", default_value_t = target_cache::DEFAULT_TTL_SECS)]
+        target_cache_ttl: u64,
+    },
+
+    /// Serve a local stand-in for the Crunchyroll API from a fixture file (for testing)
+    #[cfg(feature = "mock-server")]
+    #[command(hide = true)]
+    MockServer {
+        #[arg(long)]
+        fixtures: PathBuf,
+        /// 0 picks a free port
+        #[arg(long, default_value_t = 0)]
+        port: u16,
     },
 }
 
@@ -111,19 +311,72 @@ async fn main() -> anyhow::Result<()> {
     }));
 
     let cli = Cli::parse();
+    #[cfg(feature = "mock-server")]
+    let replay = match &cli.replay {
+        Some(dir) => Some(cassette::ReplayServer::start(dir).await?),
+        None => None,
+    };
+    // The replay server is ours, so its certificate needs no --insecure
+    #[cfg(feature = "mock-server")]
+    let (insecure, api_base) = (
+        cli.insecure || replay.is_some(),
+        replay.as_ref().map(|server| server.url()).or(cli.api_base),
+    );
+    #[cfg(not(feature = "mock-server"))]
+    let (insecure, api_base) = (cli.insecure, cli.api_base);
+    anyhow::ensure!(cli.rate > 0.0, "--rate must be greater than 0");
+    let mut client =
+        ClientOptions::new(api_base.as_deref(), insecure)?.limit(cli.rate, cli.concurrency);
+    if let Some(dir) = &cli.record {
+        client = client.record(dir)?;
+    }
+
+    if let Some(path) = &cli.passphrase_file {
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+        crypt::set_passphrase(content.lines().next().unwrap_or_default().to_string());
+    }
+
+    let selection = selection::Selection::new(&cli.only, &cli.skip);
+    anyhow::ensure!(
+        !selection.phases().is_empty(),
+        "--only and --skip leave no data types to work on"
+    );
 
     let command = match cli.command {
         Some(cmd) => cmd,
         None => select_command()?,
     };
 
+    let result = run(command, &client, selection).await;
+    #[cfg(feature = "mock-server")]
+    if let Some(server) = &replay {
+        let misses = server.misses();
+        if !misses.is_empty() {
+            eprintln!("{} requests had no recorded response:", misses.len());
+            for miss in misses {
+                eprintln!("  {}", miss);
+            }
+        }
+    }
+    match result {
+        Err(e) if e.is::<ui::Cancelled>() => std::process::exit(ui::CANCELLED_EXIT_CODE),
+        result => result,
+    }
+}
+
+async fn run(
+    command: Command,
+    client: &ClientOptions,
+    selection: selection::Selection,
+) -> anyhow::Result<()> {
     match command {
         Command::Status {
             email,
             password,
             profile,
         } => {
-            let session = auth::initial_login(email, password, "").await?;
+            let session = auth::initial_login(email, password, "", client).await?;
             let is_premium = session.crunchy.premium().await;
 
             println!("Account");
@@ -160,7 +413,8 @@ async fn main() -> anyhow::Result<()> {
                 let selected = auth::select_profile(&session.profiles, profile)?;
                 let profile_name = selected.profile_name.clone();
                 let crunchy =
-                    auth::switch_profile(&session.refresh_token, selected, session.device).await?;
+                    auth::switch_profile(&session.refresh_token, selected, session.device, client)
+                        .await?;
 
                 let watchlist = crunchy.watchlist(WatchlistOptions::default()).await?;
                 let mut history_count: u32 = 0;
@@ -185,28 +439,158 @@ async fn main() -> anyhow::Result<()> {
             password,
             profile,
             output_dir,
+            archive,
+            format,
+            encrypt,
         } => {
-            let crunchy = auth::login(email, password, profile, "", false).await?;
-            export::run(&crunchy, &output_dir).await?;
+            anyhow::ensure!(
+                !encrypt || format == export::ExportFormat::Json,
+                "--encrypt only works with --format json"
+            );
+            let key = if encrypt {
+                Some(crypt::Key::new(&crypt::passphrase(true)?)?)
+            } else {
+                None
+            };
+            let crunchy = CrunchyrollBackend::new(
+                auth::login(email, password, profile, "", false, client).await?,
+                client,
+            );
+            let display = ui::Display::detect();
+            export::run(
+                &crunchy,
+                &output_dir,
+                selection,
+                format,
+                key.as_ref(),
+                display,
+            )
+            .await?;
+            if let Some(path) = archive {
+                let manifest = archive::create(&output_dir, selection, &path)?;
+                println!(
+                    "Archived {} files to {}",
+                    manifest.files.len(),
+                    path.display()
+                );
+            }
         }
         Command::Import {
             email,
             password,
             profile,
             input_dir,
+            archive,
             dry_run,
+            resume,
+            ratings_policy,
+            ordered_history,
+            plan,
+            target_cache_ttl,
+        } => {
+            if let Some(path) = &archive {
+                archive::unpack(path, &input_dir)?;
+            }
+            let crunchy = CrunchyrollBackend::new(
+                auth::login(email, password, profile, "", true, client).await?,
+                client,
+            );
+            let options = import::ImportOptions {
+                dry_run,
+                resume,
+                ratings_policy,
+                ordered_history,
+                plan,
+                target_cache_ttl: Duration::from_secs(target_cache_ttl),
+                selection,
+                display: ui::Display::detect(),
+            };
+            import::run(&crunchy, &input_dir, &options).await?;
+        }
+        Command::RetryFailed {
+            email,
+            password,
+            profile,
+            input_dir,
+        } => {
+            let crunchy = CrunchyrollBackend::new(
+                auth::login(email, password, profile, "", true, client).await?,
+                client,
+            );
+            import::retry_failed(&crunchy, &input_dir, selection, ui::Display::detect()).await?;
+        }
+        Command::Apply {
+            email,
+            password,
+            profile,
+            plan,
         } => {
-            let crunchy = auth::login(email, password, profile, "", true).await?;
-            import::run(&crunchy, &input_dir, dry_run).await?;
+            let crunchy = CrunchyrollBackend::new(
+                auth::login(email, password, profile, "", true, client).await?,
+                client,
+            );
+            plan::apply(&crunchy, &plan, selection, ui::Display::detect()).await?;
         }
         Command::Diff {
             email,
             password,
             profile,
             input_dir,
+            archive,
+            from,
+            to,
+            format,
+            details,
+            target_cache_ttl,
+        } => {
+            if let (Some(from), Some(to)) = (from, to) {
+                changes::run(&from, &to, selection, format, details)?;
+            } else {
+                if let Some(path) = &archive {
+                    archive::unpack(path, &input_dir)?;
+                }
+                let crunchy = CrunchyrollBackend::new(
+                    auth::login(email, password, profile, "", true, client).await?,
+                    client,
+                );
+                let ttl = Duration::from_secs(target_cache_ttl);
+                diff::run(&crunchy, &input_dir, selection, format, details, ttl).await?;
+            }
+        }
+        Command::UpgradeExport { input_dir } => {
+            println!("Upgrading {}:", input_dir.display());
+            export::upgrade(&input_dir, selection)?;
+        }
+        Command::Validate { input_dir, format } => {
+            let problems = validate::run(&input_dir, selection, format)?;
+            let errors = validate::errors(&problems);
+            if errors > 0 {
+                anyhow::bail!("{} errors in {}", errors, input_dir.display());
+            }
+        }
+        Command::Verify {
+            email,
+            password,
+            profile,
+            input_dir,
+            archive,
+            ratings_policy,
         } => {
-            let crunchy = auth::login(email, password, profile, "", true).await?;
-            diff::run(&crunchy, &input_dir).await?;
+            if let Some(path) = &archive {
+                archive::unpack(path, &input_dir)?;
+            }
+            let crunchy = CrunchyrollBackend::new(
+                auth::login(email, password, profile, "", true, client).await?,
+                client,
+            );
+            let report = verify::run(&crunchy, &input_dir, ratings_policy, selection).await?;
+            if !report.problems.is_empty() {
+                anyhow::bail!(
+                    "{} items are missing or mismatched on the target",
+                    report.problems.len()
+                );
+            }
+            println!("Everything in the export is on the target.");
         }
         Command::RenameProfile {
             email,
@@ -214,7 +598,7 @@ async fn main() -> anyhow::Result<()> {
             profile,
             new_name,
         } => {
-            let session = auth::initial_login(email, password, "").await?;
+            let session = auth::initial_login(email, password, "", client).await?;
             let mut target = auth::select_profile(&session.profiles, profile)?.clone();
             let new_name = new_name.unwrap_or_else(|| {
                 dialoguer::Input::new()
@@ -233,7 +617,12 @@ async fn main() -> anyhow::Result<()> {
             target_password,
             target_profile,
             data_dir,
+            ratings_policy,
+            ordered_history,
+            yes,
+            target_cache_ttl,
         } => {
+            let cache_ttl = Duration::from_secs(target_cache_ttl);
             println!("=== Step 1: Export from source ===\n");
             let source = auth::login(
                 source_email,
@@ -241,9 +630,19 @@ async fn main() -> anyhow::Result<()> {
                 source_profile,
                 "Source",
                 false,
+                client,
+            )
+            .await?;
+            let source = CrunchyrollBackend::new(source, client);
+            export::run(
+                &source,
+                &data_dir,
+                selection,
+                export::ExportFormat::Json,
+                None,
+                ui::Display::detect(),
             )
             .await?;
-            export::run(&source, &data_dir).await?;
             drop(source);
 
             println!("\n=== Step 2: Login to target ===\n");
@@ -253,16 +652,27 @@ async fn main() -> anyhow::Result<()> {
                 target_profile,
                 "Target",
                 true,
+                client,
             )
             .await?;
+            let target = CrunchyrollBackend::new(target, client);
 
             println!("=== Step 3: Diff ===");
-            diff::run(&target, &data_dir).await?;
+            diff::run(
+                &target,
+                &data_dir,
+                selection,
+                diff::DiffFormat::Table,
+                false,
+                cache_ttl,
+            )
+            .await?;
 
-            let proceed = dialoguer::Confirm::new()
-                .with_prompt("Proceed with import?")
-                .default(true)
-                .interact()?;
+            let proceed = yes
+                || dialoguer::Confirm::new()
+                    .with_prompt("Proceed with import?")
+                    .default(true)
+                    .interact()?;
 
             if !proceed {
                 println!("Aborted.");
@@ -270,10 +680,31 @@ async fn main() -> anyhow::Result<()> {
             }
 
             println!("\n=== Step 4: Import ===\n");
-            import::run(&target, &data_dir, false).await?;
+            let options = import::ImportOptions {
+                ratings_policy,
+                ordered_history,
+                target_cache_ttl: cache_ttl,
+                selection,
+                display: ui::Display::detect(),
+                ..Default::default()
+            };
+            import::run(&target, &data_dir, &options).await?;
 
+            println!("\n=== Step 5: Verify ===\n");
+            let report = verify::run(&target, &data_dir, ratings_policy, selection).await?;
+            if !report.problems.is_empty() {
+                anyhow::bail!(
+                    "{} items are missing or mismatched on the target; run `import --input-dir {}` again to retry them",
+                    report.problems.len(),
+                    data_dir.display()
+                );
+            }
             println!("\nMigration complete.");
         }
+        #[cfg(feature = "mock-server")]
+        Command::MockServer { fixtures, port } => {
+            mock_server::serve(&fixtures, port).await?;
+        }
     }
 
     Ok(())
@@ -305,6 +736,10 @@ fn select_command() -> anyhow::Result<Command> {
             target_password: None,
             target_profile: None,
             data_dir: PathBuf::from("./migration"),
+            ratings_policy: import::RatingsPolicy::default(),
+            ordered_history: false,
+            yes: false,
+            target_cache_ttl: target_cache::DEFAULT_TTL_SECS,
         },
         1 => Command::Status {
             email: None,
@@ -316,19 +751,34 @@ fn select_command() -> anyhow::Result<Command> {
             password: None,
             profile: None,
             output_dir: PathBuf::from("./export"),
+            archive: None,
+            format: export::ExportFormat::default(),
+            encrypt: false,
         },
         3 => Command::Import {
             email: None,
             password: None,
             profile: None,
             input_dir: PathBuf::from("./export"),
+            archive: None,
             dry_run: false,
+            resume: false,
+            ratings_policy: import::RatingsPolicy::default(),
+            ordered_history: false,
+            plan: None,
+            target_cache_ttl: target_cache::DEFAULT_TTL_SECS,
         },
         4 => Command::Diff {
             email: None,
             password: None,
             profile: None,
             input_dir: PathBuf::from("./export"),
+            archive: None,
+            from: None,
+            to: None,
+            format: diff::DiffFormat::default(),
+            details: false,
+            target_cache_ttl: target_cache::DEFAULT_TTL_SECS,
         },
         5 => Command::RenameProfile {
             email: None,
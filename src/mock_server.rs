@@ -0,0 +1,1158 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+/// Self-signed certificate for www.crunchyroll.com. The client only trusts it with
+/// `--insecure` and a loopback API base.
+const TLS_PEM: &[u8] = include_bytes!("mock_server.pem");
+
+/// Accounts, profile data and catalog the mock serves. Profile data changes as the tool
+/// writes to it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fixtures {
+    pub accounts: Vec<AccountFixture>,
+    pub catalog: Catalog,
+    #[serde(default)]
+    pub faults: Vec<Fault>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccountFixture {
+    pub email: String,
+    pub password: String,
+    pub account_id: String,
+    #[serde(default = "default_true")]
+    pub premium: bool,
+    pub profiles: Vec<ProfileFixture>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProfileFixture {
+    pub profile_id: String,
+    pub profile_name: String,
+    #[serde(default)]
+    pub is_primary: bool,
+    #[serde(default)]
+    pub watchlist: Vec<WatchlistFixture>,
+    #[serde(default)]
+    pub history: Vec<HistoryFixture>,
+    #[serde(default)]
+    pub crunchylists: Vec<ListFixture>,
+    /// Content id -> rating name, e.g. "FourStars"
+    #[serde(default)]
+    pub ratings: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatchlistFixture {
+    pub content_id: String,
+    #[serde(default)]
+    pub is_favourite: bool,
+    #[serde(default)]
+    pub fully_watched: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryFixture {
+    pub content_id: String,
+    pub date_played: DateTime<Utc>,
+    #[serde(default)]
+    pub playhead: u32,
+    #[serde(default)]
+    pub fully_watched: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ListFixture {
+    /// Assigned on load when empty
+    #[serde(default)]
+    pub list_id: String,
+    pub name: String,
+    /// Series and movie listing ids
+    #[serde(default)]
+    pub items: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Catalog {
+    #[serde(default)]
+    pub series: Vec<Show>,
+    #[serde(default)]
+    pub movie_listings: Vec<Show>,
+    #[serde(default)]
+    pub episodes: Vec<Video>,
+    #[serde(default)]
+    pub movies: Vec<Video>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Show {
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub slug_title: String,
+}
+
+/// An episode of a series or a movie of a movie listing.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Video {
+    pub id: String,
+    pub title: String,
+    pub parent_id: String,
+}
+
+/// Answer the next `times` requests whose path contains `path` (and whose method matches,
+/// if given) with an error instead of serving them.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fault {
+    #[serde(default)]
+    pub method: Option<String>,
+    pub path: String,
+    /// 429, 5xx, 409, ... Ignored for Cloudflare blocks, which are always 403.
+    #[serde(default)]
+    pub status: u16,
+    /// Respond with Cloudflare's bot-check page
+    #[serde(default)]
+    pub cloudflare: bool,
+    /// Seconds to send as Retry-After
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+    #[serde(default = "default_times")]
+    pub times: u32,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_times() -> u32 {
+    1
+}
+
+struct State {
+    accounts: Vec<AccountFixture>,
+    catalog: Catalog,
+    faults: Vec<Fault>,
+    /// "METHOD /path" of every request served, in order
+    requests: Vec<String>,
+    next_id: u32,
+}
+
+/// A local stand-in for the subset of the Crunchyroll API this tool uses. Point the tool at
+/// it with `--api-base`.
+pub struct MockServer {
+    addr: SocketAddr,
+    #[cfg_attr(not(test), allow(dead_code))]
+    state: Arc<Mutex<State>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl MockServer {
+    pub async fn start(mut fixtures: Fixtures, port: u16) -> Result<Self> {
+        let mut next_id = 0;
+        for list in fixtures
+            .accounts
+            .iter_mut()
+            .flat_map(|a| &mut a.profiles)
+            .flat_map(|p| &mut p.crunchylists)
+        {
+            if list.list_id.is_empty() {
+                next_id += 1;
+                list.list_id = format!("list-{}", next_id);
+            }
+        }
+        let state = Arc::new(Mutex::new(State {
+            accounts: fixtures.accounts,
+            catalog: fixtures.catalog,
+            faults: fixtures.faults,
+            requests: Vec::new(),
+            next_id,
+        }));
+
+        let handler_state = state.clone();
+        let (addr, task) = listen(
+            port,
+            Arc::new(move |request| handle(&mut handler_state.lock().unwrap(), request)),
+        )
+        .await?;
+
+        Ok(Self { addr, state, task })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    #[cfg(test)]
+    pub fn inject(&self, fault: Fault) {
+        self.state.lock().unwrap().faults.push(fault);
+    }
+
+    #[cfg(test)]
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Serve `fixtures_path` until interrupted.
+pub async fn serve(fixtures_path: &Path, port: u16) -> Result<()> {
+    let content = std::fs::read_to_string(fixtures_path)
+        .with_context(|| format!("Reading {}", fixtures_path.display()))?;
+    let fixtures: Fixtures = serde_json::from_str(&content)
+        .with_context(|| format!("Parsing {}", fixtures_path.display()))?;
+    let server = MockServer::start(fixtures, port).await?;
+    println!("Mock Crunchyroll API listening on {}", server.url());
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+fn tls_acceptor() -> Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_slice_iter(TLS_PEM).collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_slice(TLS_PEM)?;
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_no_client_auth()
+    .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Answers each request on a local server.
+pub type Handler = Arc<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// Serve `handler` on 127.0.0.1 until the returned task is aborted.
+pub async fn listen(
+    port: u16,
+    handler: Handler,
+) -> Result<(SocketAddr, tokio::task::JoinHandle<()>)> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    let addr = listener.local_addr()?;
+    let tls = tls_acceptor()?;
+    let task = tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let handler = handler.clone();
+            let tls = tls.clone();
+            tokio::spawn(async move {
+                let _ = serve_connection(stream, tls, &*handler).await;
+            });
+        }
+    });
+    Ok((addr, task))
+}
+
+async fn serve_connection(
+    stream: TcpStream,
+    tls: TlsAcceptor,
+    handler: &(dyn Fn(&Request) -> Response + Send + Sync),
+) -> Result<()> {
+    // The library's pre-login request arrives over TLS; everything else is plain HTTP
+    let mut first = [0u8; 1];
+    stream.peek(&mut first).await?;
+    if first[0] == 0x16 {
+        serve_http(tls.accept(stream).await?, handler).await
+    } else {
+        serve_http(stream, handler).await
+    }
+}
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    /// Names are lowercase
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json(status: u16, value: Value) -> Self {
+        Self::new(status, "application/json", value.to_string().into_bytes())
+    }
+
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type: content_type.to_string(),
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    fn ok(value: Value) -> Self {
+        Self::json(200, value)
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, json!({ "error": message }))
+    }
+}
+
+/// One request per connection, which keeps the client from reusing a connection the
+/// mock is about to close.
+async fn serve_http<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    handler: &(dyn Fn(&Request) -> Response + Send + Sync),
+) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().context("Empty request")?.to_string();
+    let target = parts.next().context("No request target")?.to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    let length = headers
+        .get("content-length")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let request = Request {
+        method,
+        path: path.to_string(),
+        query: serde_urlencoded::from_str(query).unwrap_or_default(),
+        headers,
+        body,
+    };
+    let response = handler(&request);
+
+    let reason = reqwest::StatusCode::from_u16(response.status)
+        .ok()
+        .and_then(|s| s.canonical_reason())
+        .unwrap_or("");
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason,
+        response.content_type,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let stream = reader.get_mut();
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn handle(state: &mut State, request: &Request) -> Response {
+    state
+        .requests
+        .push(format!("{} {}", request.method, request.path));
+
+    if let Some(fault) = state.faults.iter_mut().find(|f| {
+        f.times > 0
+            && request.path.contains(&f.path)
+            && f.method
+                .as_ref()
+                .is_none_or(|m| m.eq_ignore_ascii_case(&request.method))
+    }) {
+        fault.times -= 1;
+        return fault_response(fault);
+    }
+
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", [""]) => Response::new(
+            200,
+            "text/html",
+            b"<!DOCTYPE html><html><body>crunchyroll mock</body></html>".to_vec(),
+        ),
+        ("POST", ["auth", "v1", "token"]) => token(state, request),
+        _ => match session(request) {
+            Some(session) => route(state, &session, request, &segments),
+            None => Response::error(401, "invalid_auth_token"),
+        },
+    }
+}
+
+fn fault_response(fault: &Fault) -> Response {
+    if fault.cloudflare {
+        return Response::new(
+            403,
+            "text/html",
+            b"<!DOCTYPE html><html><head><title>Just a moment...</title></head></html>".to_vec(),
+        );
+    }
+    let message = match fault.status {
+        409 => "conflict",
+        429 => "rate_limited",
+        _ => "injected_failure",
+    };
+    let mut response = Response::error(fault.status, message);
+    if let Some(secs) = fault.retry_after {
+        response
+            .headers
+            .push(("Retry-After".to_string(), secs.to_string()));
+    }
+    response
+}
+
+/// Who a bearer token belongs to.
+#[derive(Deserialize)]
+struct Session {
+    account_id: String,
+    profile_id: String,
+}
+
+fn session(request: &Request) -> Option<Session> {
+    let token = request
+        .headers
+        .get("authorization")?
+        .strip_prefix("Bearer ")?;
+    let payload = token.split('.').nth(1)?;
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+}
+
+/// An unsigned JWT; the library only reads its claims.
+fn access_token(account: &AccountFixture, profile_id: &str) -> String {
+    let header = URL_SAFE_NO_PAD.encode(json!({ "alg": "HS256", "typ": "JWT" }).to_string());
+    let benefits: &[&str] = if account.premium {
+        &["cr_premium"]
+    } else {
+        &[]
+    };
+    let claims = json!({
+        "account_id": account.account_id,
+        "profile_id": profile_id,
+        "benefits": benefits,
+    });
+    let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+    format!("{}.{}.mock", header, claims)
+}
+
+fn token(state: &State, request: &Request) -> Response {
+    let form: HashMap<String, String> =
+        serde_urlencoded::from_bytes(&request.body).unwrap_or_default();
+    let field = |name: &str| form.get(name).map(String::as_str).unwrap_or("");
+
+    // Refresh tokens are "<account_id>:<profile_id>"
+    let (account, profile_id) = match field("grant_type") {
+        "password" => {
+            let Some(account) = state
+                .accounts
+                .iter()
+                .find(|a| a.email == field("username") && a.password == field("password"))
+            else {
+                return Response::error(401, "invalid_grant");
+            };
+            (account, None)
+        }
+        "refresh_token" | "refresh_token_profile_id" => {
+            let (account_id, profile_id) =
+                field("refresh_token").split_once(':').unwrap_or_default();
+            let Some(account) = state.accounts.iter().find(|a| a.account_id == account_id) else {
+                return Response::error(401, "invalid_grant");
+            };
+            let profile_id = match form.get("profile_id") {
+                Some(id) => id.as_str(),
+                None => profile_id,
+            };
+            (account, Some(profile_id))
+        }
+        _ => return Response::error(400, "unsupported_grant_type"),
+    };
+
+    let profile = match profile_id {
+        Some(id) => account.profiles.iter().find(|p| p.profile_id == id),
+        None => account
+            .profiles
+            .iter()
+            .find(|p| p.is_primary)
+            .or(account.profiles.first()),
+    };
+    let Some(profile) = profile else {
+        return Response::error(400, "invalid_profile");
+    };
+
+    Response::ok(json!({
+        "access_token": access_token(account, &profile.profile_id),
+        "refresh_token": format!("{}:{}", account.account_id, profile.profile_id),
+        "expires_in": 3600,
+        "token_type": "Bearer",
+        "scope": "offline_access",
+        "country": "US",
+        "account_id": account.account_id,
+        "profile_id": profile.profile_id,
+    }))
+}
+
+fn route(state: &mut State, session: &Session, request: &Request, segments: &[&str]) -> Response {
+    let State {
+        accounts,
+        catalog,
+        next_id,
+        ..
+    } = state;
+    let Some(account) = accounts
+        .iter_mut()
+        .find(|a| a.account_id == session.account_id)
+    else {
+        return Response::error(401, "invalid_auth_token");
+    };
+    let body: Value = serde_json::from_slice(&request.body).unwrap_or(Value::Null);
+    let method = request.method.as_str();
+
+    // Account-level endpoints
+    match (method, segments) {
+        ("GET", ["accounts", "v1", "me"]) => {
+            return Response::ok(json!({
+                "account_id": account.account_id,
+                "external_id": account.account_id,
+                "email": account.email,
+            }));
+        }
+        ("GET", ["accounts", "v1", "me", "multiprofile"]) => {
+            let profiles: Vec<Value> = account
+                .profiles
+                .iter()
+                .map(|p| profile_json(account, p, &session.profile_id))
+                .collect();
+            return Response::ok(json!({
+                "profiles": profiles,
+                "max_profiles": 5,
+                "tier_max_profiles": 5,
+            }));
+        }
+        ("POST", ["accounts", "v1", "me", "multiprofile"]) => {
+            *next_id += 1;
+            account.profiles.push(ProfileFixture {
+                profile_id: format!("profile-{}", next_id),
+                profile_name: body["profile_name"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                is_primary: false,
+                watchlist: vec![],
+                history: vec![],
+                crunchylists: vec![],
+                ratings: HashMap::new(),
+            });
+            let profile = account.profiles.last().unwrap();
+            return Response::ok(profile_json(account, profile, &session.profile_id));
+        }
+        ("PATCH", ["accounts", "v1", "me", "multiprofile", id]) => {
+            let Some(profile) = account.profiles.iter_mut().find(|p| p.profile_id == *id) else {
+                return not_found();
+            };
+            if let Some(name) = body["profile_name"].as_str() {
+                profile.profile_name = name.to_string();
+            }
+            let profile = account
+                .profiles
+                .iter()
+                .find(|p| p.profile_id == *id)
+                .unwrap();
+            return Response::ok(profile_json(account, profile, &session.profile_id));
+        }
+        ("GET", ["content", "v2", "cms", "series", id]) => {
+            return match catalog.series.iter().find(|s| s.id == *id) {
+                Some(show) => Response::ok(json!({ "data": [series_panel(show)], "total": 1 })),
+                None => not_found(),
+            };
+        }
+        ("GET", ["content", "v2", "cms", "movie_listings", id]) => {
+            return match catalog.movie_listings.iter().find(|s| s.id == *id) {
+                Some(show) => {
+                    Response::ok(json!({ "data": [movie_listing_panel(show)], "total": 1 }))
+                }
+                None => not_found(),
+            };
+        }
+        _ => {}
+    }
+
+    // Profile-scoped endpoints, addressed by account id
+    let account_id = match segments {
+        ["content", "v2", "discover", id, ..]
+        | ["content-reviews", "v2", "user", id, ..]
+        | ["content", "v2", id, ..] => *id,
+        _ => return not_found(),
+    };
+    if account_id != account.account_id {
+        return Response::error(403, "forbidden");
+    }
+    let Some(profile) = account
+        .profiles
+        .iter_mut()
+        .find(|p| p.profile_id == session.profile_id)
+    else {
+        return Response::error(401, "invalid_auth_token");
+    };
+
+    match (method, segments) {
+        ("GET", ["content", "v2", "discover", _, "watchlist"]) => {
+            let data: Vec<Value> = profile
+                .watchlist
+                .iter()
+                .filter_map(|entry| {
+                    Some(json!({
+                        "panel": show_panel(catalog, &entry.content_id)?,
+                        "is_favorite": entry.is_favourite,
+                        "fully_watched": entry.fully_watched,
+                        "never_watched": false,
+                        "playhead": 0,
+                        "new": false,
+                    }))
+                })
+                .collect();
+            Response::ok(json!({ "total": data.len(), "data": data }))
+        }
+        ("POST", ["content", "v2", "discover", _, "mark_as_watched", id]) => {
+            update_history(catalog, profile, id, None)
+        }
+        ("GET", ["content", "v2", _, "watchlist"]) => {
+            let ids: Vec<&str> = request
+                .query
+                .get("content_ids")
+                .map(|ids| ids.split(',').collect())
+                .unwrap_or_default();
+            let data: Vec<Value> = profile
+                .watchlist
+                .iter()
+                .filter(|e| ids.contains(&e.content_id.as_str()))
+                .map(|e| json!({ "id": e.content_id, "is_favorite": e.is_favourite }))
+                .collect();
+            Response::ok(json!({ "total": data.len(), "data": data }))
+        }
+        ("POST", ["content", "v2", _, "watchlist"]) => {
+            let id = body["content_id"].as_str().unwrap_or_default();
+            if show_panel(catalog, id).is_none() {
+                return not_found();
+            }
+            if profile.watchlist.iter().any(|e| e.content_id == id) {
+                return Response::error(409, "conflict");
+            }
+            profile.watchlist.push(WatchlistFixture {
+                content_id: id.to_string(),
+                is_favourite: false,
+                fully_watched: false,
+            });
+            Response::ok(json!({}))
+        }
+        ("PATCH", ["content", "v2", _, "watchlist", id]) => {
+            let Some(entry) = profile.watchlist.iter_mut().find(|e| e.content_id == *id) else {
+                return not_found();
+            };
+            entry.is_favourite = body["is_favorite"].as_bool().unwrap_or(entry.is_favourite);
+            Response::ok(json!({}))
+        }
+        ("GET", ["content", "v2", _, "watch-history"]) => {
+            let page: usize = query_number(request, "page").unwrap_or(1).max(1);
+            let page_size: usize = query_number(request, "page_size").unwrap_or(20).max(1);
+            let mut history: Vec<&HistoryFixture> = profile.history.iter().collect();
+            history.sort_by_key(|h| std::cmp::Reverse(h.date_played));
+            let data: Vec<Value> = history
+                .iter()
+                .skip((page - 1) * page_size)
+                .take(page_size)
+                .map(|h| history_json(catalog, h))
+                .collect();
+            Response::ok(json!({ "total": history.len(), "data": data }))
+        }
+        ("POST", ["content", "v2", _, "playheads"]) => {
+            let id = body["content_id"].as_str().unwrap_or_default();
+            let playhead = body["playhead"].as_u64().unwrap_or(0) as u32;
+            update_history(catalog, profile, id, Some(playhead))
+        }
+        ("GET", ["content", "v2", _, "custom-lists"]) => {
+            let data: Vec<Value> = profile
+                .crunchylists
+                .iter()
+                .map(|l| {
+                    json!({
+                        "list_id": l.list_id,
+                        "title": l.name,
+                        "is_public": false,
+                        "total": l.items.len(),
+                        "modified_at": Utc::now(),
+                    })
+                })
+                .collect();
+            Response::ok(json!({
+                "total": data.len(),
+                "data": data,
+                "meta": {
+                    "total_private": profile.crunchylists.len(),
+                    "total_public": 0,
+                    "max_private": 10,
+                },
+            }))
+        }
+        ("POST", ["content", "v2", _, "custom-lists"]) => {
+            *next_id += 1;
+            let list = ListFixture {
+                list_id: format!("list-{}", next_id),
+                name: body["title"].as_str().unwrap_or_default().to_string(),
+                items: vec![],
+            };
+            let created = json!({
+                "data": [{
+                    "list_id": list.list_id,
+                    "title": list.name,
+                    "modified_at": Utc::now(),
+                }],
+            });
+            profile.crunchylists.push(list);
+            Response::ok(created)
+        }
+        ("GET", ["content", "v2", _, "custom-lists", list_id]) => {
+            let Some(list) = profile.crunchylists.iter().find(|l| l.list_id == *list_id) else {
+                return not_found();
+            };
+            let data: Vec<Value> = list
+                .items
+                .iter()
+                .filter_map(|id| {
+                    Some(json!({
+                        "id": format!("{}-{}", list.list_id, id),
+                        "list_id": list.list_id,
+                        "modified_at": Utc::now(),
+                        "panel": show_panel(catalog, id)?,
+                    }))
+                })
+                .collect();
+            Response::ok(json!({
+                "total": data.len(),
+                "data": data,
+                "meta": {
+                    "title": list.name,
+                    "is_public": false,
+                    "modified_at": Utc::now(),
+                    "max": 100,
+                },
+            }))
+        }
+        ("POST", ["content", "v2", _, "custom-lists", list_id]) => {
+            let id = body["content_id"].as_str().unwrap_or_default();
+            if show_panel(catalog, id).is_none() {
+                return not_found();
+            }
+            let Some(list) = profile
+                .crunchylists
+                .iter_mut()
+                .find(|l| l.list_id == *list_id)
+            else {
+                return not_found();
+            };
+            if list.items.iter().any(|i| i == id) {
+                return Response::error(409, "conflict");
+            }
+            list.items.push(id.to_string());
+            Response::ok(json!({}))
+        }
+        ("GET", ["content-reviews", "v2", "user", _, "rating", _, id]) => {
+            let rating = profile.ratings.get(*id).and_then(|r| stars_to_wire(r));
+            Response::ok(rating_json(rating))
+        }
+        ("PUT", ["content-reviews", "v2", "user", _, "rating", _, id]) => {
+            if show_panel(catalog, id).is_none() {
+                return not_found();
+            }
+            let wire = body["rating"].as_str().unwrap_or_default();
+            let Some(name) = wire_to_stars(wire) else {
+                return Response::error(400, "invalid_rating");
+            };
+            profile.ratings.insert(id.to_string(), name.to_string());
+            Response::ok(rating_json(Some(wire)))
+        }
+        _ => not_found(),
+    }
+}
+
+fn not_found() -> Response {
+    Response::error(404, "not_found")
+}
+
+fn query_number(request: &Request, name: &str) -> Option<usize> {
+    request.query.get(name)?.parse().ok()
+}
+
+/// Set a playhead, or mark as watched when `playhead` is None.
+fn update_history(
+    catalog: &Catalog,
+    profile: &mut ProfileFixture,
+    content_id: &str,
+    playhead: Option<u32>,
+) -> Response {
+    if video(catalog, content_id).is_none() {
+        return not_found();
+    }
+    let entry = match profile
+        .history
+        .iter()
+        .position(|h| h.content_id == content_id)
+    {
+        Some(index) => &mut profile.history[index],
+        None => {
+            profile.history.push(HistoryFixture {
+                content_id: content_id.to_string(),
+                date_played: Utc::now(),
+                playhead: 0,
+                fully_watched: false,
+            });
+            profile.history.last_mut().unwrap()
+        }
+    };
+    match playhead {
+        Some(playhead) => entry.playhead = playhead,
+        None => entry.fully_watched = true,
+    }
+    entry.date_played = Utc::now();
+    Response::ok(json!({}))
+}
+
+fn profile_json(account: &AccountFixture, profile: &ProfileFixture, selected: &str) -> Value {
+    json!({
+        "profile_id": profile.profile_id,
+        "profile_name": profile.profile_name,
+        "username": profile.profile_name.to_lowercase().replace(' ', "_"),
+        "email": account.email,
+        "is_primary": profile.is_primary,
+        "is_selected": profile.profile_id == selected,
+        "can_switch": true,
+        "maturity_rating": "M2",
+    })
+}
+
+fn series_panel(show: &Show) -> Value {
+    json!({
+        "id": show.id,
+        "title": show.title,
+        "slug_title": show.slug_title,
+        "type": "series",
+        "series_metadata": { "episode_count": 0, "season_count": 0 },
+    })
+}
+
+fn movie_listing_panel(show: &Show) -> Value {
+    json!({
+        "id": show.id,
+        "title": show.title,
+        "slug_title": show.slug_title,
+        "type": "movie_listing",
+        "movie_listing_metadata": {},
+    })
+}
+
+/// Panel for a series or movie listing in the catalog.
+fn show_panel(catalog: &Catalog, id: &str) -> Option<Value> {
+    if let Some(show) = catalog.series.iter().find(|s| s.id == id) {
+        return Some(series_panel(show));
+    }
+    catalog
+        .movie_listings
+        .iter()
+        .find(|s| s.id == id)
+        .map(movie_listing_panel)
+}
+
+/// An episode or movie, with its parent show and whether that's a movie listing.
+fn video<'a>(catalog: &'a Catalog, id: &str) -> Option<(&'a Video, Option<&'a Show>, bool)> {
+    if let Some(episode) = catalog.episodes.iter().find(|v| v.id == id) {
+        let series = catalog.series.iter().find(|s| s.id == episode.parent_id);
+        return Some((episode, series, false));
+    }
+    let movie = catalog.movies.iter().find(|v| v.id == id)?;
+    let listing = catalog
+        .movie_listings
+        .iter()
+        .find(|s| s.id == movie.parent_id);
+    Some((movie, listing, true))
+}
+
+fn history_json(catalog: &Catalog, entry: &HistoryFixture) -> Value {
+    let mut value = json!({
+        "id": entry.content_id,
+        "date_played": entry.date_played,
+        "playhead": entry.playhead,
+        "fully_watched": entry.fully_watched,
+    });
+    // Entries for content missing from the catalog come without a panel, like delisted titles
+    if let Some((video, show, is_movie)) = video(catalog, &entry.content_id) {
+        let (show_title, show_slug) = show.map_or((String::new(), String::new()), |s| {
+            (s.title.clone(), s.slug_title.clone())
+        });
+        value["parent_id"] = json!(video.parent_id);
+        value["panel"] = if is_movie {
+            value["parent_type"] = json!("movie_listing");
+            json!({
+                "id": video.id,
+                "title": video.title,
+                "type": "movie",
+                "movie_metadata": {
+                    "movie_listing_id": video.parent_id,
+                    "movie_listing_title": show_title,
+                    "movie_listing_slug_title": show_slug,
+                },
+            })
+        } else {
+            value["parent_type"] = json!("series");
+            json!({
+                "id": video.id,
+                "title": video.title,
+                "type": "episode",
+                "episode_metadata": {
+                    "series_id": video.parent_id,
+                    "series_title": show_title,
+                    "series_slug_title": show_slug,
+                },
+            })
+        };
+    }
+    value
+}
+
+fn rating_json(rating: Option<&str>) -> Value {
+    json!({
+        "rating": rating.unwrap_or(""),
+        "total": 0,
+        "average": "0",
+    })
+}
+
+const STARS: [(&str, &str); 5] = [
+    ("OneStar", "1s"),
+    ("TwoStars", "2s"),
+    ("ThreeStars", "3s"),
+    ("FourStars", "4s"),
+    ("FiveStars", "5s"),
+];
+
+fn stars_to_wire(name: &str) -> Option<&'static str> {
+    STARS.iter().find(|(n, _)| *n == name).map(|(_, w)| *w)
+}
+
+fn wire_to_stars(wire: &str) -> Option<&'static str> {
+    STARS.iter().find(|(_, w)| *w == wire).map(|(n, _)| *n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth;
+    use crate::backend::{Backend, CrunchyrollBackend};
+    use crate::client::ClientOptions;
+    use crate::import;
+    use crate::limiter::PauseReason;
+    use futures_util::StreamExt;
+    use std::time::Duration;
+
+    async fn start() -> MockServer {
+        let fixtures = include_str!("../tests/fixtures/mock_crunchyroll.json");
+        MockServer::start(serde_json::from_str(fixtures).unwrap(), 0)
+            .await
+            .unwrap()
+    }
+
+    async fn source(server: &MockServer) -> CrunchyrollBackend {
+        let client = ClientOptions::new(Some(&server.url()), true).unwrap();
+        let crunchy = auth::login(
+            Some("source@example.com".to_string()),
+            Some("source-password".to_string()),
+            Some("Main".to_string()),
+            "Source",
+            false,
+            &client,
+        )
+        .await
+        .unwrap();
+        CrunchyrollBackend::new(crunchy, &client)
+    }
+
+    fn fault(method: &str, path: &str, status: u16) -> Fault {
+        Fault {
+            method: Some(method.to_string()),
+            path: path.to_string(),
+            status,
+            cloudflare: false,
+            retry_after: None,
+            times: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_fixture_profile_through_the_real_client() {
+        let server = start().await;
+        let backend = source(&server).await;
+
+        assert_eq!(backend.profile_id().await, "source-main");
+        let watchlist = backend.watchlist().await.unwrap();
+        assert_eq!(watchlist.len(), 3);
+        assert!(
+            watchlist
+                .iter()
+                .any(|i| i.content_id == "S1" && i.is_favourite)
+        );
+
+        let history: Vec<_> = backend.watch_history().collect().await;
+        let ids: Vec<_> = history
+            .into_iter()
+            .map(|item| item.unwrap().content_id)
+            .collect();
+        assert_eq!(ids, ["MV1", "E3", "E2", "E1"]);
+
+        assert_eq!(
+            backend.crunchylist_names().await.unwrap(),
+            ["Comfort shows"]
+        );
+        assert_eq!(backend.crunchylist("Comfort shows").await.unwrap().len(), 2);
+        assert_eq!(
+            backend.rating("S1", "series").await.unwrap().as_deref(),
+            Some("FiveStars")
+        );
+    }
+
+    #[tokio::test]
+    async fn injected_faults_are_classified() {
+        let server = start().await;
+        let backend = source(&server).await;
+
+        server.inject(fault("POST", "/watchlist", 409));
+        let err = backend.add_to_watchlist("S1", "series").await.unwrap_err();
+        assert!(import::is_conflict(&err));
+
+        server.inject(Fault {
+            cloudflare: true,
+            ..fault("GET", "/custom-lists", 0)
+        });
+        let err = backend.crunchylist_names().await.unwrap_err();
+        assert!(import::is_cloudflare_block(&err));
+        assert_eq!(
+            backend.limiter().paused().unwrap().reason,
+            PauseReason::Blocked { count: 1 }
+        );
+    }
+
+    #[tokio::test]
+    async fn history_pages_are_retried() {
+        let server = start().await;
+        let backend = source(&server).await;
+
+        server.inject(fault("GET", "/watch-history", 503));
+        let history = import::fetch_history(&backend).await.unwrap();
+        assert_eq!(history.len(), 4);
+        assert_eq!(history[0].content_id, "MV1");
+    }
+
+    #[tokio::test]
+    async fn exported_history_pages_count_against_the_rate() {
+        let server = start().await;
+        let backend = source(&server).await;
+
+        server.inject(fault("GET", "/watch-history", 429));
+        let history: Vec<_> = backend.watch_history().collect().await;
+        assert_eq!(history.len(), 4);
+        assert!(history.iter().all(Result::is_ok));
+        assert!(backend.limiter().rate() < backend.limiter().max_rate());
+    }
+
+    #[tokio::test]
+    async fn retry_recovers_from_server_errors() {
+        let server = start().await;
+        let backend = source(&server).await;
+
+        server.inject(fault("POST", "/playheads", 503));
+        import::retry_with_backoff(|| backend.set_playhead("E3", 600))
+            .await
+            .unwrap();
+
+        let attempts = server
+            .requests()
+            .iter()
+            .filter(|r| r.ends_with("/playheads"))
+            .count();
+        assert_eq!(attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn retry_after_pauses_the_whole_backend() {
+        let server = start().await;
+        let backend = source(&server).await;
+
+        server.inject(Fault {
+            retry_after: Some(1),
+            ..fault("POST", "/playheads", 429)
+        });
+        let start = std::time::Instant::now();
+        let (first, second) = tokio::join!(
+            import::retry_with_backoff(|| backend.set_playhead("E3", 600)),
+            async {
+                // Let the first write hit the 429 before this one starts
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                backend.set_playhead("E2", 300).await
+            }
+        );
+        first.unwrap();
+        second.unwrap();
+        // The unrelated write waited out the Retry-After too
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert!(backend.limiter().rate() < backend.limiter().max_rate());
+    }
+
+    #[tokio::test]
+    async fn rate_limited_retries_wait_only_for_retry_after() {
+        let server = start().await;
+        let backend = source(&server).await;
+
+        server.inject(Fault {
+            retry_after: Some(1),
+            ..fault("POST", "/playheads", 429)
+        });
+        let start = std::time::Instant::now();
+        let (first, second) = tokio::join!(
+            import::retry_with_backoff(|| backend.set_playhead("E3", 600)),
+            import::retry_with_backoff(|| backend.set_playhead("E2", 300)),
+        );
+        first.unwrap();
+        second.unwrap();
+        // The first write's 429 paused both for the Retry-After; neither added a backoff
+        // of its own (2s, longer than the pause) on top
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_secs(1), "{:?}", elapsed);
+        assert!(elapsed < import::INITIAL_BACKOFF, "{:?}", elapsed);
+    }
+}
@@ -1,23 +1,133 @@
+use crate::{crypt, csv_format};
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Format of the export files this version writes. Bump it, and add a step to
+/// `UPGRADES`, whenever an item type changes shape.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Steps that bring an older document up to date: `UPGRADES[n]` turns schema version `n`
+/// into `n + 1`.
+const UPGRADES: [fn(&mut Value); SCHEMA_VERSION as usize] = [upgrade_from_v0];
 
 pub fn read_export<T: serde::de::DeserializeOwned>(dir: &Path, filename: &str) -> Result<T> {
-    let path = dir.join(filename);
-    let content =
-        std::fs::read_to_string(&path).with_context(|| format!("Reading {}", path.display()))?;
-    serde_json::from_str(&content).with_context(|| format!("Parsing {}", path.display()))
+    read_versioned(dir, filename).map(|(data, _)| data)
+}
+
+/// Where export file `filename` is in `dir`: the JSON file, or its CSV form if only that
+/// is there.
+pub fn export_path(dir: &Path, filename: &str) -> PathBuf {
+    let json = dir.join(filename);
+    let csv = dir.join(csv_format::csv_name(filename));
+    if !json.exists() && csv.exists() {
+        csv
+    } else {
+        json
+    }
+}
+
+/// Read an export file, from its CSV form if that is what's there, decrypting it if it
+/// was written with `--encrypt` and upgrading an older schema in memory. Also returns
+/// the schema version the file was written with.
+pub fn read_versioned<T: serde::de::DeserializeOwned>(
+    dir: &Path,
+    filename: &str,
+) -> Result<(T, u32)> {
+    let path = export_path(dir, filename);
+    let mut doc = if path.extension().is_some_and(|e| e == "csv") {
+        csv_format::read(&path, filename)?
+    } else {
+        let csv = dir.join(csv_format::csv_name(filename));
+        anyhow::ensure!(
+            !csv.exists(),
+            "Both {} and {} are in {}; remove the one you don't want to use",
+            filename,
+            csv.display(),
+            dir.display()
+        );
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Reading {}", path.display()))?;
+        serde_json::from_str(&content).with_context(|| format!("Parsing {}", path.display()))?
+    };
+    if crypt::is_sealed(&doc) {
+        let plaintext =
+            crypt::open(doc).with_context(|| format!("Decrypting {}", path.display()))?;
+        doc = serde_json::from_slice(&plaintext)
+            .with_context(|| format!("Parsing {}", path.display()))?;
+    }
+    let version = upgrade(&mut doc).with_context(|| format!("Upgrading {}", path.display()))?;
+    let data =
+        serde_json::from_value(doc).with_context(|| format!("Parsing {}", path.display()))?;
+    Ok((data, version))
+}
+
+/// Bring a parsed export document up to `SCHEMA_VERSION`, returning the version it had.
+/// Files from before versioning have no `schema_version` and count as version 0.
+pub fn upgrade(doc: &mut Value) -> Result<u32> {
+    upgrade_with(doc, &UPGRADES)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// `upgrade` with its own chain of steps, where `steps[n]` turns version `n` into `n + 1`
+/// and the current version is `steps.len()`.
+pub fn upgrade_with(doc: &mut Value, steps: &[fn(&mut Value)]) -> Result<u32> {
+    let current = steps.len() as u32;
+    let version = match doc.pointer("/metadata/schema_version") {
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .context("metadata.schema_version is not a version number")?,
+        None => 0,
+    };
+    anyhow::ensure!(
+        doc.get("metadata").is_some_and(Value::is_object),
+        "No metadata object"
+    );
+    anyhow::ensure!(
+        version <= current,
+        "Written with schema version {}, but this version of crunchyroll-migrate only reads up to {}",
+        version,
+        current
+    );
+    for from in version..current {
+        steps[from as usize](doc);
+        doc["metadata"]["schema_version"] = (from + 1).into();
+    }
+    Ok(version)
+}
+
+/// Version 0 had no `schema_version`, and the earliest files no `partial` flags on the
+/// metadata or on history entries.
+fn upgrade_from_v0(doc: &mut Value) {
+    let metadata = &mut doc["metadata"];
+    if metadata.get("partial").is_none() {
+        metadata["partial"] = false.into();
+    }
+    if let Some(items) = doc.get_mut("items").and_then(Value::as_array_mut) {
+        for item in items.iter_mut().filter(|i| i.get("playhead").is_some()) {
+            if item.get("partial").is_none() {
+                item["partial"] = false.into();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ExportMetadata {
+    /// See `SCHEMA_VERSION`; older files are upgraded when read
+    #[serde(default)]
+    pub schema_version: u32,
     pub profile_name: String,
     pub exported_at: DateTime<Utc>,
     pub total_count: usize,
+    /// The export was cancelled before this data type was fully fetched
+    #[serde(default)]
+    pub partial: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct WatchlistExport {
     pub metadata: ExportMetadata,
     pub items: Vec<WatchlistItem>,
@@ -33,7 +143,7 @@ pub struct WatchlistItem {
     pub fully_watched: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct WatchHistoryExport {
     pub metadata: ExportMetadata,
     pub items: Vec<WatchHistoryItem>,
@@ -53,7 +163,7 @@ pub struct WatchHistoryItem {
     pub partial: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct CrunchylistsExport {
     pub metadata: ExportMetadata,
     pub lists: Vec<CrunchylistData>,
@@ -71,7 +181,7 @@ pub struct CrunchylistItem {
     pub title: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct RatingsExport {
     pub metadata: ExportMetadata,
     pub items: Vec<RatingItem>,
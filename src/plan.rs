@@ -0,0 +1,649 @@
+use crate::backend::Backend;
+use crate::crypt;
+use crate::import::{
+    self, Counts, ImportOptions, PendingWrite, PlayheadState, RatingsPolicy, TargetState,
+    retry_with_backoff,
+};
+use crate::models::{
+    CrunchylistsExport, RatingItem, RatingsExport, WatchHistoryExport, WatchlistExport,
+};
+use crate::selection::{Exports, Selection};
+use crate::ui::{self, DataType, ProgressReporter};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use futures_util::{StreamExt, future, stream};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// A reviewable list of the writes an import would make, computed against a snapshot of
+/// the target. Operations may be removed or edited by hand before running `apply`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Plan {
+    pub created_at: DateTime<Utc>,
+    /// Profile the plan was computed against; `apply` refuses any other
+    pub profile_id: String,
+    /// History operations are listed in replay order and applied one at a time
+    pub ordered_history: bool,
+    pub operations: Vec<Operation>,
+}
+
+/// One write against the target. `previous` fields record what the target had when the
+/// plan was made, so `apply` can detect drift.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    AddToWatchlist {
+        content_id: String,
+        content_type: String,
+        title: String,
+    },
+    MarkFavourite {
+        content_id: String,
+        content_type: String,
+        title: String,
+    },
+    CreateCrunchylist {
+        name: String,
+    },
+    AddToCrunchylist {
+        list: String,
+        content_id: String,
+        title: String,
+    },
+    Rate {
+        content_id: String,
+        content_type: String,
+        title: String,
+        rating: String,
+        previous: Option<String>,
+    },
+    SetPlayhead {
+        content_id: String,
+        title: String,
+        playhead: u32,
+        previous: Option<PlayheadState>,
+    },
+    MarkWatched {
+        content_id: String,
+        title: String,
+        previous: Option<PlayheadState>,
+    },
+}
+
+impl Operation {
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Self::AddToWatchlist { .. } => DataType::Watchlist,
+            Self::MarkFavourite { .. } => DataType::Favourites,
+            Self::CreateCrunchylist { .. } | Self::AddToCrunchylist { .. } => {
+                DataType::Crunchylists
+            }
+            Self::Rate { .. } => DataType::Ratings,
+            Self::SetPlayhead { .. } | Self::MarkWatched { .. } => DataType::History,
+        }
+    }
+
+    /// Why this operation no longer matches the target, if it doesn't.
+    fn drift(&self, target: &TargetState) -> Option<String> {
+        match self {
+            Self::AddToWatchlist { content_id, .. } => target
+                .watchlist_ids
+                .contains(content_id)
+                .then(|| "now already on the watchlist".to_string()),
+            Self::MarkFavourite { content_id, .. } => target
+                .watchlist_favourites
+                .contains(content_id)
+                .then(|| "now already a favourite".to_string()),
+            Self::CreateCrunchylist { name } => target
+                .crunchylists
+                .contains_key(name)
+                .then(|| "list now exists".to_string()),
+            Self::AddToCrunchylist {
+                list, content_id, ..
+            } => target
+                .crunchylists
+                .get(list)
+                .is_some_and(|ids| ids.contains(content_id))
+                .then(|| "now already in the list".to_string()),
+            Self::Rate {
+                content_id,
+                previous,
+                ..
+            } => {
+                let current = target.ratings.get(content_id);
+                (current != previous.as_ref()).then(|| {
+                    format!(
+                        "rating changed from {} to {}",
+                        previous.as_deref().unwrap_or("none"),
+                        current.map_or("none", String::as_str)
+                    )
+                })
+            }
+            Self::SetPlayhead {
+                content_id,
+                previous,
+                ..
+            }
+            | Self::MarkWatched {
+                content_id,
+                previous,
+                ..
+            } => (target.history.get(content_id) != previous.as_ref())
+                .then(|| "watch progress changed".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AddToWatchlist { title, .. } => write!(f, "{}", title),
+            Self::MarkFavourite { title, .. } => write!(f, "\u{2605} {}", title),
+            Self::CreateCrunchylist { name } => write!(f, "New list '{}'", name),
+            Self::AddToCrunchylist { list, title, .. } => write!(f, "  {} -> {}", list, title),
+            Self::Rate { title, rating, .. } => write!(f, "{} ({})", title, rating),
+            Self::SetPlayhead {
+                title, playhead, ..
+            } => write!(f, "{} @ {}s", title, playhead),
+            Self::MarkWatched { title, .. } => write!(f, "{}", title),
+        }
+    }
+}
+
+/// `import --plan`: compute the operations and write them to `plan_path` without
+/// touching the target.
+pub async fn create<B: Backend>(
+    crunchy: &B,
+    input_dir: &Path,
+    plan_path: &Path,
+    options: &ImportOptions,
+) -> Result<()> {
+    let exports = Exports::read(input_dir, options.selection)?;
+
+    println!("Fetching target account state...");
+    let target =
+        import::fetch_target_state(crunchy, exports.selection, &exports.ratings.items).await?;
+
+    let plan = Plan {
+        created_at: Utc::now(),
+        profile_id: crunchy.profile_id().await,
+        ordered_history: options.ordered_history,
+        operations: build(
+            &exports.watchlist,
+            &exports.history,
+            &exports.crunchylists,
+            &exports.ratings,
+            &target,
+            options.ratings_policy,
+            options.ordered_history,
+        ),
+    };
+
+    let json = crypt::seal_if_encrypted(serde_json::to_string_pretty(&plan)?)?;
+    std::fs::write(plan_path, json).with_context(|| format!("Writing {}", plan_path.display()))?;
+
+    println!("\n  Import Plan");
+    println!("  {}", "\u{2500}".repeat(50));
+    for dt in exports.selection.phases() {
+        let n = plan
+            .operations
+            .iter()
+            .filter(|op| op.data_type() == dt)
+            .count();
+        println!("  {:14} {} operations", dt, n);
+    }
+    println!(
+        "\nWrote {} operations to {}. Review it, then run `apply {}`.",
+        plan.operations.len(),
+        plan_path.display(),
+        plan_path.display()
+    );
+    Ok(())
+}
+
+/// The operations an import would perform, in the order it would perform them. Uses the
+/// same pre-filtering as `import`.
+pub fn build(
+    watchlist: &WatchlistExport,
+    history: &WatchHistoryExport,
+    crunchylists: &CrunchylistsExport,
+    ratings: &RatingsExport,
+    target: &TargetState,
+    policy: RatingsPolicy,
+    ordered_history: bool,
+) -> Vec<Operation> {
+    let mut ops = Vec::new();
+
+    for item in &watchlist.items {
+        if !target.watchlist_ids.contains(&item.content_id) {
+            ops.push(Operation::AddToWatchlist {
+                content_id: item.content_id.clone(),
+                content_type: item.content_type.clone(),
+                title: item.title.clone(),
+            });
+        }
+    }
+
+    for item in watchlist.items.iter().filter(|i| i.is_favourite) {
+        if !target.watchlist_favourites.contains(&item.content_id) {
+            ops.push(Operation::MarkFavourite {
+                content_id: item.content_id.clone(),
+                content_type: item.content_type.clone(),
+                title: item.title.clone(),
+            });
+        }
+    }
+
+    for list in &crunchylists.lists {
+        let existing = target.crunchylists.get(&list.name);
+        if existing.is_none() {
+            ops.push(Operation::CreateCrunchylist {
+                name: list.name.clone(),
+            });
+        }
+        for item in &list.items {
+            if !existing.is_some_and(|ids| ids.contains(&item.content_id)) {
+                ops.push(Operation::AddToCrunchylist {
+                    list: list.name.clone(),
+                    content_id: item.content_id.clone(),
+                    title: item.title.clone(),
+                });
+            }
+        }
+    }
+
+    for item in &ratings.items {
+        let previous = target.ratings.get(&item.content_id);
+        if import::should_write_rating(policy, &item.rating, previous.map(String::as_str)) {
+            ops.push(Operation::Rate {
+                content_id: item.content_id.clone(),
+                content_type: item.content_type.clone(),
+                title: item.title.clone(),
+                rating: item.rating.clone(),
+                previous: previous.cloned(),
+            });
+        }
+    }
+
+    let pending: Vec<PendingWrite> = history
+        .items
+        .iter()
+        .filter_map(|item| match target.history.get(&item.content_id) {
+            Some(existing) if existing.is_behind(item) => Some((item, true)),
+            Some(_) => None,
+            None => Some((item, false)),
+        })
+        .collect();
+    let pending = if ordered_history {
+        let (bodies, heads) = import::ordered_batches(pending);
+        bodies.into_iter().flatten().chain(heads).collect()
+    } else {
+        pending
+    };
+    for (item, _) in pending {
+        let title = if item.title.is_empty() {
+            format!("{} - {}", item.series_title, item.content_id)
+        } else {
+            format!("{} - {}", item.series_title, item.title)
+        };
+        let previous = target.history.get(&item.content_id).copied();
+        if item.fully_watched {
+            ops.push(Operation::MarkWatched {
+                content_id: item.content_id.clone(),
+                title,
+                previous,
+            });
+        } else if item.playhead > 0 {
+            ops.push(Operation::SetPlayhead {
+                content_id: item.content_id.clone(),
+                title,
+                playhead: item.playhead,
+                previous,
+            });
+        }
+    }
+
+    ops
+}
+
+/// Every operation that no longer matches the target, with the reason.
+fn find_drift<'a>(plan: &'a Plan, target: &TargetState) -> Vec<(&'a Operation, String)> {
+    plan.operations
+        .iter()
+        .filter_map(|op| op.drift(target).map(|reason| (op, reason)))
+        .collect()
+}
+
+/// `apply`: execute a plan exactly, after checking the target still matches it. Only
+/// operations on the selected data types are checked and run.
+pub async fn apply<B: Backend>(
+    crunchy: &B,
+    plan_path: &Path,
+    selection: Selection,
+    display: ui::Display,
+) -> Result<()> {
+    let content = std::fs::read_to_string(plan_path)
+        .with_context(|| format!("Reading {}", plan_path.display()))?;
+    let mut plan: Plan = serde_json::from_str(&crypt::open_if_sealed(&content)?)
+        .with_context(|| format!("Parsing {}", plan_path.display()))?;
+    plan.operations
+        .retain(|op| selection.includes(op.data_type()));
+
+    let profile_id = crunchy.profile_id().await;
+    if plan.profile_id != profile_id {
+        anyhow::bail!(
+            "{} was made for profile {}, not the selected profile {}",
+            plan_path.display(),
+            plan.profile_id,
+            profile_id
+        );
+    }
+
+    println!("Checking target account against the plan...");
+    let rating_candidates: Vec<RatingItem> = plan
+        .operations
+        .iter()
+        .filter_map(|op| match op {
+            Operation::Rate {
+                content_id,
+                content_type,
+                title,
+                rating,
+                ..
+            } => Some(RatingItem {
+                content_id: content_id.clone(),
+                content_type: content_type.clone(),
+                title: title.clone(),
+                rating: rating.clone(),
+            }),
+            _ => None,
+        })
+        .collect();
+    let target = import::fetch_target_state(crunchy, selection, &rating_candidates).await?;
+
+    let drift = find_drift(&plan, &target);
+    if !drift.is_empty() {
+        println!("\n  The target has changed since the plan was made:");
+        for (op, reason) in drift.iter().take(20) {
+            println!("  - {} ({})", op, reason);
+        }
+        if drift.len() > 20 {
+            println!("  ... and {} more", drift.len() - 20);
+        }
+        println!();
+        anyhow::bail!(
+            "{} operations drifted; re-run `import --plan` to make a fresh plan",
+            drift.len()
+        );
+    }
+
+    let ops_of = |dt: DataType| -> Vec<&Operation> {
+        plan.operations
+            .iter()
+            .filter(|op| op.data_type() == dt)
+            .collect()
+    };
+
+    let (reporter, dashboard) = ui::start_dashboard(
+        display,
+        "Apply",
+        "",
+        &profile_id,
+        selection.phases(),
+        crunchy.limiter().clone(),
+    );
+    let concurrency = crunchy.limiter().concurrency();
+
+    let wl = apply_ops(
+        crunchy,
+        &ops_of(DataType::Watchlist),
+        concurrency,
+        &reporter,
+    )
+    .await;
+    let fv = apply_ops(crunchy, &ops_of(DataType::Favourites), 1, &reporter).await;
+    // Sequential so items are only added once their list has been created
+    let cl = apply_ops(crunchy, &ops_of(DataType::Crunchylists), 1, &reporter).await;
+    let rt = apply_ops(crunchy, &ops_of(DataType::Ratings), 1, &reporter).await;
+    let history_concurrency = if plan.ordered_history { 1 } else { concurrency };
+    let hi = apply_ops(
+        crunchy,
+        &ops_of(DataType::History),
+        history_concurrency,
+        &reporter,
+    )
+    .await;
+
+    reporter.done();
+    dashboard.wait();
+
+    if !reporter.is_tty() {
+        let counts = [
+            (DataType::Watchlist, wl),
+            (DataType::Favourites, fv),
+            (DataType::Crunchylists, cl),
+            (DataType::Ratings, rt),
+            (DataType::History, hi),
+        ];
+        let counts: Vec<_> = counts
+            .into_iter()
+            .filter(|(data_type, _)| selection.includes(*data_type))
+            .collect();
+        import::print_summary(&counts);
+    }
+
+    if reporter.is_cancelled() {
+        crunchy.limiter().check()?;
+        return Err(ui::Cancelled.into());
+    }
+    Ok(())
+}
+
+/// Run one data type's operations, `concurrency` at a time. With a concurrency of 1 they
+/// complete in plan order.
+async fn apply_ops<B: Backend>(
+    crunchy: &B,
+    ops: &[&Operation],
+    concurrency: usize,
+    reporter: &ProgressReporter,
+) -> Counts {
+    let Some(data_type) = ops.first().map(|op| op.data_type()) else {
+        return Counts::new(0);
+    };
+    let mut c = Counts::new(ops.len());
+    reporter.progress(c.to_update(data_type));
+
+    let cancel = reporter.cancellation();
+    let mut results = stream::iter(ops.iter().copied())
+        .take_while(|_| future::ready(!cancel.is_cancelled()))
+        .map(|op| async move { (op, apply_op(crunchy, op).await) })
+        .buffer_unordered(concurrency);
+
+    while let Some((op, result)) = results.next().await {
+        record(&mut c, op, result, reporter);
+        reporter.progress(c.to_update(data_type));
+    }
+    c
+}
+
+fn record(c: &mut Counts, op: &Operation, result: Result<()>, reporter: &ProgressReporter) {
+    match result {
+        Ok(()) => {
+            reporter.log_success(&op.to_string());
+            c.added += 1;
+        }
+        Err(e) if import::is_conflict(&e) => {
+            reporter.log_skip(&op.to_string());
+            c.already_present += 1;
+        }
+        Err(e) => {
+            reporter.log_error(&format!("{} -- {}", op, e));
+            c.failed += 1;
+        }
+    }
+}
+
+async fn apply_op<B: Backend>(crunchy: &B, op: &Operation) -> Result<()> {
+    match op {
+        Operation::AddToWatchlist {
+            content_id,
+            content_type,
+            ..
+        } => retry_with_backoff(|| crunchy.add_to_watchlist(content_id, content_type)).await,
+        Operation::MarkFavourite {
+            content_id,
+            content_type,
+            ..
+        } => retry_with_backoff(|| crunchy.mark_favourite(content_id, content_type)).await,
+        // Not retried: a create that timed out may still have gone through
+        Operation::CreateCrunchylist { name } => crunchy.create_crunchylist(name).await,
+        Operation::AddToCrunchylist {
+            list, content_id, ..
+        } => retry_with_backoff(|| crunchy.add_to_crunchylist(list, content_id)).await,
+        Operation::Rate {
+            content_id,
+            content_type,
+            rating,
+            ..
+        } => retry_with_backoff(|| crunchy.rate(content_id, content_type, rating)).await,
+        Operation::SetPlayhead {
+            content_id,
+            playhead,
+            ..
+        } => retry_with_backoff(|| crunchy.set_playhead(content_id, *playhead)).await,
+        Operation::MarkWatched { content_id, .. } => {
+            retry_with_backoff(|| crunchy.mark_as_watched(content_id)).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{history_item, metadata};
+    use crate::models::{CrunchylistData, CrunchylistItem, WatchlistItem};
+
+    fn watchlist_item(content_id: &str, is_favourite: bool) -> WatchlistItem {
+        WatchlistItem {
+            content_id: content_id.to_string(),
+            title: content_id.to_string(),
+            slug: String::new(),
+            content_type: "series".to_string(),
+            is_favourite,
+            fully_watched: false,
+        }
+    }
+
+    fn exports() -> (
+        WatchlistExport,
+        WatchHistoryExport,
+        CrunchylistsExport,
+        RatingsExport,
+    ) {
+        let watchlist = WatchlistExport {
+            metadata: metadata(2),
+            items: vec![watchlist_item("S1", true), watchlist_item("S2", false)],
+        };
+        let history = WatchHistoryExport {
+            metadata: metadata(3),
+            items: vec![
+                history_item("E1", 0, true),
+                history_item("E2", 300, false),
+                history_item("E3", 0, false),
+            ],
+        };
+        let crunchylists = CrunchylistsExport {
+            metadata: metadata(1),
+            lists: vec![CrunchylistData {
+                name: "Faves".to_string(),
+                items: vec![CrunchylistItem {
+                    content_id: "S2".to_string(),
+                    title: "S2".to_string(),
+                }],
+            }],
+        };
+        let ratings = RatingsExport {
+            metadata: metadata(1),
+            items: vec![RatingItem {
+                content_id: "S1".to_string(),
+                content_type: "series".to_string(),
+                title: "S1".to_string(),
+                rating: "FiveStars".to_string(),
+            }],
+        };
+        (watchlist, history, crunchylists, ratings)
+    }
+
+    #[test]
+    fn build_skips_what_the_target_has() {
+        let (wl, hi, cl, rt) = exports();
+        let mut target = TargetState::default();
+        target.watchlist_ids.insert("S1".to_string());
+        target.history.insert(
+            "E1".to_string(),
+            PlayheadState {
+                playhead: 0,
+                fully_watched: true,
+            },
+        );
+        target
+            .ratings
+            .insert("S1".to_string(), "FiveStars".to_string());
+
+        let ops = build(&wl, &hi, &cl, &rt, &target, RatingsPolicy::default(), false);
+        let kinds: Vec<_> = ops.iter().map(|op| op.to_string()).collect();
+        assert_eq!(
+            kinds,
+            [
+                "S2",
+                "\u{2605} S1",
+                "New list 'Faves'",
+                "  Faves -> S2",
+                "Show - Episode E2 @ 300s",
+            ]
+        );
+    }
+
+    #[test]
+    fn drift_detects_changed_target() {
+        let (wl, hi, cl, rt) = exports();
+        let target = TargetState::default();
+        let plan = Plan {
+            created_at: Utc::now(),
+            profile_id: "p".to_string(),
+            ordered_history: false,
+            operations: build(&wl, &hi, &cl, &rt, &target, RatingsPolicy::default(), false),
+        };
+        assert!(find_drift(&plan, &target).is_empty());
+
+        let mut changed = TargetState::default();
+        changed
+            .ratings
+            .insert("S1".to_string(), "TwoStars".to_string());
+        changed.history.insert(
+            "E2".to_string(),
+            PlayheadState {
+                playhead: 10,
+                fully_watched: false,
+            },
+        );
+        let drift = find_drift(&plan, &changed);
+        assert_eq!(drift.len(), 2);
+        assert_eq!(drift[0].1, "rating changed from none to TwoStars");
+    }
+
+    #[test]
+    fn operations_round_trip_as_tagged_json() {
+        let op = Operation::SetPlayhead {
+            content_id: "E1".to_string(),
+            title: "Show - E1".to_string(),
+            playhead: 42,
+            previous: None,
+        };
+        let json = serde_json::to_value(&op).unwrap();
+        assert_eq!(json["op"], "set_playhead");
+        let back: Operation = serde_json::from_value(json).unwrap();
+        assert_eq!(back, op);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::models::{self, CrunchylistsExport, RatingsExport, WatchHistoryExport, WatchlistExport};
+use crate::ui::{self, DataType};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// What `--only` / `--skip` choose between: one export file each. Favourites are flags on
+/// watchlist entries, so they come and go with the watchlist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFile {
+    Watchlist,
+    History,
+    Crunchylists,
+    Ratings,
+}
+
+impl ExportFile {
+    pub const ALL: [ExportFile; 4] = [
+        ExportFile::Watchlist,
+        ExportFile::History,
+        ExportFile::Crunchylists,
+        ExportFile::Ratings,
+    ];
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ExportFile::Watchlist => "watchlist.json",
+            ExportFile::History => "watch_history.json",
+            ExportFile::Crunchylists => "crunchylists.json",
+            ExportFile::Ratings => "ratings.json",
+        }
+    }
+
+    fn of(data_type: DataType) -> Self {
+        match data_type {
+            DataType::Watchlist | DataType::Favourites => ExportFile::Watchlist,
+            DataType::History => ExportFile::History,
+            DataType::Crunchylists => ExportFile::Crunchylists,
+            DataType::Ratings => ExportFile::Ratings,
+        }
+    }
+}
+
+/// The data types a command works on. Everything by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selection {
+    watchlist: bool,
+    history: bool,
+    crunchylists: bool,
+    ratings: bool,
+}
+
+impl Default for Selection {
+    fn default() -> Self {
+        Self::new(&[], &[])
+    }
+}
+
+impl Selection {
+    /// `only` (if not empty) minus `skip`.
+    pub fn new(only: &[ExportFile], skip: &[ExportFile]) -> Self {
+        let pick = |file| (only.is_empty() || only.contains(&file)) && !skip.contains(&file);
+        Self {
+            watchlist: pick(ExportFile::Watchlist),
+            history: pick(ExportFile::History),
+            crunchylists: pick(ExportFile::Crunchylists),
+            ratings: pick(ExportFile::Ratings),
+        }
+    }
+
+    pub fn contains(self, file: ExportFile) -> bool {
+        match file {
+            ExportFile::Watchlist => self.watchlist,
+            ExportFile::History => self.history,
+            ExportFile::Crunchylists => self.crunchylists,
+            ExportFile::Ratings => self.ratings,
+        }
+    }
+
+    pub fn includes(self, data_type: DataType) -> bool {
+        self.contains(ExportFile::of(data_type))
+    }
+
+    /// Whether everything selected here is also selected in `self`.
+    pub fn covers(self, other: Selection) -> bool {
+        ExportFile::ALL
+            .iter()
+            .all(|&file| self.contains(file) || !other.contains(file))
+    }
+
+    /// Only what both select.
+    pub fn intersect(self, other: Selection) -> Self {
+        Self {
+            watchlist: self.watchlist && other.watchlist,
+            history: self.history && other.history,
+            crunchylists: self.crunchylists && other.crunchylists,
+            ratings: self.ratings && other.ratings,
+        }
+    }
+
+    /// Selected data types in dashboard order.
+    pub fn phases(self) -> Vec<DataType> {
+        ui::PHASES
+            .into_iter()
+            .filter(|&d| self.includes(d))
+            .collect()
+    }
+
+    /// Narrowed to the files present in `dir`, as JSON or CSV; a missing file means that
+    /// data type wasn't exported.
+    pub fn exported_in(self, dir: &Path) -> Self {
+        let present = |file: ExportFile| {
+            self.contains(file) && models::export_path(dir, file.file_name()).exists()
+        };
+        Self {
+            watchlist: present(ExportFile::Watchlist),
+            history: present(ExportFile::History),
+            crunchylists: present(ExportFile::Crunchylists),
+            ratings: present(ExportFile::Ratings),
+        }
+    }
+
+    /// `exported_in`, failing if that leaves nothing to work on.
+    pub fn exported_in_or_err(self, dir: &Path) -> Result<Self> {
+        let selection = self.exported_in(dir);
+        anyhow::ensure!(
+            ExportFile::ALL.iter().any(|&file| selection.contains(file)),
+            "No export files for the selected data types in {}",
+            dir.display()
+        );
+        Ok(selection)
+    }
+}
+
+/// The export files in a directory. Data types that weren't selected or weren't exported
+/// are empty, and left out of `selection`.
+#[derive(Debug)]
+pub struct Exports {
+    pub watchlist: WatchlistExport,
+    pub history: WatchHistoryExport,
+    pub crunchylists: CrunchylistsExport,
+    pub ratings: RatingsExport,
+    pub selection: Selection,
+}
+
+impl Exports {
+    pub fn read(dir: &Path, selection: Selection) -> Result<Self> {
+        let selection = selection.exported_in_or_err(dir)?;
+        fn read<T: serde::de::DeserializeOwned + Default>(
+            dir: &Path,
+            selection: Selection,
+            file: ExportFile,
+        ) -> Result<T> {
+            if selection.contains(file) {
+                models::read_export(dir, file.file_name())
+            } else {
+                Ok(T::default())
+            }
+        }
+        Ok(Self {
+            watchlist: read(dir, selection, ExportFile::Watchlist)?,
+            history: read(dir, selection, ExportFile::History)?,
+            crunchylists: read(dir, selection, ExportFile::Crunchylists)?,
+            ratings: read(dir, selection, ExportFile::Ratings)?,
+            selection,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_and_skip_combine() {
+        let all = Selection::default();
+        assert!(ExportFile::ALL.iter().all(|&f| all.contains(f)));
+
+        let only = Selection::new(&[ExportFile::Watchlist, ExportFile::History], &[]);
+        assert!(only.includes(DataType::Favourites));
+        assert!(!only.includes(DataType::Ratings));
+        assert_eq!(
+            only.phases(),
+            [DataType::Watchlist, DataType::Favourites, DataType::History]
+        );
+
+        let skip = Selection::new(&[], &[ExportFile::History]);
+        assert_eq!(skip.phases().len(), 4);
+        assert!(all.covers(skip));
+        assert!(!skip.covers(all));
+        assert_eq!(skip.intersect(only).phases().len(), 2);
+    }
+}
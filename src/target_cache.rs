@@ -0,0 +1,171 @@
+use crate::backend::Backend;
+use crate::crypt;
+use crate::import::{self, TargetState};
+use crate::models::RatingItem;
+use crate::selection::Selection;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const TARGET_STATE_FILE: &str = "target_state.json";
+
+/// Long enough for `migrate` to diff and then import against one snapshot
+pub const DEFAULT_TTL_SECS: u64 = 600;
+
+/// On disk; `S` is the state, borrowed when saving.
+#[derive(Serialize, Deserialize)]
+struct Snapshot<S> {
+    profile_id: String,
+    /// When the snapshot was fetched; updates from later imports don't move it
+    fetched_at: DateTime<Utc>,
+    /// Data types the snapshot was taken of; the rest of `state` is empty
+    selection: Selection,
+    /// Content whose rating has been looked up, whether or not it turned out rated
+    rating_lookups: HashSet<String>,
+    state: S,
+}
+
+/// Snapshot of the target profile kept in the data directory, so `diff` and `import`
+/// (and the two halves of `migrate`) don't each re-fetch it. A zero TTL turns caching
+/// off: no snapshot is read or written.
+pub struct TargetCache {
+    path: PathBuf,
+    ttl: Duration,
+    profile_id: String,
+    fetched_at: DateTime<Utc>,
+    selection: Selection,
+    rating_lookups: HashSet<String>,
+}
+
+impl TargetCache {
+    /// The cached snapshot for this profile if it is younger than `ttl` and covers
+    /// `selection`, with any ratings it hasn't looked up yet added; otherwise a freshly
+    /// fetched one.
+    pub async fn load_or_fetch<B: Backend>(
+        crunchy: &B,
+        dir: &Path,
+        selection: Selection,
+        rating_candidates: &[RatingItem],
+        ttl: Duration,
+    ) -> Result<(Self, TargetState)> {
+        if let Some((mut cache, mut state)) = Self::load(crunchy, dir, selection, ttl).await? {
+            let missing: Vec<_> = rating_candidates
+                .iter()
+                .filter(|r| !cache.rating_lookups.contains(&r.content_id))
+                .collect();
+            if !missing.is_empty() {
+                state
+                    .ratings
+                    .extend(import::fetch_ratings(crunchy, missing.iter().copied()).await?);
+                cache
+                    .rating_lookups
+                    .extend(missing.iter().map(|r| r.content_id.clone()));
+                cache.save(&state)?;
+            }
+            return Ok((cache, state));
+        }
+
+        eprintln!("Fetching target account state...");
+        let state = import::fetch_target_state(crunchy, selection, rating_candidates).await?;
+        let cache = Self {
+            path: dir.join(TARGET_STATE_FILE),
+            ttl,
+            profile_id: crunchy.profile_id().await,
+            fetched_at: Utc::now(),
+            selection,
+            rating_lookups: rating_candidates
+                .iter()
+                .map(|r| r.content_id.clone())
+                .collect(),
+        };
+        if !ttl.is_zero() {
+            cache.save(&state)?;
+        }
+        Ok((cache, state))
+    }
+
+    /// The cached snapshot for this profile, if there is one younger than `ttl` that
+    /// covers `selection`.
+    pub async fn load<B: Backend>(
+        crunchy: &B,
+        dir: &Path,
+        selection: Selection,
+        ttl: Duration,
+    ) -> Result<Option<(Self, TargetState)>> {
+        let path = dir.join(TARGET_STATE_FILE);
+        if ttl.is_zero() {
+            return Ok(None);
+        }
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+        };
+        let content = crypt::open_if_sealed(&content)
+            .with_context(|| format!("Decrypting {}", path.display()))?;
+        // A snapshot from an older version is simply fetched again
+        let Ok(snapshot) = serde_json::from_str::<Snapshot<TargetState>>(&content) else {
+            return Ok(None);
+        };
+        let age = (Utc::now() - snapshot.fetched_at)
+            .to_std()
+            .unwrap_or_default();
+        if snapshot.profile_id != crunchy.profile_id().await
+            || age >= ttl
+            || !snapshot.selection.covers(selection)
+        {
+            return Ok(None);
+        }
+        eprintln!(
+            "Using target state cached {}s ago ({}; --target-cache-ttl 0 to re-fetch)",
+            age.as_secs(),
+            path.display()
+        );
+        let cache = Self {
+            path,
+            ttl,
+            profile_id: snapshot.profile_id,
+            fetched_at: snapshot.fetched_at,
+            selection: snapshot.selection,
+            rating_lookups: snapshot.rating_lookups,
+        };
+        Ok(Some((cache, snapshot.state)))
+    }
+
+    /// Write back a state that import has kept up to date with its own writes. With
+    /// caching off, drop any older snapshot instead, since the target has moved on from it.
+    pub fn save(&self, state: &TargetState) -> Result<()> {
+        if self.ttl.is_zero() {
+            return invalidate(self.path.parent().unwrap_or(Path::new(".")));
+        }
+        let snapshot = Snapshot {
+            profile_id: self.profile_id.clone(),
+            fetched_at: self.fetched_at,
+            selection: self.selection,
+            rating_lookups: self.rating_lookups.clone(),
+            state,
+        };
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(
+            &tmp,
+            crypt::seal_if_encrypted(serde_json::to_string(&snapshot)?)?,
+        )
+        .with_context(|| format!("Writing {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Writing {}", self.path.display()))
+    }
+}
+
+/// Drop the snapshot in `dir`, for when the target changed in ways it doesn't reflect.
+pub fn invalidate(dir: &Path) -> Result<()> {
+    let path = dir.join(TARGET_STATE_FILE);
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Removing {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
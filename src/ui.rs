@@ -1,3 +1,4 @@
+use crate::limiter::{PauseReason, RateLimiter};
 use crossterm::{
     ExecutableCommand,
     event::{self, Event, KeyCode},
@@ -11,10 +12,27 @@ use ratatui::{
     text::{Line, Span},
     widgets::{Block, Borders, Gauge, Paragraph},
 };
+use serde::{Deserialize, Serialize};
 use std::io::{self, IsTerminal};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Process exit code when the user cancels an operation (same as SIGINT in shells).
+pub const CANCELLED_EXIT_CODE: i32 = 130;
+
+/// Error returned by operations that stopped early because the user cancelled them.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cancelled by user")
+    }
+}
+
+impl std::error::Error for Cancelled {}
 
 #[derive(Debug, Clone)]
 pub struct ProgressUpdate {
@@ -27,22 +45,43 @@ pub struct ProgressUpdate {
     pub failed: usize,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum DataType {
     Watchlist,
+    /// Favourite flags on watchlist entries
+    Favourites,
     History,
     Crunchylists,
     Ratings,
 }
 
+/// Dashboard gauge order, matching the order import works through the data types.
+pub const PHASES: [DataType; 5] = [
+    DataType::Watchlist,
+    DataType::Favourites,
+    DataType::Crunchylists,
+    DataType::Ratings,
+    DataType::History,
+];
+
+pub fn phase_index(data_type: DataType) -> usize {
+    PHASES
+        .iter()
+        .position(|&d| d == data_type)
+        .expect("every data type has a gauge")
+}
+
 impl std::fmt::Display for DataType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            DataType::Watchlist => write!(f, "Watchlist"),
-            DataType::History => write!(f, "History"),
-            DataType::Crunchylists => write!(f, "Crunchylists"),
-            DataType::Ratings => write!(f, "Ratings"),
-        }
+        // pad() so callers can align labels with width specifiers like {:14}
+        f.pad(match self {
+            DataType::Watchlist => "Watchlist",
+            DataType::Favourites => "Favourites",
+            DataType::History => "History",
+            DataType::Crunchylists => "Crunchylists",
+            DataType::Ratings => "Ratings",
+        })
     }
 }
 
@@ -59,8 +98,25 @@ pub enum UiEvent {
     Done,
 }
 
-pub fn is_tty() -> bool {
-    io::stdout().is_terminal()
+/// How an operation shows its progress.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Display {
+    /// The full-screen dashboard
+    Dashboard,
+    /// Plain log lines, and a summary at the end
+    #[default]
+    Headless,
+}
+
+impl Display {
+    /// The dashboard if stdout is a terminal.
+    pub fn detect() -> Self {
+        if io::stdout().is_terminal() {
+            Self::Dashboard
+        } else {
+            Self::Headless
+        }
+    }
 }
 
 pub struct DashboardState {
@@ -68,35 +124,46 @@ pub struct DashboardState {
     pub account: String,
     pub profile: String,
     pub started: Instant,
-    pub progress: [Option<ProgressUpdate>; 4],
-    pub phase_started: [Option<Instant>; 4],
+    /// Data types the operation works on; only these get a gauge
+    pub phases: Vec<DataType>,
+    pub progress: [Option<ProgressUpdate>; 5],
+    pub phase_started: [Option<Instant>; 5],
     pub log: Vec<LogEntry>,
     pub done: bool,
+    /// User asked to quit; the operation is draining in-flight requests
+    pub cancelling: bool,
+    /// Read on every frame for the current request rate
+    pub limiter: Arc<RateLimiter>,
 }
 
 impl DashboardState {
-    fn new(operation: &str, account: &str, profile: &str) -> Self {
+    fn new(
+        operation: &str,
+        account: &str,
+        profile: &str,
+        phases: Vec<DataType>,
+        limiter: Arc<RateLimiter>,
+    ) -> Self {
         Self {
             operation: operation.to_string(),
             account: account.to_string(),
             profile: profile.to_string(),
+            phases,
             started: Instant::now(),
-            progress: [None, None, None, None],
-            phase_started: [None, None, None, None],
+            progress: Default::default(),
+            phase_started: [None; 5],
             log: Vec::new(),
             done: false,
+            cancelling: false,
+            limiter,
         }
     }
 
     fn apply(&mut self, event: UiEvent) {
         match event {
+            UiEvent::Progress(p) if !self.phases.contains(&p.data_type) => {}
             UiEvent::Progress(p) => {
-                let idx = match p.data_type {
-                    DataType::Watchlist => 0,
-                    DataType::Crunchylists => 1,
-                    DataType::Ratings => 2,
-                    DataType::History => 3,
-                };
+                let idx = phase_index(p.data_type);
                 if self.phase_started[idx].is_none() {
                     self.phase_started[idx] = Some(Instant::now());
                 }
@@ -129,13 +196,30 @@ impl DashboardState {
 }
 
 /// Sender handle for operations to report progress.
+/// Also carries the cancellation token: operations should stop scheduling new work once
+/// `is_cancelled()` turns true, let in-flight requests finish, and flush what they have.
 #[derive(Clone)]
 pub struct ProgressReporter {
     tx: mpsc::UnboundedSender<UiEvent>,
     is_tty: bool,
+    cancel: CancellationToken,
 }
 
 impl ProgressReporter {
+    /// Whether the dashboard is showing progress, rather than plain lines.
+    pub fn is_tty(&self) -> bool {
+        self.is_tty
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Token for stream combinators that need to own a handle to the cancellation state.
+    pub fn cancellation(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
     pub fn progress(&self, update: ProgressUpdate) {
         if self.is_tty {
             let _ = self.tx.send(UiEvent::Progress(update));
@@ -175,6 +259,17 @@ impl ProgressReporter {
         }
     }
 
+    pub fn log_warning(&self, message: &str) {
+        if self.is_tty {
+            let _ = self.tx.send(UiEvent::Log(LogEntry {
+                icon: '!',
+                message: message.to_string(),
+            }));
+        } else {
+            eprintln!("  ! {}", message);
+        }
+    }
+
     pub fn done(&self) {
         let _ = self.tx.send(UiEvent::Done);
     }
@@ -183,6 +278,10 @@ impl ProgressReporter {
 /// Handle to wait for the dashboard thread to finish and restore the terminal.
 pub struct DashboardHandle {
     join: Option<std::thread::JoinHandle<()>>,
+    /// Non-TTY Ctrl+C listener, stopped once the operation is over
+    signal: Option<tokio::task::JoinHandle<()>>,
+    /// Logs each rate-limit pause, stopped once the operation is over
+    pauses: tokio::task::JoinHandle<()>,
 }
 
 impl DashboardHandle {
@@ -191,27 +290,41 @@ impl DashboardHandle {
         if let Some(handle) = self.join.take() {
             let _ = handle.join();
         }
+        if let Some(signal) = self.signal.take() {
+            signal.abort();
+        }
+        self.pauses.abort();
     }
 }
 
 /// Run the dashboard in a background task, returning a ProgressReporter and a handle.
+/// Gauges are shown for `phases`, in dashboard order.
 /// When the operation is done, call `reporter.done()` then `handle.wait()` to ensure
 /// the terminal is restored before continuing.
 pub fn start_dashboard(
+    display: Display,
     operation: &str,
     account: &str,
     profile: &str,
+    phases: Vec<DataType>,
+    limiter: Arc<RateLimiter>,
 ) -> (ProgressReporter, DashboardHandle) {
     let (tx, rx) = mpsc::unbounded_channel();
-    let tty = is_tty();
+    let tty = display == Display::Dashboard;
+    // Tripping the limiter's circuit breaker winds the operation down like a cancel
+    let cancel = limiter.breaker().child_token();
+    let pauses = log_pauses(&limiter, tx.clone(), tty);
 
     if tty {
-        let state = Arc::new(Mutex::new(DashboardState::new(operation, account, profile)));
+        let state = Arc::new(Mutex::new(DashboardState::new(
+            operation, account, profile, phases, limiter,
+        )));
         let state_clone = state.clone();
+        let cancel_clone = cancel.clone();
 
         // Spawn a thread (not tokio task) for the terminal UI to avoid blocking the async runtime
         let join = std::thread::spawn(move || {
-            if let Err(e) = run_tui(state_clone, rx) {
+            if let Err(e) = run_tui(state_clone, rx, cancel_clone) {
                 eprintln!("UI error: {}", e);
             }
         });
@@ -220,10 +333,31 @@ pub fn start_dashboard(
         std::thread::sleep(Duration::from_millis(50));
 
         (
-            ProgressReporter { tx, is_tty: true },
-            DashboardHandle { join: Some(join) },
+            ProgressReporter {
+                tx,
+                is_tty: true,
+                cancel,
+            },
+            DashboardHandle {
+                join: Some(join),
+                signal: None,
+                pauses,
+            },
         )
     } else {
+        // No dashboard to press `q` in: the first Ctrl+C cancels gracefully, a second one
+        // kills the process
+        let cancel_clone = cancel.clone();
+        let signal = tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                eprintln!("Cancelling -- finishing in-flight requests (Ctrl+C again to force)");
+                cancel_clone.cancel();
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    std::process::exit(CANCELLED_EXIT_CODE);
+                }
+            }
+        });
+
         // Non-TTY: just drain events in background
         tokio::spawn(async move {
             let mut rx = rx;
@@ -233,21 +367,60 @@ pub fn start_dashboard(
         });
 
         (
-            ProgressReporter { tx, is_tty: false },
-            DashboardHandle { join: None },
+            ProgressReporter {
+                tx,
+                is_tty: false,
+                cancel,
+            },
+            DashboardHandle {
+                join: None,
+                signal: Some(signal),
+                pauses,
+            },
         )
     }
 }
 
+/// Log a warning whenever the limiter pauses every request, so a long silence on the
+/// dashboard doesn't look like a hang.
+fn log_pauses(
+    limiter: &RateLimiter,
+    tx: mpsc::UnboundedSender<UiEvent>,
+    is_tty: bool,
+) -> tokio::task::JoinHandle<()> {
+    let mut pauses = limiter.subscribe();
+    let reporter = ProgressReporter {
+        tx,
+        is_tty,
+        cancel: CancellationToken::new(),
+    };
+    tokio::spawn(async move {
+        while pauses.changed().await.is_ok() {
+            let pause = *pauses.borrow_and_update();
+            if let Some(pause) = pause {
+                let remaining = pause
+                    .until
+                    .saturating_duration_since(tokio::time::Instant::now());
+                reporter.log_warning(&format!(
+                    "Pausing all requests: {}",
+                    format_pause(remaining, &pause.reason)
+                ));
+            }
+        }
+    })
+}
+
 fn run_tui(
     state: Arc<Mutex<DashboardState>>,
     mut rx: mpsc::UnboundedReceiver<UiEvent>,
+    cancel: CancellationToken,
 ) -> io::Result<()> {
     enable_raw_mode()?;
     io::stdout().execute(EnterAlternateScreen)?;
     let backend = CrosstermBackend::new(io::stdout());
     let mut terminal = Terminal::new(backend)?;
     let mut scroll_offset: usize = 0;
+    let mut force_quit = false;
 
     loop {
         // Process all pending events
@@ -272,13 +445,31 @@ fn run_tui(
         if event::poll(Duration::from_millis(100))?
             && let Event::Key(key) = event::read()?
         {
-            match key.code {
-                KeyCode::Char('q') | KeyCode::Esc => {
-                    break;
-                }
-                KeyCode::Char('c') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
+            let quit = match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => true,
+                KeyCode::Char('c') => key.modifiers.contains(event::KeyModifiers::CONTROL),
+                _ => false,
+            };
+            if quit {
+                let mut s = state.lock().unwrap();
+                if s.cancelling {
+                    // Second request: stop waiting for in-flight work
+                    force_quit = true;
                     break;
                 }
+                // Stop scheduling new requests but keep drawing until the operation
+                // has drained and reports done
+                s.cancelling = true;
+                s.apply(UiEvent::Log(LogEntry {
+                    icon: '!',
+                    message: "Cancelling -- finishing in-flight requests (q again to force)"
+                        .to_string(),
+                }));
+                cancel.cancel();
+                continue;
+            }
+
+            match key.code {
                 KeyCode::Up | KeyCode::Char('k') => {
                     scroll_offset = scroll_offset.saturating_add(1);
                 }
@@ -307,13 +498,11 @@ fn run_tui(
     io::stdout().execute(LeaveAlternateScreen)?;
 
     let s = state.lock().unwrap();
-    let is_done = s.done;
     print_final_summary(&s);
     drop(s);
 
-    if !is_done {
-        // User quit mid-operation -- kill the process so the import actually stops
-        std::process::exit(0);
+    if force_quit {
+        std::process::exit(CANCELLED_EXIT_CODE);
     }
 
     Ok(())
@@ -325,10 +514,10 @@ fn draw_dashboard(f: &mut Frame, state: &DashboardState, scroll_offset: usize) -
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
-            Constraint::Length(4), // header
-            Constraint::Length(8), // progress gauges
-            Constraint::Min(6),    // log
-            Constraint::Length(3), // stats bar
+            Constraint::Length(4),                             // header
+            Constraint::Length(2 * state.phases.len() as u16), // progress gauges
+            Constraint::Min(6),                                // log
+            Constraint::Length(3),                             // stats bar
         ])
         .split(f.area());
 
@@ -342,11 +531,23 @@ fn draw_dashboard(f: &mut Frame, state: &DashboardState, scroll_offset: usize) -
                 .add_modifier(Modifier::BOLD),
         )]),
         Line::from(format!(
-            " {} / {}  |  elapsed: {}m {}s",
+            " {} / {}  |  elapsed: {}m {}s  |  {}",
             state.account,
             state.profile,
             elapsed.as_secs() / 60,
             elapsed.as_secs() % 60,
+            match state.limiter.paused() {
+                Some(pause) => format!(
+                    "paused: {}",
+                    format_pause(
+                        pause
+                            .until
+                            .saturating_duration_since(tokio::time::Instant::now()),
+                        &pause.reason
+                    )
+                ),
+                None => format_rate(state.limiter.rate(), state.limiter.max_rate()),
+            },
         )),
     ])
     .block(
@@ -359,16 +560,11 @@ fn draw_dashboard(f: &mut Frame, state: &DashboardState, scroll_offset: usize) -
     // Progress gauges
     let gauge_area = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Length(2),
-            Constraint::Length(2),
-            Constraint::Length(2),
-            Constraint::Length(2),
-        ])
+        .constraints(vec![Constraint::Length(2); state.phases.len()])
         .split(chunks[1]);
 
-    let labels = ["Watchlist", "Crunchylists", "Ratings", "History"];
-    for (i, label) in labels.iter().enumerate() {
+    for (&label, &area) in state.phases.iter().zip(gauge_area.iter()) {
+        let i = phase_index(label);
         let (ratio, info) = match &state.progress[i] {
             Some(p) if p.total > 0 => {
                 let ratio = p.processed as f64 / p.total as f64;
@@ -397,7 +593,7 @@ fn draw_dashboard(f: &mut Frame, state: &DashboardState, scroll_offset: usize) -
             .gauge_style(Style::default().fg(Color::Green))
             .ratio(ratio.clamp(0.0, 1.0))
             .label(info);
-        f.render_widget(gauge, gauge_area[i]);
+        f.render_widget(gauge, area);
     }
 
     // Log
@@ -412,6 +608,7 @@ fn draw_dashboard(f: &mut Frame, state: &DashboardState, scroll_offset: usize) -
             let color = match entry.icon {
                 '\u{2713}' => Color::Green,
                 'x' => Color::Red,
+                '!' => Color::Yellow,
                 _ => Color::DarkGray,
             };
             Line::from(vec![
@@ -456,7 +653,12 @@ fn draw_dashboard(f: &mut Frame, state: &DashboardState, scroll_offset: usize) -
             format!("{} failed ", total_failed),
             Style::default().fg(Color::Red),
         ),
-        Span::raw(if state.done { "| DONE" } else { "" }),
+        Span::raw(match (state.done, state.cancelling) {
+            (true, true) => "| CANCELLED",
+            (true, false) => "| DONE",
+            (false, true) => "| CANCELLING",
+            (false, false) => "",
+        }),
     ]))
     .block(Block::default().borders(Borders::ALL));
     f.render_widget(stats, chunks[3]);
@@ -467,18 +669,22 @@ fn draw_dashboard(f: &mut Frame, state: &DashboardState, scroll_offset: usize) -
 fn print_final_summary(state: &DashboardState) {
     let elapsed = state.started.elapsed();
     println!(
-        "\n{} complete in {}m {}s\n",
+        "\n{} {} in {}m {}s\n",
         state.operation,
+        if state.cancelling {
+            "cancelled"
+        } else {
+            "complete"
+        },
         elapsed.as_secs() / 60,
         elapsed.as_secs() % 60
     );
 
-    let labels = ["Watchlist", "Crunchylists", "Ratings", "History"];
     let mut total_added = 0;
     let mut total_already = 0;
     let mut total_failed = 0;
 
-    for (i, label) in labels.iter().enumerate() {
+    for (i, label) in PHASES.iter().enumerate() {
         if let Some(p) = &state.progress[i] {
             println!(
                 "  {:14} {} added, {} already there, {} failed",
@@ -500,6 +706,22 @@ fn print_final_summary(state: &DashboardState) {
     );
 }
 
+fn format_pause(remaining: Duration, reason: &PauseReason) -> String {
+    format!(
+        "{} ({}s left)",
+        reason,
+        remaining.as_secs_f64().ceil() as u64
+    )
+}
+
+fn format_rate(rate: f64, max_rate: f64) -> String {
+    if rate < max_rate {
+        format!("{:.1} req/s (slowed from {:.1})", rate, max_rate)
+    } else {
+        format!("{:.1} req/s", rate)
+    }
+}
+
 fn format_eta(eta: Option<Duration>) -> String {
     match eta {
         Some(d) if d.as_secs() >= 60 => {
@@ -529,12 +751,32 @@ mod tests {
         assert_eq!(format_eta(Some(Duration::from_secs(125))), " | ~2m5s left");
     }
 
+    #[test]
+    fn format_rate_notes_slowdowns() {
+        assert_eq!(format_rate(5.0, 5.0), "5.0 req/s");
+        assert_eq!(format_rate(1.25, 5.0), "1.2 req/s (slowed from 5.0)");
+    }
+
+    #[test]
+    fn format_pause_rounds_up() {
+        assert_eq!(
+            format_pause(Duration::from_millis(29_200), &PauseReason::RateLimited),
+            "rate limited (Retry-After) (30s left)"
+        );
+        assert_eq!(
+            format_pause(Duration::from_secs(60), &PauseReason::Blocked { count: 1 }),
+            "Cloudflare block 1/3 (60s left)"
+        );
+    }
+
     #[test]
     fn data_type_display() {
         assert_eq!(DataType::Watchlist.to_string(), "Watchlist");
+        assert_eq!(DataType::Favourites.to_string(), "Favourites");
         assert_eq!(DataType::History.to_string(), "History");
         assert_eq!(DataType::Crunchylists.to_string(), "Crunchylists");
         assert_eq!(DataType::Ratings.to_string(), "Ratings");
+        assert_eq!(format!("{:12}|", DataType::History), "History     |");
     }
 
     #[test]
@@ -0,0 +1,403 @@
+use crate::backend::{CONTENT_TYPES, RATINGS};
+use crate::diff::DiffFormat;
+use crate::models::{
+    self, CrunchylistsExport, ExportMetadata, RatingsExport, WatchHistoryExport, WatchlistExport,
+};
+use crate::selection::{ExportFile, Selection};
+use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Would make an import fail or write the wrong thing
+    Error,
+    /// Worth a look, but an import would cope
+    Warning,
+}
+
+impl Severity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    pub severity: Severity,
+    pub file: &'static str,
+    /// Item the problem is about; None for the file as a whole
+    pub content_id: Option<String>,
+    pub message: String,
+}
+
+/// `validate`: check the export in `dir` without logging in, print what's wrong with it,
+/// and return the problems found.
+pub fn run(dir: &Path, selection: Selection, format: DiffFormat) -> Result<Vec<Problem>> {
+    let problems = check(dir, selection)?;
+    match format {
+        DiffFormat::Table => print_table(dir, &problems),
+        DiffFormat::Json => println!("{}", serde_json::to_string_pretty(&problems)?),
+        DiffFormat::Csv => print_csv(&problems)?,
+    }
+    Ok(problems)
+}
+
+/// Every problem in the selected export files in `dir`. A file that doesn't parse is one
+/// problem; the other files are still checked.
+pub fn check(dir: &Path, selection: Selection) -> Result<Vec<Problem>> {
+    let selection = selection.exported_in_or_err(dir)?;
+
+    let mut checker = Checker::default();
+    for file in ExportFile::ALL
+        .into_iter()
+        .filter(|&f| selection.contains(f))
+    {
+        let name = file.file_name();
+        checker.csv = models::export_path(dir, name).extension() == Some("csv".as_ref());
+        let parsed = match file {
+            ExportFile::Watchlist => models::read_export(dir, name).map(|e| checker.watchlist(&e)),
+            ExportFile::History => models::read_export(dir, name).map(|e| checker.history(&e)),
+            ExportFile::Crunchylists => {
+                models::read_export(dir, name).map(|e| checker.crunchylists(&e))
+            }
+            ExportFile::Ratings => models::read_export(dir, name).map(|e| checker.ratings(&e)),
+        };
+        if let Err(e) = parsed {
+            checker.error(name, None, format!("{:#}", e));
+        }
+    }
+    Ok(checker.problems)
+}
+
+/// Collects problems file by file; also remembers the first profile name seen so the
+/// others can be compared against it.
+#[derive(Default)]
+struct Checker {
+    problems: Vec<Problem>,
+    profile: Option<(&'static str, String)>,
+    /// The file being checked is CSV, which has no profile name to compare
+    csv: bool,
+}
+
+impl Checker {
+    fn error(&mut self, file: &'static str, content_id: Option<&str>, message: String) {
+        self.push(Severity::Error, file, content_id, message);
+    }
+
+    fn warning(&mut self, file: &'static str, content_id: Option<&str>, message: String) {
+        self.push(Severity::Warning, file, content_id, message);
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        file: &'static str,
+        content_id: Option<&str>,
+        message: String,
+    ) {
+        self.problems.push(Problem {
+            severity,
+            file,
+            content_id: content_id.map(str::to_string),
+            message,
+        });
+    }
+
+    fn metadata(&mut self, file: &'static str, metadata: &ExportMetadata, count: usize) {
+        if metadata.total_count != count {
+            self.error(
+                file,
+                None,
+                format!(
+                    "metadata.total_count is {} but the file has {} items",
+                    metadata.total_count, count
+                ),
+            );
+        }
+        if metadata.partial {
+            self.warning(
+                file,
+                None,
+                "export was cancelled before this file was complete".to_string(),
+            );
+        }
+        if metadata.exported_at > Utc::now() {
+            self.error(
+                file,
+                None,
+                format!("exported_at {} is in the future", metadata.exported_at),
+            );
+        }
+        match &self.profile {
+            _ if self.csv => {}
+            Some((first, profile)) if *profile != metadata.profile_name => {
+                let message = format!(
+                    "profile '{}' differs from '{}' in {}",
+                    metadata.profile_name, profile, first
+                );
+                self.error(file, None, message);
+            }
+            Some(_) => {}
+            None => self.profile = Some((file, metadata.profile_name.clone())),
+        }
+    }
+
+    /// Empty and repeated ids, the latter tracked in `seen`.
+    fn content_id(&mut self, file: &'static str, id: &str, seen: &mut HashSet<String>) {
+        if id.trim().is_empty() {
+            self.error(file, None, "item with an empty content_id".to_string());
+        } else if !seen.insert(id.to_string()) {
+            self.error(file, Some(id), "listed more than once".to_string());
+        }
+    }
+
+    fn content_type(&mut self, file: &'static str, id: &str, content_type: &str) {
+        if !CONTENT_TYPES.contains(&content_type) {
+            self.error(
+                file,
+                Some(id),
+                format!(
+                    "content_type '{}' is not one of {}",
+                    content_type,
+                    CONTENT_TYPES.join(", ")
+                ),
+            );
+        }
+    }
+
+    fn watchlist(&mut self, export: &WatchlistExport) {
+        let file = ExportFile::Watchlist.file_name();
+        self.metadata(file, &export.metadata, export.items.len());
+        let mut seen = HashSet::new();
+        for item in &export.items {
+            self.content_id(file, &item.content_id, &mut seen);
+            self.content_type(file, &item.content_id, &item.content_type);
+        }
+    }
+
+    fn history(&mut self, export: &WatchHistoryExport) {
+        let file = ExportFile::History.file_name();
+        self.metadata(file, &export.metadata, export.items.len());
+        // Nothing on Crunchyroll was watched before it launched
+        let launch: DateTime<Utc> = Utc.with_ymd_and_hms(2006, 1, 1, 0, 0, 0).unwrap();
+        for item in &export.items {
+            // Repeated entries are normal in history: the latest one counts
+            if item.content_id.trim().is_empty() {
+                self.error(file, None, "entry with an empty content_id".to_string());
+                continue;
+            }
+            let id = Some(item.content_id.as_str());
+            if item.date_played > export.metadata.exported_at {
+                let message = format!("played at {}, after the export was made", item.date_played);
+                self.error(file, id, message);
+            } else if item.date_played < launch {
+                let message = format!("played at {}, before Crunchyroll existed", item.date_played);
+                self.error(file, id, message);
+            }
+            if !CONTENT_TYPES.contains(&item.parent_type.as_str()) {
+                let message = format!("parent_type '{}' is not a known type", item.parent_type);
+                self.warning(file, id, message);
+            }
+        }
+    }
+
+    fn crunchylists(&mut self, export: &CrunchylistsExport) {
+        let file = ExportFile::Crunchylists.file_name();
+        let count = export.lists.iter().map(|l| l.items.len()).sum();
+        self.metadata(file, &export.metadata, count);
+        let mut names = HashSet::new();
+        for list in &export.lists {
+            if list.name.trim().is_empty() {
+                self.error(file, None, "crunchylist with an empty name".to_string());
+            } else if !names.insert(list.name.as_str()) {
+                let message = format!("crunchylist '{}' appears more than once", list.name);
+                self.error(file, None, message);
+            }
+            let mut seen = HashSet::new();
+            for item in &list.items {
+                self.content_id(file, &item.content_id, &mut seen);
+            }
+        }
+    }
+
+    fn ratings(&mut self, export: &RatingsExport) {
+        let file = ExportFile::Ratings.file_name();
+        self.metadata(file, &export.metadata, export.items.len());
+        let mut seen = HashSet::new();
+        for item in &export.items {
+            self.content_id(file, &item.content_id, &mut seen);
+            self.content_type(file, &item.content_id, &item.content_type);
+            if !RATINGS.contains(&item.rating.as_str()) {
+                let message = format!(
+                    "rating '{}' is not one of {}",
+                    item.rating,
+                    RATINGS.join(", ")
+                );
+                self.error(file, Some(&item.content_id), message);
+            }
+        }
+    }
+}
+
+fn count(problems: &[Problem], severity: Severity) -> usize {
+    problems.iter().filter(|p| p.severity == severity).count()
+}
+
+fn print_table(dir: &Path, problems: &[Problem]) {
+    if problems.is_empty() {
+        println!("No problems found in {}.", dir.display());
+        return;
+    }
+    println!();
+    println!(
+        "  {:<8} {:<20} {:<14} Problem",
+        "Severity", "File", "Content ID"
+    );
+    println!("  {}", "─".repeat(80));
+    for p in problems {
+        println!(
+            "  {:<8} {:<20} {:<14} {}",
+            p.severity.as_str(),
+            p.file,
+            p.content_id.as_deref().unwrap_or("-"),
+            p.message
+        );
+    }
+    println!(
+        "\n  {} errors, {} warnings in {}\n",
+        count(problems, Severity::Error),
+        count(problems, Severity::Warning),
+        dir.display()
+    );
+}
+
+fn print_csv(problems: &[Problem]) -> Result<()> {
+    let mut out = csv::Writer::from_writer(std::io::stdout());
+    out.write_record(["severity", "file", "content_id", "message"])?;
+    for p in problems {
+        out.write_record([
+            p.severity.as_str(),
+            p.file,
+            p.content_id.as_deref().unwrap_or_default(),
+            &p.message,
+        ])?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Number of problems that should fail the command.
+pub fn errors(problems: &[Problem]) -> usize {
+    count(problems, Severity::Error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, file: &str, json: &str) {
+        std::fs::write(dir.join(file), json).unwrap();
+    }
+
+    #[test]
+    fn finds_problems_across_files() {
+        let dir = std::env::temp_dir().join(format!("validate-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write(
+            &dir,
+            "watchlist.json",
+            r#"{
+                "metadata": {"profile_name": "Main", "exported_at": "2026-01-01T00:00:00Z", "total_count": 3},
+                "items": [
+                    {"content_id": "S1", "title": "A", "slug": "a", "content_type": "series", "is_favourite": false, "fully_watched": false},
+                    {"content_id": "S1", "title": "A", "slug": "a", "content_type": "episode", "is_favourite": false, "fully_watched": false}
+                ]
+            }"#,
+        );
+        write(
+            &dir,
+            "watch_history.json",
+            r#"{
+                "metadata": {"profile_name": "Other", "exported_at": "2026-01-01T00:00:00Z", "total_count": 2},
+                "items": [
+                    {"content_id": "E1", "parent_id": "S1", "parent_type": "series", "title": "1", "series_title": "A",
+                     "date_played": "2026-03-01T00:00:00Z", "playhead": 10, "fully_watched": false},
+                    {"content_id": "E1", "parent_id": "S1", "parent_type": "series", "title": "1", "series_title": "A",
+                     "date_played": "1970-01-01T00:00:00Z", "playhead": 10, "fully_watched": false}
+                ]
+            }"#,
+        );
+        write(&dir, "ratings.json", r#"{"metadata": {"#);
+
+        let problems = check(&dir, Selection::default()).unwrap();
+        let summary: Vec<_> = problems
+            .iter()
+            .map(|p| {
+                format!(
+                    "{} {} {}",
+                    p.severity.as_str(),
+                    p.file,
+                    p.content_id.as_deref().unwrap_or("-")
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                "error watchlist.json -",
+                "error watchlist.json S1",
+                "error watchlist.json S1",
+                "error watch_history.json -",
+                "error watch_history.json E1",
+                "error watch_history.json E1",
+                "error ratings.json -",
+            ]
+        );
+        assert!(problems[0].message.contains("total_count is 3"));
+        assert!(problems[2].message.contains("'episode'"));
+        assert!(problems[3].message.contains("differs from 'Main'"));
+        assert!(problems[4].message.contains("after the export"));
+        assert!(problems[5].message.contains("before Crunchyroll"));
+        assert_eq!(errors(&problems), 7);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn csv_files_have_no_profile_to_compare() {
+        let dir = std::env::temp_dir().join(format!("validate-csv-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write(
+            &dir,
+            "ratings.csv",
+            "content_id,content_type,title,rating\nS1,series,A,FiveStars\n",
+        );
+        write(
+            &dir,
+            "watchlist.json",
+            r#"{
+                "metadata": {"profile_name": "Main", "exported_at": "2026-01-01T00:00:00Z", "total_count": 1},
+                "items": [
+                    {"content_id": "S1", "title": "A", "slug": "a", "content_type": "series", "is_favourite": false, "fully_watched": false}
+                ]
+            }"#,
+        );
+        write(
+            &dir,
+            "watch_history.csv",
+            "content_id,parent_id,parent_type,title,series_title,date_played,playhead,fully_watched,partial\n",
+        );
+
+        let problems = check(&dir, Selection::default()).unwrap();
+        assert!(problems.is_empty(), "{:?}", problems);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,286 @@
+use crate::backend::Backend;
+use crate::crypt;
+use crate::import::{self, RatingsPolicy, TargetState};
+use crate::models::{
+    CrunchylistsExport, RatingsExport, WatchHistoryExport, WatchHistoryItem, WatchlistExport,
+};
+use crate::selection::{Exports, Selection};
+use crate::ui::{self, DataType};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const REPORT_FILE: &str = "verification_report.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Not on the target at all
+    Missing,
+    /// On the target, but not in the exported state
+    Mismatched,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Problem {
+    pub data_type: DataType,
+    pub content_id: String,
+    pub title: String,
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Report {
+    pub verified_at: DateTime<Utc>,
+    pub profile_id: String,
+    /// Number of exported items checked per data type
+    pub checked: HashMap<DataType, usize>,
+    pub problems: Vec<Problem>,
+}
+
+/// Re-fetch the target, compare the selected data types of the export in `input_dir`
+/// against it, and write `verification_report.json` there.
+pub async fn run<B: Backend>(
+    crunchy: &B,
+    input_dir: &Path,
+    policy: RatingsPolicy,
+    selection: Selection,
+) -> Result<Report> {
+    let exports = Exports::read(input_dir, selection)?;
+
+    println!("Fetching target account state for verification...");
+    let target =
+        import::fetch_target_state(crunchy, exports.selection, &exports.ratings.items).await?;
+
+    let (mut checked, problems) = check(
+        &exports.watchlist,
+        &exports.history,
+        &exports.crunchylists,
+        &exports.ratings,
+        &target,
+        policy,
+    );
+    checked.retain(|&data_type, _| exports.selection.includes(data_type));
+    let report = Report {
+        verified_at: Utc::now(),
+        profile_id: crunchy.profile_id().await,
+        checked,
+        problems,
+    };
+
+    let path = input_dir.join(REPORT_FILE);
+    let json = crypt::seal_if_encrypted(serde_json::to_string_pretty(&report)?)?;
+    std::fs::write(&path, json).with_context(|| format!("Writing {}", path.display()))?;
+
+    print_report(&report);
+    println!("Report written to {}", path.display());
+    Ok(report)
+}
+
+/// Compare every exported item that an import would write against the target.
+pub fn check(
+    watchlist: &WatchlistExport,
+    history: &WatchHistoryExport,
+    crunchylists: &CrunchylistsExport,
+    ratings: &RatingsExport,
+    target: &TargetState,
+    policy: RatingsPolicy,
+) -> (HashMap<DataType, usize>, Vec<Problem>) {
+    let mut checked = HashMap::new();
+    let mut problems = Vec::new();
+    let missing = |data_type, content_id: &str, title: &str| Problem {
+        data_type,
+        content_id: content_id.to_string(),
+        title: title.to_string(),
+        status: Status::Missing,
+        expected: None,
+        actual: None,
+    };
+
+    checked.insert(DataType::Watchlist, watchlist.items.len());
+    for item in &watchlist.items {
+        if !target.watchlist_ids.contains(&item.content_id) {
+            problems.push(missing(DataType::Watchlist, &item.content_id, &item.title));
+        }
+    }
+
+    let favourites: Vec<_> = watchlist.items.iter().filter(|i| i.is_favourite).collect();
+    checked.insert(DataType::Favourites, favourites.len());
+    for item in favourites {
+        if !target.watchlist_favourites.contains(&item.content_id) {
+            problems.push(missing(DataType::Favourites, &item.content_id, &item.title));
+        }
+    }
+
+    checked.insert(
+        DataType::Crunchylists,
+        crunchylists.lists.iter().map(|l| l.items.len()).sum(),
+    );
+    for list in &crunchylists.lists {
+        let on_target = target.crunchylists.get(&list.name);
+        for item in &list.items {
+            if !on_target.is_some_and(|ids| ids.contains(&item.content_id)) {
+                let title = format!("{} -> {}", list.name, item.title);
+                problems.push(missing(DataType::Crunchylists, &item.content_id, &title));
+            }
+        }
+    }
+
+    checked.insert(DataType::Ratings, ratings.items.len());
+    for item in &ratings.items {
+        let actual = target.ratings.get(&item.content_id);
+        // A rating the policy chose to keep on the target isn't a failure
+        if import::should_write_rating(policy, &item.rating, actual.map(String::as_str)) {
+            problems.push(Problem {
+                status: if actual.is_some() {
+                    Status::Mismatched
+                } else {
+                    Status::Missing
+                },
+                expected: Some(item.rating.clone()),
+                actual: actual.cloned(),
+                ..missing(DataType::Ratings, &item.content_id, &item.title)
+            });
+        }
+    }
+
+    // Latest entry per content_id; entries without progress are never written
+    let latest: HashMap<&str, &WatchHistoryItem> = history
+        .items
+        .iter()
+        .filter(|i| i.fully_watched || i.playhead > 0)
+        .map(|i| (i.content_id.as_str(), i))
+        .collect();
+    checked.insert(DataType::History, latest.len());
+    let mut history_problems: Vec<Problem> = latest
+        .values()
+        .filter_map(|item| {
+            let title = format!("{} - {}", item.series_title, item.title);
+            match target.history.get(&item.content_id) {
+                None => Some(missing(DataType::History, &item.content_id, &title)),
+                Some(actual) if actual.is_behind(item) => Some(Problem {
+                    status: Status::Mismatched,
+                    expected: Some(describe_progress(item.playhead, item.fully_watched)),
+                    actual: Some(describe_progress(actual.playhead, actual.fully_watched)),
+                    ..missing(DataType::History, &item.content_id, &title)
+                }),
+                Some(_) => None,
+            }
+        })
+        .collect();
+    history_problems.sort_by(|a, b| a.title.cmp(&b.title));
+    problems.extend(history_problems);
+
+    (checked, problems)
+}
+
+fn describe_progress(playhead: u32, fully_watched: bool) -> String {
+    if fully_watched {
+        "watched".to_string()
+    } else {
+        format!("playhead {}s", playhead)
+    }
+}
+
+fn print_report(report: &Report) {
+    println!();
+    println!(
+        "  {:<14} {:>10} {:>10} {:>10}",
+        "Data Type", "Checked", "Missing", "Mismatched"
+    );
+    println!("  {}", "─".repeat(47));
+    // Data types that weren't checked are left out of the report
+    for data_type in ui::PHASES
+        .into_iter()
+        .filter(|d| report.checked.contains_key(d))
+    {
+        let count = |status| {
+            report
+                .problems
+                .iter()
+                .filter(|p| p.data_type == data_type && p.status == status)
+                .count()
+        };
+        println!(
+            "  {:<14} {:>10} {:>10} {:>10}",
+            data_type,
+            report.checked.get(&data_type).copied().unwrap_or(0),
+            count(Status::Missing),
+            count(Status::Mismatched)
+        );
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{empty_exports, history_item};
+    use crate::import::PlayheadState;
+    use crate::models::RatingItem;
+
+    #[test]
+    fn history_that_did_not_stick_is_reported() {
+        let (wl, mut hi, cl, rt) = empty_exports();
+        hi.items = vec![
+            history_item("E1", 0, true),
+            history_item("E2", 600, false),
+            history_item("E3", 0, false),
+            history_item("E4", 100, false),
+        ];
+        let mut target = TargetState::default();
+        target.history.insert(
+            "E2".to_string(),
+            PlayheadState {
+                playhead: 0,
+                fully_watched: false,
+            },
+        );
+        target.history.insert(
+            "E4".to_string(),
+            PlayheadState {
+                playhead: 100,
+                fully_watched: false,
+            },
+        );
+
+        let (checked, problems) = check(&wl, &hi, &cl, &rt, &target, RatingsPolicy::default());
+        // E3 has no progress, so an import never writes it
+        assert_eq!(checked[&DataType::History], 3);
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].content_id, "E1");
+        assert_eq!(problems[0].status, Status::Missing);
+        assert_eq!(problems[1].content_id, "E2");
+        assert_eq!(problems[1].status, Status::Mismatched);
+        assert_eq!(problems[1].expected.as_deref(), Some("playhead 600s"));
+        assert_eq!(problems[1].actual.as_deref(), Some("playhead 0s"));
+    }
+
+    #[test]
+    fn ratings_kept_by_policy_are_not_problems() {
+        let (wl, hi, cl, mut rt) = empty_exports();
+        rt.items = vec![RatingItem {
+            content_id: "S1".to_string(),
+            content_type: "series".to_string(),
+            title: "Show".to_string(),
+            rating: "FiveStars".to_string(),
+        }];
+        let mut target = TargetState::default();
+        target
+            .ratings
+            .insert("S1".to_string(), "TwoStars".to_string());
+
+        let (_, problems) = check(&wl, &hi, &cl, &rt, &target, RatingsPolicy::TargetWins);
+        assert!(problems.is_empty());
+
+        let (_, problems) = check(&wl, &hi, &cl, &rt, &target, RatingsPolicy::SourceWins);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].status, Status::Mismatched);
+    }
+}
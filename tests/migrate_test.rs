@@ -0,0 +1,210 @@
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+
+const BIN: &str = env!("CARGO_BIN_EXE_crunchyroll-migrate");
+
+/// The hidden `mock-server` subcommand, killed when dropped.
+struct MockServer {
+    child: Child,
+    url: String,
+}
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("mock_crunchyroll.json")
+}
+
+impl MockServer {
+    fn start() -> Self {
+        Self::start_with(&fixtures())
+    }
+
+    fn start_with(fixtures: &std::path::Path) -> Self {
+        let mut child = Command::new(BIN)
+            .arg("mock-server")
+            .arg("--fixtures")
+            .arg(fixtures)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to start mock server");
+
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let url = line
+            .split_whitespace()
+            .last()
+            .expect("mock server did not print its URL")
+            .to_string();
+        Self { child, url }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn migrate_against_mock_server() {
+    let server = MockServer::start();
+    let data_dir = std::env::temp_dir().join(format!("migrate-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_dir);
+
+    let output = Command::new(BIN)
+        .args(["migrate", "--yes", "--api-base", &server.url, "--insecure"])
+        .args(["--source-email", "source@example.com"])
+        .args(["--source-password", "source-password"])
+        .args(["--source-profile", "Main"])
+        .args(["--target-email", "target@example.com"])
+        .args(["--target-password", "target-password"])
+        .args(["--target-profile", "Main"])
+        .arg("--data-dir")
+        .arg(&data_dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "migrate failed:\n{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let report: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(data_dir.join("verification_report.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(report["profile_id"], "target-main");
+    assert_eq!(report["problems"], serde_json::json!([]));
+    assert_eq!(report["checked"]["watchlist"], 3);
+    assert_eq!(report["checked"]["history"], 4);
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[test]
+fn migrate_fails_when_a_write_does_not_stick() {
+    let data_dir = std::env::temp_dir().join(format!("migrate-lost-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_dir);
+    std::fs::create_dir_all(&data_dir).unwrap();
+    // The first watchlist add is acknowledged but never applied
+    let mut fixtures: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(fixtures()).unwrap()).unwrap();
+    fixtures["faults"] = serde_json::json!([
+        { "method": "POST", "path": "/watchlist", "status": 200, "times": 1 }
+    ]);
+    let path = data_dir.join("fixtures.json");
+    std::fs::write(&path, fixtures.to_string()).unwrap();
+    let server = MockServer::start_with(&path);
+
+    let output = Command::new(BIN)
+        .args(["migrate", "--yes", "--api-base", &server.url, "--insecure"])
+        .args(["--source-email", "source@example.com"])
+        .args(["--source-password", "source-password"])
+        .args(["--source-profile", "Main"])
+        .args(["--target-email", "target@example.com"])
+        .args(["--target-password", "target-password"])
+        .args(["--target-profile", "Main"])
+        .arg("--data-dir")
+        .arg(data_dir.join("export"))
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "migrate succeeded:\n{}", stderr);
+    assert!(stderr.contains("missing or mismatched"), "{}", stderr);
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+fn export(dir: &std::path::Path, api: &[&str]) {
+    let output = Command::new(BIN)
+        .arg("export")
+        .args(api)
+        .args(["--email", "source@example.com"])
+        .args(["--password", "source-password"])
+        .args(["--profile", "Main"])
+        .arg("--output-dir")
+        .arg(dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "export failed:\n{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn items(dir: &std::path::Path, file: &str) -> serde_json::Value {
+    let export: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join(file)).unwrap()).unwrap();
+    export["items"].clone()
+}
+
+#[test]
+fn replayed_export_matches_recorded_one() {
+    let root = std::env::temp_dir().join(format!("replay-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let (cassette, recorded, replayed) = (root.join("cassette"), root.join("a"), root.join("b"));
+
+    let server = MockServer::start();
+    let record = cassette.to_str().unwrap();
+    export(
+        &recorded,
+        &["--api-base", &server.url, "--insecure", "--record", record],
+    );
+    drop(server);
+
+    let content = std::fs::read_to_string(cassette.join("cassette.jsonl")).unwrap();
+    assert!(!content.contains("source-password"));
+
+    export(&replayed, &["--replay", record]);
+    for file in ["watchlist.json", "watch_history.json", "ratings.json"] {
+        assert_eq!(items(&recorded, file), items(&replayed, file), "{}", file);
+    }
+
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn json_diff_is_the_only_thing_on_stdout() {
+    let dir = std::env::temp_dir().join(format!("diff-json-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let server = MockServer::start();
+    export(&dir, &["--api-base", &server.url, "--insecure"]);
+
+    let output = Command::new(BIN)
+        .args([
+            "diff",
+            "--format",
+            "json",
+            "--api-base",
+            &server.url,
+            "--insecure",
+        ])
+        .args(["--email", "target@example.com"])
+        .args(["--password", "target-password"])
+        .args(["--profile", "Main"])
+        .args(["--target-cache-ttl", "0"])
+        .arg("--input-dir")
+        .arg(&dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "diff failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let diff: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_else(|e| {
+        panic!("{}:\n{}", e, String::from_utf8_lossy(&output.stdout));
+    });
+    assert!(diff.is_object());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -1,12 +1,16 @@
 use chrono::{TimeZone, Utc};
 use crunchyroll_migrate::models::*;
+use crunchyroll_migrate::{crypt, csv_format};
+use serde_json::Value;
 
 fn sample_watchlist() -> WatchlistExport {
     WatchlistExport {
         metadata: ExportMetadata {
+            schema_version: SCHEMA_VERSION,
             profile_name: "Sean".to_string(),
             exported_at: Utc.with_ymd_and_hms(2026, 2, 18, 12, 0, 0).unwrap(),
             total_count: 2,
+            partial: false,
         },
         items: vec![
             WatchlistItem {
@@ -32,9 +36,11 @@ fn sample_watchlist() -> WatchlistExport {
 fn sample_history() -> WatchHistoryExport {
     WatchHistoryExport {
         metadata: ExportMetadata {
+            schema_version: SCHEMA_VERSION,
             profile_name: "Sean".to_string(),
             exported_at: Utc.with_ymd_and_hms(2026, 2, 18, 12, 0, 0).unwrap(),
             total_count: 2,
+            partial: false,
         },
         items: vec![
             WatchHistoryItem {
@@ -66,9 +72,11 @@ fn sample_history() -> WatchHistoryExport {
 fn sample_crunchylists() -> CrunchylistsExport {
     CrunchylistsExport {
         metadata: ExportMetadata {
+            schema_version: SCHEMA_VERSION,
             profile_name: "Sean".to_string(),
             exported_at: Utc.with_ymd_and_hms(2026, 2, 18, 12, 0, 0).unwrap(),
             total_count: 1,
+            partial: false,
         },
         lists: vec![CrunchylistData {
             name: "Favourites".to_string(),
@@ -83,9 +91,11 @@ fn sample_crunchylists() -> CrunchylistsExport {
 fn sample_ratings() -> RatingsExport {
     RatingsExport {
         metadata: ExportMetadata {
+            schema_version: SCHEMA_VERSION,
             profile_name: "Sean".to_string(),
             exported_at: Utc.with_ymd_and_hms(2026, 2, 18, 12, 0, 0).unwrap(),
             total_count: 1,
+            partial: false,
         },
         items: vec![RatingItem {
             content_id: "G4PH0WXYZ".to_string(),
@@ -143,6 +153,17 @@ fn history_partial_defaults_to_false() {
     assert!(!item.partial);
 }
 
+#[test]
+fn metadata_partial_defaults_to_false() {
+    let json = r#"{
+        "profile_name": "Sean",
+        "exported_at": "2026-02-18T12:00:00Z",
+        "total_count": 3
+    }"#;
+    let metadata: ExportMetadata = serde_json::from_str(json).unwrap();
+    assert!(!metadata.partial);
+}
+
 #[test]
 fn crunchylists_round_trip() {
     let original = sample_crunchylists();
@@ -165,3 +186,153 @@ fn ratings_round_trip() {
     assert_eq!(parsed.items[0].rating, "FiveStars");
     assert_eq!(parsed.items[0].content_type, "series");
 }
+
+#[test]
+fn unversioned_export_is_upgraded() {
+    let mut doc = serde_json::json!({
+        "metadata": {
+            "profile_name": "Sean",
+            "exported_at": "2025-06-01T00:00:00Z",
+            "total_count": 1
+        },
+        "items": [{
+            "content_id": "ABC",
+            "parent_id": "DEF",
+            "parent_type": "series",
+            "title": "Test",
+            "series_title": "Test Series",
+            "date_played": "2025-05-15T20:30:00Z",
+            "playhead": 100,
+            "fully_watched": false
+        }]
+    });
+    assert_eq!(upgrade(&mut doc).unwrap(), 0);
+    assert_eq!(doc["metadata"]["schema_version"], SCHEMA_VERSION);
+    assert_eq!(doc["items"][0]["partial"], false);
+
+    let parsed: WatchHistoryExport = serde_json::from_value(doc.clone()).unwrap();
+    assert_eq!(parsed.metadata.schema_version, SCHEMA_VERSION);
+    // Already current: left as is
+    assert_eq!(upgrade(&mut doc).unwrap(), SCHEMA_VERSION);
+}
+
+/// A shape change: items' `title` becomes `name`
+fn rename_title(doc: &mut Value) {
+    for item in doc["items"].as_array_mut().unwrap() {
+        let title = item.as_object_mut().unwrap().remove("title").unwrap();
+        item["name"] = title;
+    }
+}
+
+/// Builds on the rename, so it only works if that ran first
+fn nest_name(doc: &mut Value) {
+    for item in doc["items"].as_array_mut().unwrap() {
+        let name = item.as_object_mut().unwrap().remove("name").unwrap();
+        item["names"] = serde_json::json!({ "en": name });
+    }
+}
+
+#[test]
+fn upgrade_steps_run_in_order_from_the_file_version() {
+    let steps: [fn(&mut Value); 2] = [rename_title, nest_name];
+    let mut v0 = serde_json::json!({
+        "metadata": { "profile_name": "Sean" },
+        "items": [{ "title": "Show" }]
+    });
+    assert_eq!(upgrade_with(&mut v0, &steps).unwrap(), 0);
+    assert_eq!(
+        v0["items"][0],
+        serde_json::json!({ "names": { "en": "Show" } })
+    );
+    assert_eq!(v0["metadata"]["schema_version"], 2);
+
+    // Already past the rename: only the second step runs
+    let mut v1 = serde_json::json!({
+        "metadata": { "profile_name": "Sean", "schema_version": 1 },
+        "items": [{ "name": "Show" }]
+    });
+    assert_eq!(upgrade_with(&mut v1, &steps).unwrap(), 1);
+    assert_eq!(v1, v0);
+
+    assert_eq!(upgrade_with(&mut v1, &steps).unwrap(), 2);
+    assert_eq!(v1, v0);
+}
+
+#[test]
+fn newer_schema_is_rejected() {
+    let mut doc = serde_json::to_value(sample_ratings()).unwrap();
+    doc["metadata"]["schema_version"] = (SCHEMA_VERSION + 1).into();
+    let err = upgrade(&mut doc).unwrap_err();
+    assert!(err.to_string().contains("only reads up to"), "{}", err);
+}
+
+#[test]
+fn encrypted_export_is_decrypted_on_read() {
+    let dir = std::env::temp_dir().join(format!("crypt-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let json = serde_json::to_string(&sample_watchlist()).unwrap();
+    let key = crypt::Key::new("correct horse").unwrap();
+    std::fs::write(
+        dir.join("watchlist.json"),
+        key.seal(json.as_bytes()).unwrap(),
+    )
+    .unwrap();
+    let sealed = std::fs::read_to_string(dir.join("watchlist.json")).unwrap();
+    assert!(!sealed.contains("One Piece"));
+
+    crypt::set_passphrase("wrong".to_string());
+    assert_eq!(crypt::seal_if_encrypted("{}".to_string()).unwrap(), "{}");
+    let err = read_export::<WatchlistExport>(&dir, "watchlist.json").unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Wrong passphrase"),
+        "{:#}",
+        err
+    );
+
+    crypt::set_passphrase("correct horse".to_string());
+    let parsed: WatchlistExport = read_export(&dir, "watchlist.json").unwrap();
+    assert_eq!(parsed.items[1].title, "A Silent Voice");
+
+    // Files written next to the export from now on are sealed with the same passphrase
+    let journal_line = r#"{"data_type":"watchlist","key":"S1","outcome":"done"}"#;
+    let sealed = crypt::seal_if_encrypted(journal_line.to_string()).unwrap();
+    assert!(!sealed.contains("data_type") && !sealed.contains('\n'));
+    assert_eq!(crypt::open_if_sealed(&sealed).unwrap(), journal_line);
+    assert_eq!(crypt::open_if_sealed(journal_line).unwrap(), journal_line);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn csv_form_reads_back_like_json() {
+    let dir = std::env::temp_dir().join(format!("csv-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let history = sample_history();
+    let csv = csv_format::to_csv(&history).unwrap();
+    std::fs::write(dir.join("watch_history.csv"), csv).unwrap();
+    let mut lists = sample_crunchylists();
+    lists.lists.push(CrunchylistData {
+        name: "Empty, for now".to_string(),
+        items: Vec::new(),
+    });
+    let csv = csv_format::to_csv(&lists).unwrap();
+    std::fs::write(dir.join("crunchylists.csv"), csv).unwrap();
+
+    let parsed: WatchHistoryExport = read_export(&dir, "watch_history.json").unwrap();
+    assert_eq!(parsed.metadata.total_count, 2);
+    assert_eq!(parsed.items[0].date_played, history.items[0].date_played);
+    assert_eq!(parsed.items[0].playhead, 1420);
+    assert!(parsed.items[0].fully_watched);
+
+    let parsed: CrunchylistsExport = read_export(&dir, "crunchylists.json").unwrap();
+    assert_eq!(parsed.lists.len(), 2);
+    assert_eq!(parsed.lists[0].items[0].content_id, "G4PH0WXYZ");
+    assert!(parsed.lists[1].items.is_empty());
+
+    // The JSON form next to it makes the choice ambiguous
+    std::fs::write(dir.join("crunchylists.json"), "{}").unwrap();
+    let err = read_export::<CrunchylistsExport>(&dir, "crunchylists.json").unwrap_err();
+    assert!(err.to_string().contains("Both"), "{}", err);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}